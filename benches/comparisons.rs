@@ -0,0 +1,45 @@
+use std::time::Instant;
+
+use monkey::compiler::Compiler;
+use monkey::lexer::Lexer;
+use monkey::parser::{ast::Node, Parser};
+use monkey::vm::VM;
+
+const ITERATIONS: u32 = 2000;
+
+// recursion stands in for a loop; the depth stays within the vm stack
+const PROGRAM: &str = r#"
+let count = fn(n, hits) {
+    if (n == 0) {
+        hits
+    } else {
+        let hit = (n > 60) == (n != 125) == !(n < 190);
+        count(n - 1, if (hit) { hits + 1 } else { hits })
+    }
+};
+count(250, 0);
+"#;
+
+fn main() {
+    let program = Parser::new(Lexer::new(PROGRAM)).parse_program().unwrap();
+    let mut compiler = Compiler::new();
+    compiler.compile(Node::Program(program)).unwrap();
+    let bytecode = compiler.bytecode();
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        let mut machine = VM::new(monkey::compiler::Bytecode {
+            instructions: bytecode.instructions.clone(),
+            constants: bytecode.constants.clone(),
+        });
+        machine.run().unwrap();
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "comparisons: {} runs in {:?} ({:?} per run)",
+        ITERATIONS,
+        elapsed,
+        elapsed / ITERATIONS
+    );
+}
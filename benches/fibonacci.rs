@@ -0,0 +1,42 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Instant;
+
+use monkey::evaluator::evaluate;
+use monkey::lexer::Lexer;
+use monkey::object::environment::Environment;
+use monkey::object::Object;
+use monkey::parser::{ast::Node, Parser};
+
+const ITERATIONS: u32 = 5;
+
+// every call binds a fresh environment, which is what this measures
+const PROGRAM: &str = r#"
+let fibonacci = fn(x) {
+    if (x < 2) {
+        x
+    } else {
+        fibonacci(x - 1) + fibonacci(x - 2)
+    }
+};
+fibonacci(25);
+"#;
+
+fn main() {
+    let program = Parser::new(Lexer::new(PROGRAM)).parse_program().unwrap();
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        let env = Rc::new(RefCell::new(Environment::new()));
+        let result = evaluate(Node::Program(program.clone()), env).unwrap();
+        assert_eq!(*result, Object::Integer(75025));
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "fibonacci(25), direct: {} runs in {:?} ({:?} per run)",
+        ITERATIONS,
+        elapsed,
+        elapsed / ITERATIONS
+    );
+}
@@ -0,0 +1,51 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Instant;
+
+use monkey::evaluator::{define_macros, expand_macros};
+use monkey::lexer::Lexer;
+use monkey::object::environment::Environment;
+use monkey::parser::{ast::Node, Parser};
+
+const ITERATIONS: u32 = 20;
+const CALLS: usize = 500;
+
+const MACRO: &str = r#"
+let unless = macro(condition, consequence, alternative) {
+    quote(
+        if (!(unquote(condition))) {
+            unquote(consequence);
+        } else {
+            unquote(alternative);
+        }
+    );
+};
+"#;
+
+fn main() {
+    let mut source = MACRO.to_string();
+    for i in 0..CALLS {
+        source.push_str(&format!(
+            "let f{} = fn(x) {{ unless(x > {}, [x, x + 1, x * 2, {{\"k\": x}}], fn() {{ x - 1 }}) }};\n",
+            i, i
+        ));
+    }
+    let program = Parser::new(Lexer::new(&source)).parse_program().unwrap();
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        let macro_env = Rc::new(RefCell::new(Environment::new()));
+        let mut program = program.clone();
+        define_macros(&mut program, Rc::clone(&macro_env));
+        expand_macros(Node::Program(program), macro_env).unwrap();
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "macros: {} expansions of {} calls in {:?} ({:?} per run)",
+        ITERATIONS,
+        CALLS,
+        elapsed,
+        elapsed / ITERATIONS
+    );
+}
@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use monkey::lexer::Lexer;
+use monkey::parser::Parser;
+
+fuzz_target!(|data: &[u8]| {
+    let lexer = match Lexer::from_reader(data) {
+        Ok(lexer) => lexer,
+        Err(_) => return,
+    };
+    let mut parser = Parser::new(lexer);
+    let _ = parser.parse_program();
+});
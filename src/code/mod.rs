@@ -1,9 +1,11 @@
 pub mod error;
 use std::ops::{Index, IndexMut};
 
+use error::CodeError;
+
 use std::fmt::{Debug, Display};
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(u8)]
 pub enum Opcode {
     Constant,
@@ -36,6 +38,13 @@ pub enum Opcode {
     Closure,
     GetFree,
     CurrentClosure,
+    ConstantWide,
+    LessThan,
+    LessEqual,
+    GreaterEqual,
+    Mod,
+    JumpNotNull,
+    SetIndex,
 }
 impl From<u8> for Opcode {
     fn from(op: u8) -> Opcode {
@@ -70,14 +79,21 @@ impl From<u8> for Opcode {
             27 => Opcode::Closure,
             28 => Opcode::GetFree,
             29 => Opcode::CurrentClosure,
+            30 => Opcode::ConstantWide,
+            31 => Opcode::LessThan,
+            32 => Opcode::LessEqual,
+            33 => Opcode::GreaterEqual,
+            34 => Opcode::Mod,
+            35 => Opcode::JumpNotNull,
+            36 => Opcode::SetIndex,
             _ => panic!("unknown opcode"),
         }
     }
 }
 
-impl Into<Instructions> for Vec<u8> {
-    fn into(self) -> Instructions {
-        Instructions::new(self)
+impl From<Vec<u8>> for Instructions {
+    fn from(val: Vec<u8>) -> Self {
+        Instructions::new(val)
     }
 }
 
@@ -118,7 +134,11 @@ impl Instructions {
         self.0.len()
     }
 
-    pub fn iter(&self) -> std::slice::Iter<u8> {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, u8> {
         self.0.iter()
     }
 
@@ -141,10 +161,99 @@ impl Instructions {
     pub fn slice_from(&self, start: usize, end: usize) -> Vec<u8> {
         self.0[start..end].to_vec()
     }
+
+    /// Copies these instructions with every jump target moved `offset` bytes
+    /// forward, for placing them `offset` bytes into a larger block. Jumps hold
+    /// absolute positions, so a block can't be moved without this.
+    pub fn relocate(&self, offset: usize) -> Result<Instructions, CodeError> {
+        let mut relocated = self.clone();
+        let mut i = 0;
+        while i < self.0.len() {
+            let def = lookup(self.0[i]).ok_or_else(|| {
+                CodeError::new(format!("undefined opcode {} at {}", self.0[i], i))
+            })?;
+            let (operands, n) = read_operands(&def, &self.0[i + 1..]).map_err(|_| {
+                CodeError::new(format!("{} at {} is missing its operands", def.name, i))
+            })?;
+            let op = Opcode::from(self.0[i]);
+            if matches!(
+                op,
+                Opcode::Jump | Opcode::JumpNotTruthy | Opcode::JumpNotNull
+            ) {
+                let target = operands[0] + offset;
+                if target > u16::MAX as usize {
+                    return Err(CodeError::new(format!(
+                        "relocated jump target {} does not fit in its operand",
+                        target
+                    )));
+                }
+                relocated.0[i..i + n + 1].copy_from_slice(&make(op, vec![target]));
+            }
+            i += n + 1;
+        }
+        Ok(relocated)
+    }
+
+    /// Joins blocks end to end, relocating each so its jumps still land inside it.
+    pub fn concat(blocks: &[Instructions]) -> Result<Instructions, CodeError> {
+        let mut joined = Instructions::new(vec![]);
+        for block in blocks {
+            joined.extend(block.relocate(joined.len())?);
+        }
+        Ok(joined)
+    }
 }
 
 impl Opcode {
-    pub fn name(&self) -> &str {
+    /// The opcode encoded as `byte`, or `None` if no opcode uses it.
+    pub fn from_byte(byte: u8) -> Option<Opcode> {
+        Opcode::all().get(byte as usize).copied()
+    }
+
+    /// Every opcode, in encoding order.
+    pub fn all() -> &'static [Opcode] {
+        &[
+            Opcode::Constant,
+            Opcode::Add,
+            Opcode::Pop,
+            Opcode::Sub,
+            Opcode::Mul,
+            Opcode::Div,
+            Opcode::True,
+            Opcode::False,
+            Opcode::Equal,
+            Opcode::NotEqual,
+            Opcode::GreaterThan,
+            Opcode::Minus,
+            Opcode::Bang,
+            Opcode::JumpNotTruthy,
+            Opcode::Jump,
+            Opcode::Null,
+            Opcode::GetGlobal,
+            Opcode::SetGlobal,
+            Opcode::Array,
+            Opcode::Hash,
+            Opcode::Index,
+            Opcode::Call,
+            Opcode::ReturnValue,
+            Opcode::Return,
+            Opcode::GetLocal,
+            Opcode::SetLocal,
+            Opcode::GetBuiltin,
+            Opcode::Closure,
+            Opcode::GetFree,
+            Opcode::CurrentClosure,
+            Opcode::ConstantWide,
+            Opcode::LessThan,
+            Opcode::LessEqual,
+            Opcode::GreaterEqual,
+            Opcode::Mod,
+            Opcode::JumpNotNull,
+            Opcode::SetIndex,
+        ]
+    }
+
+    pub fn name(&self) -> &'static str {
         match self {
             Opcode::Constant => "OpConstant",
             Opcode::Add => "OpAdd",
@@ -176,6 +285,13 @@ impl Opcode {
             Opcode::Closure => "OpClosure",
             Opcode::GetFree => "OpGetFree",
             Opcode::CurrentClosure => "OpCurrentClosure",
+            Opcode::ConstantWide => "OpConstantWide",
+            Opcode::LessThan => "OpLessThan",
+            Opcode::LessEqual => "OpLessEqual",
+            Opcode::GreaterEqual => "OpGreaterEqual",
+            Opcode::Mod => "OpMod",
+            Opcode::JumpNotNull => "OpJumpNotNull",
+            Opcode::SetIndex => "OpSetIndex",
         }
     }
 
@@ -211,6 +327,13 @@ impl Opcode {
             Opcode::Closure => vec![2, 1],
             Opcode::GetFree => vec![1],
             Opcode::CurrentClosure => vec![],
+            Opcode::ConstantWide => vec![4],
+            Opcode::LessThan => vec![],
+            Opcode::LessEqual => vec![],
+            Opcode::GreaterEqual => vec![],
+            Opcode::Mod => vec![],
+            Opcode::JumpNotNull => vec![2],
+            Opcode::SetIndex => vec![],
         }
     }
 }
@@ -362,11 +485,51 @@ pub fn lookup(op: u8) -> Option<Definition> {
             operand_widths: vec![1],
         }),
 
+        29 => Some(Definition {
+            name: "OpCurrentClosure",
+            operand_widths: vec![],
+        }),
+
+        30 => Some(Definition {
+            name: "OpConstantWide",
+            operand_widths: vec![4],
+        }),
+
+        31 => Some(Definition {
+            name: "OpLessThan",
+            operand_widths: vec![],
+        }),
+
+        32 => Some(Definition {
+            name: "OpLessEqual",
+            operand_widths: vec![],
+        }),
+
+        33 => Some(Definition {
+            name: "OpGreaterEqual",
+            operand_widths: vec![],
+        }),
+
+        34 => Some(Definition {
+            name: "OpMod",
+            operand_widths: vec![],
+        }),
+
+        35 => Some(Definition {
+            name: "OpJumpNotNull",
+            operand_widths: vec![2],
+        }),
+
+        36 => Some(Definition {
+            name: "OpSetIndex",
+            operand_widths: vec![],
+        }),
+
         _ => None,
     }
 }
 
-pub fn format_instruction(def: &Definition, operands: &Vec<usize>) -> String {
+pub fn format_instruction(def: &Definition, operands: &[usize]) -> String {
     let operand_count = def.operand_widths.len();
     if operands.len() != operand_count {
         return format!(
@@ -377,10 +540,10 @@ pub fn format_instruction(def: &Definition, operands: &Vec<usize>) -> String {
         .to_string();
     }
     match operand_count {
-        0 => return def.name.to_string(),
-        1 => return format!("{} {}", def.name, operands[0]).to_string(),
-        2 => return format!("{} {} {}", def.name, operands[0], operands[1]).to_string(),
-        _ => return format!("ERROR: unhandled operand_count for {}\n", def.name),
+        0 => def.name.to_string(),
+        1 => format!("{} {}", def.name, operands[0]).to_string(),
+        2 => format!("{} {} {}", def.name, operands[0], operands[1]).to_string(),
+        _ => format!("ERROR: unhandled operand_count for {}\n", def.name),
     }
 }
 
@@ -390,12 +553,19 @@ impl Display for Instructions {
         while i < self.0.len() {
             let definition = lookup(self.0[i]);
             if definition.is_none() {
-                write!(f, "ERROR: undefined opcode {}", self.0[i])?;
-                continue;
+                // the operand widths are unknown, so nothing after it can be decoded
+                writeln!(f, "{:04} ERROR: undefined opcode {}", i, self.0[i])?;
+                break;
             }
             let def = definition.unwrap();
-            let (operands, n) = read_operands(&def, &self.0[i + 1..]);
-            let _ = write!(f, "{:04} {}\n", i, format_instruction(&def, &operands));
+            let (operands, n) = match read_operands(&def, &self.0[i + 1..]) {
+                Ok(read) => read,
+                Err(e) => {
+                    writeln!(f, "{:04} ERROR: {}", i, e)?;
+                    break;
+                }
+            };
+            let _ = writeln!(f, "{:04} {}", i, format_instruction(&def, &operands));
             i += n + 1;
         }
         Ok(())
@@ -405,16 +575,23 @@ impl Display for Instructions {
 impl Debug for Instructions {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut i = 0;
-        write!(f, "\n")?;
+        writeln!(f)?;
         while i < self.0.len() {
             let definition = lookup(self.0[i]);
             if definition.is_none() {
-                write!(f, "ERROR: undefined opcode {}", self.0[i])?;
-                continue;
+                // the operand widths are unknown, so nothing after it can be decoded
+                writeln!(f, "{:04} ERROR: undefined opcode {}", i, self.0[i])?;
+                break;
             }
             let def = definition.unwrap();
-            let (operands, n) = read_operands(&def, &self.0[i + 1..]);
-            let _ = write!(f, "{:04} {}\n", i, format_instruction(&def, &operands));
+            let (operands, n) = match read_operands(&def, &self.0[i + 1..]) {
+                Ok(read) => read,
+                Err(e) => {
+                    writeln!(f, "{:04} ERROR: {}", i, e)?;
+                    break;
+                }
+            };
+            let _ = writeln!(f, "{:04} {}", i, format_instruction(&def, &operands));
             i += n + 1;
         }
         Ok(())
@@ -429,6 +606,13 @@ pub fn make(op: Opcode, operands: Vec<usize>) -> Vec<u8> {
     let mut offset = 1;
     for (i, &o) in operands.iter().enumerate() {
         let width = op.operand_widths()[i];
+        assert!(
+            (o as u64) >> (width * 8) == 0,
+            "operand {} of {} does not fit in {} bytes",
+            o,
+            op.name(),
+            width
+        );
         match width {
             1 => instructions[offset] = o as u8,
             2 => {
@@ -436,6 +620,10 @@ pub fn make(op: Opcode, operands: Vec<usize>) -> Vec<u8> {
                 instructions[offset] = bytes[0];
                 instructions[offset + 1] = bytes[1];
             }
+            4 => {
+                let bytes = (o as u32).to_be_bytes();
+                instructions[offset..offset + 4].copy_from_slice(&bytes);
+            }
             _ => panic!("invalid operand width"),
         }
         offset += width;
@@ -443,29 +631,54 @@ pub fn make(op: Opcode, operands: Vec<usize>) -> Vec<u8> {
     instructions
 }
 
-pub fn read_operands(def: &Definition, instructions: &[u8]) -> (Vec<usize>, usize) {
+/// Decodes the operands following an opcode, returning them with the number of
+/// bytes they took up. Fails if `instructions` ends partway through them.
+pub fn read_operands(
+    def: &Definition,
+    instructions: &[u8],
+) -> Result<(Vec<usize>, usize), CodeError> {
     let mut operands: Vec<usize> = Vec::with_capacity(def.operand_widths.len());
     let mut offset = 0;
 
     for width in def.operand_widths.iter() {
+        if offset + width > instructions.len() {
+            return Err(CodeError::new(format!(
+                "{} is missing its operands",
+                def.name
+            )));
+        }
         match width {
             1 => operands.push(instructions[offset] as usize),
             2 => {
                 let bytes = instructions[offset..offset + 2].to_vec();
                 operands.push(u16::from_be_bytes([bytes[0], bytes[1]]) as usize);
             }
+            4 => {
+                let bytes = instructions[offset..offset + 4].to_vec();
+                operands
+                    .push(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize);
+            }
             _ => panic!("invalid operand width"),
         }
 
-        offset = offset + width
+        offset += width
     }
-    return (operands, offset);
+    Ok((operands, offset))
 }
 
 pub fn read_u16(instructions: &Instructions, start: usize) -> u16 {
     u16::from_be_bytes([instructions[start], instructions[start + 1]])
 }
 
+pub fn read_u32(instructions: &Instructions, start: usize) -> u32 {
+    u32::from_be_bytes([
+        instructions[start],
+        instructions[start + 1],
+        instructions[start + 2],
+        instructions[start + 3],
+    ])
+}
+
 pub fn read_u8(instructions: &Instructions, start: usize) -> u8 {
     instructions[start]
 }
@@ -497,12 +710,23 @@ mod test {
                 vec![65534, 255],
                 vec![Opcode::Closure as u8, 255, 254, 255],
             ),
+            (
+                Opcode::ConstantWide,
+                vec![65536],
+                vec![Opcode::ConstantWide as u8, 0, 1, 0, 0],
+            ),
         ];
         for (opcode, operands, expected) in tests {
             check(opcode, operands, expected);
         }
     }
 
+    #[test]
+    #[should_panic(expected = "operand 65536 of OpJump does not fit in 2 bytes")]
+    fn it_refuses_to_truncate_operands() {
+        make(Opcode::Jump, vec![65536]);
+    }
+
     #[test]
     fn it_reads_operands_correctly() {
         struct OperandTest {
@@ -526,12 +750,17 @@ mod test {
                 operands: vec![65535, 255],
                 bytes_read: 3,
             },
+            OperandTest {
+                opcode: Opcode::ConstantWide,
+                operands: vec![4294967295],
+                bytes_read: 4,
+            },
         ];
 
         for test in tests {
             let instruction = make(test.opcode, test.operands.clone());
             let definition = lookup(test.opcode as u8).unwrap();
-            let (operands_read, n) = read_operands(&definition, &instruction[1..]);
+            let (operands_read, n) = read_operands(&definition, &instruction[1..]).unwrap();
 
             if n != test.bytes_read {
                 panic!("n wrong");
@@ -552,6 +781,7 @@ mod test {
             make(Opcode::Constant, vec![2]),
             make(Opcode::Constant, vec![65535]),
             make(Opcode::Closure, vec![65535, 255]),
+            make(Opcode::ConstantWide, vec![65536]),
         ];
 
         let expected = r#"0000 OpAdd
@@ -559,6 +789,7 @@ mod test {
 0003 OpConstant 2
 0006 OpConstant 65535
 0009 OpClosure 65535 255
+0013 OpConstantWide 65536
 "#;
 
         let concattenated = instructions.into_iter().flatten().collect::<Instructions>();
@@ -574,4 +805,113 @@ mod test {
             );
         }
     }
+
+    #[test]
+    fn it_prints_current_closure_and_stops_at_undefined_opcodes() {
+        let current_closure: Instructions = make(Opcode::CurrentClosure, vec![]).into();
+        assert_eq!(current_closure.to_string(), "0000 OpCurrentClosure\n");
+
+        let mut bytes = make(Opcode::Pop, vec![]);
+        bytes.extend([255, 0, 0]);
+        let instructions = Instructions::from(bytes);
+        let expected = "0000 OpPop\n0001 ERROR: undefined opcode 255\n";
+        assert_eq!(instructions.to_string(), expected);
+        assert_eq!(format!("{:?}", instructions), format!("\n{}", expected));
+    }
+
+    #[test]
+    fn it_keeps_opcode_definitions_consistent() {
+        for (i, op) in Opcode::all().iter().enumerate() {
+            assert_eq!(*op as u8 as usize, i, "{} is out of order", op.name());
+            assert_eq!(Opcode::from(*op as u8), *op);
+            assert_eq!(Opcode::from_byte(*op as u8), Some(*op));
+
+            let definition = match lookup(*op as u8) {
+                Some(definition) => definition,
+                None => panic!("no definition for {}", op.name()),
+            };
+            assert_eq!(definition.name, op.name());
+            assert_eq!(definition.operand_widths, op.operand_widths());
+        }
+
+        assert!(lookup(Opcode::all().len() as u8).is_none());
+        assert!(Opcode::from_byte(Opcode::all().len() as u8).is_none());
+    }
+
+    #[test]
+    fn it_relocates_jump_targets() {
+        let block: Instructions = [
+            make(Opcode::True, vec![]),
+            make(Opcode::JumpNotTruthy, vec![7]),
+            make(Opcode::Jump, vec![8]),
+            make(Opcode::Null, vec![]),
+            make(Opcode::Constant, vec![3]),
+        ]
+        .concat()
+        .into();
+
+        let expected: Instructions = [
+            make(Opcode::True, vec![]),
+            make(Opcode::JumpNotTruthy, vec![107]),
+            make(Opcode::Jump, vec![108]),
+            make(Opcode::Null, vec![]),
+            // not a jump, so left alone
+            make(Opcode::Constant, vec![3]),
+        ]
+        .concat()
+        .into();
+        assert_eq!(block.relocate(100).unwrap(), expected);
+        assert_eq!(block.relocate(0).unwrap(), block);
+
+        let err = block.relocate(u16::MAX as usize).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "relocated jump target 65542 does not fit in its operand"
+        );
+        assert!(Instructions::new(vec![255]).relocate(1).is_err());
+    }
+
+    #[test]
+    fn it_rejects_truncated_instructions() {
+        let truncated = make(Opcode::Constant, vec![0])[..2].to_vec();
+        let block: Instructions = [make(Opcode::Null, vec![]), truncated.clone()]
+            .concat()
+            .into();
+        let err = block.relocate(10).unwrap_err();
+        assert_eq!(err.to_string(), "OpConstant at 1 is missing its operands");
+
+        let definition = lookup(Opcode::Constant as u8).unwrap();
+        assert!(read_operands(&definition, &truncated[1..]).is_err());
+        assert_eq!(
+            block.to_string(),
+            "0000 OpNull\n0001 ERROR: OpConstant is missing its operands\n"
+        );
+    }
+
+    #[test]
+    fn it_concatenates_blocks() {
+        let block: Instructions = [
+            make(Opcode::True, vec![]),
+            make(Opcode::JumpNotTruthy, vec![5]),
+            make(Opcode::Pop, vec![]),
+        ]
+        .concat()
+        .into();
+
+        let joined = Instructions::concat(&[block.clone(), block.clone(), block]).unwrap();
+        let expected: Instructions = [
+            make(Opcode::True, vec![]),
+            make(Opcode::JumpNotTruthy, vec![5]),
+            make(Opcode::Pop, vec![]),
+            make(Opcode::True, vec![]),
+            make(Opcode::JumpNotTruthy, vec![10]),
+            make(Opcode::Pop, vec![]),
+            make(Opcode::True, vec![]),
+            make(Opcode::JumpNotTruthy, vec![15]),
+            make(Opcode::Pop, vec![]),
+        ]
+        .concat()
+        .into();
+        assert_eq!(joined, expected);
+    }
 }
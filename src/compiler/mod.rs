@@ -1,14 +1,23 @@
 pub mod error;
+pub mod serialize;
 pub mod symbol_table;
 use crate::{
     code::{self, Instructions, Opcode},
+    lexer::Lexer,
     object::{builtin::Builtin, CompiledFunction, Object},
-    parser::ast::{Expression, Literal, Node, Statement},
+    parser::{
+        ast::{self, Expression, Literal, Node, Statement},
+        Parser,
+    },
     token::Token,
 };
 use error::CompileError;
 
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
 
 use self::symbol_table::{Scope, SymbolTable};
 
@@ -17,6 +26,17 @@ pub struct Compiler {
     pub symbol_table: Rc<RefCell<SymbolTable>>,
     pub scopes: Vec<CompilationScope>,
     pub scope_index: usize,
+    // constant pool index of each string literal compiled so far
+    interned_strings: HashMap<String, usize>,
+    // leave the value of a final top-level expression on the stack
+    keep_last_value: bool,
+    // largest position a jump operand can hold
+    max_jump_target: usize,
+    // largest element count an `Array`/`Hash` operand can hold
+    max_collection_operand: usize,
+    // compile `(fn() { expr })()` as just `expr`
+    inline_calls: bool,
+    warnings: Vec<String>,
 }
 
 pub struct Bytecode {
@@ -24,6 +44,33 @@ pub struct Bytecode {
     pub constants: Rc<RefCell<Vec<Rc<Object>>>>,
 }
 
+impl Bytecode {
+    /// The main instructions followed by those of every function in the
+    /// constant pool, each under a header naming where it came from.
+    pub fn disassemble(&self) -> String {
+        let mut out = format!("== main ==\n{}", self.instructions);
+        for (i, constant) in self.constants.borrow().iter().enumerate() {
+            if let Object::CompiledFunction(function) = &**constant {
+                out.push_str(&format!(
+                    "== constant[{}] CompiledFunction ({}, {}) ==\n{}",
+                    i,
+                    plural(function.num_parameters(), "param"),
+                    plural(function.num_locals(), "local"),
+                    function.instructions()
+                ));
+            }
+        }
+        out
+    }
+}
+
+fn plural(n: usize, noun: &str) -> String {
+    match n {
+        1 => format!("1 {}", noun),
+        n => format!("{} {}s", n, noun),
+    }
+}
+
 #[derive(Clone)]
 pub struct EmittedInstruction {
     pub opcode: Opcode,
@@ -37,17 +84,15 @@ pub struct CompilationScope {
     pub previous_instruction: EmittedInstruction,
 }
 
-impl Compiler {
-    pub fn new() -> Self {
-        let global_table = SymbolTable::new();
-
-        for (i, builtin) in Builtin::variants().iter().enumerate() {
-            global_table
-                .borrow_mut()
-                .define_builtin(i, builtin.to_string());
-        }
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-        let main_scope = CompilationScope {
+impl CompilationScope {
+    fn new() -> Self {
+        CompilationScope {
             instructions: Instructions::new(vec![]),
             last_instruction: EmittedInstruction {
                 opcode: Opcode::Constant,
@@ -57,13 +102,33 @@ impl Compiler {
                 opcode: Opcode::Constant,
                 position: 0,
             },
-        };
+        }
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        let global_table = SymbolTable::new();
+
+        for (i, builtin) in Builtin::variants().iter().enumerate() {
+            global_table
+                .borrow_mut()
+                .define_builtin(i, builtin.to_string());
+        }
+
+        let main_scope = CompilationScope::new();
 
         Compiler {
             constants: Rc::new(RefCell::new(vec![])),
             symbol_table: global_table,
             scopes: vec![main_scope],
             scope_index: 0,
+            interned_strings: HashMap::new(),
+            keep_last_value: false,
+            max_jump_target: u16::MAX as usize,
+            max_collection_operand: u16::MAX as usize,
+            inline_calls: false,
+            warnings: Vec::new(),
         }
     }
 
@@ -79,26 +144,65 @@ impl Compiler {
                 .define_builtin(i, builtin.to_string());
         }
 
-        let main_scope = CompilationScope {
-            instructions: Instructions::new(vec![]),
-            last_instruction: EmittedInstruction {
-                opcode: Opcode::Constant,
-                position: 0,
-            },
-            previous_instruction: EmittedInstruction {
-                opcode: Opcode::Constant,
-                position: 0,
-            },
-        };
+        let main_scope = CompilationScope::new();
+
+        let mut interned_strings = HashMap::new();
+        for (i, constant) in constants.borrow().iter().enumerate() {
+            if let Object::String(s) = &**constant {
+                interned_strings.entry(s.clone()).or_insert(i);
+            }
+        }
 
         Compiler {
             constants,
             symbol_table,
             scopes: vec![main_scope],
             scope_index: 0,
+            interned_strings,
+            keep_last_value: false,
+            max_jump_target: u16::MAX as usize,
+            max_collection_operand: u16::MAX as usize,
+            inline_calls: false,
+            warnings: Vec::new(),
         }
     }
 
+    /// When set, a program ending in an expression statement leaves that value on
+    /// the stack (see `VM::stack_top`) rather than popping it.
+    pub fn set_keep_last_value(&mut self, keep: bool) {
+        self.keep_last_value = keep;
+    }
+
+    /// When set, immediately invoked zero-argument function literals whose body
+    /// is a single expression are compiled in place, skipping the closure, call
+    /// and frame they would otherwise cost.
+    pub fn set_inline_calls(&mut self, inline: bool) {
+        self.inline_calls = inline;
+    }
+
+    /// Problems that don't stop compilation, such as a hash literal repeating a
+    /// key, in the order they were found.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// Parses and compiles `src` against the symbol table and constants retained
+    /// from earlier calls, returning just the instructions for this input. The
+    /// main scope starts empty on every call, so jump targets in the returned
+    /// instructions are relative to their start and can be run on their own.
+    pub fn compile_line(&mut self, src: &str) -> Result<Instructions, CompileError> {
+        let program = Parser::new(Lexer::new(src))
+            .parse_program()
+            .map_err(|errors| {
+                let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                CompileError::new(messages.join("\n"))
+            })?;
+
+        self.scopes[0] = CompilationScope::new();
+        self.compile(Node::Program(program))?;
+        Ok(self.current_instructions().clone())
+    }
+
     fn current_instructions(&self) -> &code::Instructions {
         &self.scopes[self.scope_index].instructions
     }
@@ -109,16 +213,26 @@ impl Compiler {
                 for statement in program {
                     self.compile(Node::Statement(statement))?;
                 }
+                // if-blocks are compiled by `compile_block` and function bodies
+                // in their own scope, so this only sees the top-level program
+                if self.keep_last_value
+                    && self.scope_index == 0
+                    && self.last_instruction_is(Opcode::Pop)
+                {
+                    self.remove_last_instruction();
+                }
             }
             Node::Statement(statement) => match statement {
-                Statement::Expression(expression) => {
+                Statement::Expression(expression, _) => {
                     self.compile(Node::Expression(expression))?;
                     self.emit(Opcode::Pop, vec![]);
                 }
 
                 Statement::Let(name, expression) => {
-                    let symbol = self.symbol_table.borrow_mut().define(name);
+                    // the name is only bound after its value is compiled; a function
+                    // literal reaches itself through `OpCurrentClosure` instead
                     self.compile(Node::Expression(expression))?;
+                    let symbol = self.symbol_table.borrow_mut().define(name);
                     match symbol.scope {
                         Scope::Global => {
                             self.emit(Opcode::SetGlobal, vec![symbol.index]);
@@ -146,14 +260,19 @@ impl Compiler {
             },
 
             Node::Expression(expression) => match expression {
-                Expression::Infix(left, operator, right) => {
-                    if operator == Token::Lt {
-                        self.compile(Node::Expression(*right))?;
-                        self.compile(Node::Expression(*left))?;
-                        self.emit(Opcode::GreaterThan, vec![]);
-                        return Ok(());
-                    }
+                Expression::Infix(left, operator @ (Token::And | Token::Or), right) => {
+                    self.compile_logical(*left, operator, *right)?;
+                }
+
+                Expression::Infix(left, Token::Coalesce, right) => {
+                    self.compile(Node::Expression(*left))?;
+                    let jump_position = self.emit(Opcode::JumpNotNull, vec![9999]);
+                    self.compile(Node::Expression(*right))?;
+                    let end_position = self.current_instructions().len();
+                    self.change_jump_target(jump_position, end_position)?;
+                }
 
+                Expression::Infix(left, operator, right) => {
                     self.compile(Node::Expression(*left))?;
                     self.compile(Node::Expression(*right))?;
                     match operator {
@@ -169,12 +288,22 @@ impl Compiler {
                         Token::Slash => {
                             self.emit(Opcode::Div, vec![]);
                         }
+                        Token::Percent => {
+                            self.emit(Opcode::Mod, vec![]);
+                        }
 
-                        Token::Gt | Token::Eq | Token::NotEq => {
+                        Token::Lt
+                        | Token::Gt
+                        | Token::LtEq
+                        | Token::GtEq
+                        | Token::Eq
+                        | Token::NotEq => {
                             self.emit(
                                 match operator {
-                                    Token::Lt => Opcode::GreaterThan,
+                                    Token::Lt => Opcode::LessThan,
                                     Token::Gt => Opcode::GreaterThan,
+                                    Token::LtEq => Opcode::LessEqual,
+                                    Token::GtEq => Opcode::GreaterEqual,
                                     Token::Eq => Opcode::Equal,
                                     Token::NotEq => Opcode::NotEqual,
                                     _ => {
@@ -209,7 +338,17 @@ impl Compiler {
                     Literal::Integer(value) => {
                         let integer = Rc::new(Object::Integer(value));
                         let position = self.add_constant(integer);
-                        self.emit(Opcode::Constant, vec![position]);
+                        self.emit_constant(position);
+                    }
+
+                    Literal::Float(value) => {
+                        let float = Rc::new(Object::Float(value));
+                        let position = self.add_constant(float);
+                        self.emit_constant(position);
+                    }
+
+                    Literal::Null => {
+                        self.emit(Opcode::Null, vec![]);
                     }
 
                     Literal::Boolean(value) => {
@@ -221,12 +360,12 @@ impl Compiler {
                     }
 
                     Literal::String(value) => {
-                        let string = Rc::new(Object::String(value));
-                        let position = self.add_constant(string);
-                        _ = self.emit(Opcode::Constant, vec![position]);
+                        let position = self.add_string_constant(value);
+                        self.emit_constant(position);
                     }
 
                     Literal::Array(elements) => {
+                        self.check_collection_operand("array", elements.len())?;
                         for element in elements.clone().iter() {
                             self.compile(Node::Expression(element.clone()))?;
                         }
@@ -234,20 +373,17 @@ impl Compiler {
                     }
 
                     Literal::Hash(pairs) => {
-                        let mut key_value_pairs = vec![];
-
-                        for k in &pairs {
-                            key_value_pairs.push(k);
+                        // pairs are emitted in source order so that side effects and
+                        // duplicate keys resolve the same way as in the evaluator
+                        let len = pairs.len();
+                        self.check_collection_operand("hash", len * 2)?;
+                        self.warn_duplicate_keys(&pairs);
+                        for (key, value) in pairs {
+                            self.compile(Node::Expression(key))?;
+                            self.compile(Node::Expression(value))?;
                         }
 
-                        key_value_pairs.sort_by(|a, b| a.0.cmp(&b.0));
-
-                        for k in key_value_pairs {
-                            self.compile(Node::Expression(k.0.clone()))?;
-                            self.compile(Node::Expression(k.1.clone()))?;
-                        }
-
-                        self.emit(Opcode::Hash, vec![pairs.len() * 2]);
+                        self.emit(Opcode::Hash, vec![len * 2]);
                     }
                 },
 
@@ -256,25 +392,16 @@ impl Compiler {
 
                     let jump_not_truthy_position = self.emit(Opcode::JumpNotTruthy, vec![9999]);
 
-                    self.compile(Node::Program(consequence))?;
-
-                    // leave last element of consequence on the stack
-                    if self.last_instruction_is(Opcode::Pop) {
-                        self.remove_last_instruction();
-                    }
+                    self.compile_block(consequence)?;
 
                     let jump_position = self.emit(Opcode::Jump, vec![9999]);
 
                     let after_consequence_position = self.current_instructions().len();
-                    self.change_operand(jump_not_truthy_position, after_consequence_position);
+                    self.change_jump_target(jump_not_truthy_position, after_consequence_position)?;
 
                     match alternative {
                         Some(alternative) => {
-                            self.compile(Node::Program(alternative))?;
-
-                            if self.last_instruction_is(Opcode::Pop) {
-                                self.remove_last_instruction();
-                            }
+                            self.compile_block(alternative)?;
                         }
                         None => {
                             self.emit(Opcode::Null, vec![]);
@@ -282,7 +409,47 @@ impl Compiler {
                     }
 
                     let after_alternative_position = self.current_instructions().len();
-                    self.change_operand(jump_position, after_alternative_position);
+                    self.change_jump_target(jump_position, after_alternative_position)?;
+                }
+
+                Expression::While(condition, body) => {
+                    let condition_position = self.current_instructions().len();
+                    self.compile(Node::Expression(*condition))?;
+
+                    let jump_not_truthy_position = self.emit(Opcode::JumpNotTruthy, vec![9999]);
+
+                    // statement by statement, so every value the body produces is popped
+                    for statement in body {
+                        self.compile(Node::Statement(statement))?;
+                    }
+                    let jump_position = self.emit(Opcode::Jump, vec![9999]);
+                    self.change_jump_target(jump_position, condition_position)?;
+
+                    let after_body_position = self.current_instructions().len();
+                    self.change_jump_target(jump_not_truthy_position, after_body_position)?;
+                    self.emit(Opcode::Null, vec![]);
+                }
+
+                Expression::Assign(name, value) => {
+                    let (set, get, index) = self.resolve_assignable(&name)?;
+                    self.compile(Node::Expression(*value))?;
+                    self.emit(set, vec![index]);
+                    // an assignment is an expression, so leave the value behind
+                    self.emit(get, vec![index]);
+                }
+
+                Expression::IndexAssign(target, value) => {
+                    let (name, index) = target
+                        .index_target()
+                        .ok_or_else(|| CompileError::new(format!("cannot assign to {}", target)))?;
+                    let (set, get, symbol_index) = self.resolve_assignable(name)?;
+                    self.emit(get, vec![symbol_index]);
+                    self.compile(Node::Expression(index.clone()))?;
+                    self.compile(Node::Expression(*value))?;
+                    // leaves the value under the changed container, which is
+                    // stored back so the value is what the expression gives
+                    self.emit(Opcode::SetIndex, vec![]);
+                    self.emit(set, vec![symbol_index]);
                 }
 
                 Expression::Identifier(name) => {
@@ -368,16 +535,32 @@ impl Compiler {
                         }
                     }
 
-                    let compiled_fn = Rc::new(Object::CompiledFunction(Rc::new(
-                        CompiledFunction::new(fn_instructions, num_params, num_locals),
-                    )));
+                    let compiled_fn = CompiledFunction::with_constants(
+                        fn_instructions,
+                        num_params,
+                        num_locals,
+                        &self.constants.borrow(),
+                    );
+                    let compiled_fn = Rc::new(Object::CompiledFunction(Rc::new(compiled_fn)));
 
                     let constant_index = self.add_constant(compiled_fn);
 
                     self.emit(Opcode::Closure, vec![constant_index, free_symbols.len()]);
                 }
 
+                Expression::FunctionCall(function, arguments)
+                    if *function == Expression::Identifier("quote".to_string()) =>
+                {
+                    self.compile_quote(arguments)?;
+                }
+
                 Expression::FunctionCall(function, arguments) => {
+                    if self.inline_calls && arguments.is_empty() {
+                        if let Some(body) = inlinable_body(&function) {
+                            return self.compile(Node::Expression(body.clone()));
+                        }
+                    }
+
                     self.compile(Node::Expression(*function))?;
                     let len = arguments.len();
                     for argument in arguments {
@@ -386,14 +569,110 @@ impl Compiler {
                     self.emit(Opcode::Call, vec![len]);
                 }
 
-                _ => {
-                    panic!("not implemented")
+                // `define_macros` has already taken out every macro bound by a
+                // top-level `let`, so any left over is in a place it can't be
+                Expression::Macro(..) => {
+                    return Err(CompileError::new(
+                        "macros can only be defined by a top-level let".to_string(),
+                    ));
                 }
             },
         }
         Ok(())
     }
 
+    // the opcodes that write and read `name`, for the scopes that can be
+    // assigned to
+    fn resolve_assignable(&mut self, name: &str) -> Result<(Opcode, Opcode, usize), CompileError> {
+        let symbol = self.symbol_table.borrow_mut().resolve(name);
+        let symbol = symbol.ok_or_else(|| {
+            CompileError::new(format!("cannot assign to undefined variable {}", name))
+        })?;
+        // closures capture values, not bindings, so writing to a free
+        // variable would only change the closure's own copy
+        match symbol.scope {
+            Scope::Global => Ok((Opcode::SetGlobal, Opcode::GetGlobal, symbol.index)),
+            Scope::Local => Ok((Opcode::SetLocal, Opcode::GetLocal, symbol.index)),
+            Scope::Builtin => Err(CompileError::new(format!(
+                "cannot assign to builtin {}",
+                name
+            ))),
+            Scope::Free => Err(CompileError::new(format!(
+                "cannot assign to captured variable {}",
+                name
+            ))),
+            Scope::Function => Err(CompileError::new(format!(
+                "cannot assign to function {}",
+                name
+            ))),
+        }
+    }
+
+    // a quote with nothing to unquote is just a constant; unquoting needs the
+    // evaluator, which only macro expansion has
+    fn compile_quote(&mut self, arguments: Vec<Expression>) -> Result<(), CompileError> {
+        let [argument]: [Expression; 1] = arguments
+            .try_into()
+            .map_err(|_| CompileError::new("quote takes exactly one argument".to_string()))?;
+        let unquoted = Cell::new(false);
+        let node = ast::modify(Node::Expression(argument), |node| {
+            if let Node::Expression(Expression::FunctionCall(function, _)) = &node {
+                if **function == Expression::Identifier("unquote".to_string()) {
+                    unquoted.set(true);
+                }
+            }
+            node
+        });
+        if unquoted.get() {
+            return Err(CompileError::new(
+                "unquote is only supported inside macros when compiling".to_string(),
+            ));
+        }
+        let position = self.add_constant(Rc::new(Object::Quote(node)));
+        self.emit_constant(position);
+        Ok(())
+    }
+
+    // `&&` and `||` lower to jumps rather than an opcode, so the right side is
+    // only run when the left one leaves the result open. Either way the result
+    // is a boolean.
+    fn compile_logical(
+        &mut self,
+        left: Expression,
+        operator: Token,
+        right: Expression,
+    ) -> Result<(), CompileError> {
+        self.compile(Node::Expression(left))?;
+        let jump_not_truthy_position = self.emit(Opcode::JumpNotTruthy, vec![9999]);
+
+        let jump_position = if operator == Token::And {
+            self.compile_truthiness(right)?;
+            let jump_position = self.emit(Opcode::Jump, vec![9999]);
+            let after_right_position = self.current_instructions().len();
+            self.change_jump_target(jump_not_truthy_position, after_right_position)?;
+            self.emit(Opcode::False, vec![]);
+            jump_position
+        } else {
+            self.emit(Opcode::True, vec![]);
+            let jump_position = self.emit(Opcode::Jump, vec![9999]);
+            let after_true_position = self.current_instructions().len();
+            self.change_jump_target(jump_not_truthy_position, after_true_position)?;
+            self.compile_truthiness(right)?;
+            jump_position
+        };
+
+        let end_position = self.current_instructions().len();
+        self.change_jump_target(jump_position, end_position)
+    }
+
+    // `!!expression`, the boolean the VM would branch on
+    fn compile_truthiness(&mut self, expression: Expression) -> Result<(), CompileError> {
+        self.compile(Node::Expression(expression))?;
+        self.emit(Opcode::Bang, vec![]);
+        self.emit(Opcode::Bang, vec![]);
+        Ok(())
+    }
+
     pub fn bytecode(&self) -> Bytecode {
         Bytecode {
             instructions: self.current_instructions().clone(),
@@ -407,6 +686,17 @@ impl Compiler {
         self.constants.borrow_mut().len() - 1
     }
 
+    /// Adds a string constant, reusing the existing pool entry (and so the same
+    /// `Rc`) when an identical literal has already been compiled.
+    pub fn add_string_constant(&mut self, value: String) -> usize {
+        if let Some(&position) = self.interned_strings.get(&value) {
+            return position;
+        }
+        let position = self.add_constant(Object::new_string(value.clone()));
+        self.interned_strings.insert(value, position);
+        position
+    }
+
     pub fn emit(&mut self, opcode: Opcode, operands: Vec<usize>) -> usize {
         let ins = code::make(opcode, operands);
         let pos = self.add_instructions(ins);
@@ -414,6 +704,14 @@ impl Compiler {
         pos
     }
 
+    pub fn emit_constant(&mut self, position: usize) -> usize {
+        if position > u16::MAX as usize {
+            self.emit(Opcode::ConstantWide, vec![position])
+        } else {
+            self.emit(Opcode::Constant, vec![position])
+        }
+    }
+
     pub fn set_last_instruction(&mut self, opcode: Opcode, position: usize) {
         let current_scope = &mut self.scopes[self.scope_index];
         current_scope.previous_instruction = current_scope.last_instruction.clone();
@@ -425,6 +723,20 @@ impl Compiler {
         current_scope.last_instruction.opcode == opcode
     }
 
+    // Every branch of an `if` must leave exactly one value on the stack: the
+    // last expression's, or null when the block is empty or ends in a `let`.
+    fn compile_block(&mut self, block: Vec<Statement>) -> Result<(), CompileError> {
+        for statement in block {
+            self.compile(Node::Statement(statement))?;
+        }
+        if self.last_instruction_is(Opcode::Pop) {
+            self.remove_last_instruction();
+        } else {
+            self.emit(Opcode::Null, vec![]);
+        }
+        Ok(())
+    }
+
     pub fn remove_last_instruction(&mut self) {
         let current_scope = &mut self.scopes[self.scope_index];
         let last = current_scope.last_instruction.position;
@@ -443,8 +755,8 @@ impl Compiler {
 
     fn replace_instruction(&mut self, position: usize, new_instructions: Vec<u8>) {
         let current_scope = &mut self.scopes[self.scope_index];
-        for i in 0..new_instructions.len() {
-            current_scope.instructions[position + i] = new_instructions[i];
+        for (i, byte) in new_instructions.iter().enumerate() {
+            current_scope.instructions[position + i] = *byte;
         }
     }
 
@@ -455,18 +767,58 @@ impl Compiler {
         self.replace_instruction(position, new_instrution);
     }
 
+    fn change_jump_target(&mut self, position: usize, target: usize) -> Result<(), CompileError> {
+        if target > self.max_jump_target {
+            return Err(CompileError::new(format!(
+                "jump target {} is past the {} byte limit for a single scope",
+                target, self.max_jump_target
+            )));
+        }
+        self.change_operand(position, target);
+        Ok(())
+    }
+
+    #[cfg(test)]
+    fn set_max_jump_target(&mut self, max_jump_target: usize) {
+        self.max_jump_target = max_jump_target;
+    }
+
+    // `count` is the number of stack values the instruction collects, which
+    // for a hash is two per pair
+    fn check_collection_operand(&self, kind: &str, count: usize) -> Result<(), CompileError> {
+        if count > self.max_collection_operand {
+            return Err(CompileError::new(format!(
+                "{} literal needs {} stack values, more than the {} a single instruction can collect",
+                kind, count, self.max_collection_operand
+            )));
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    fn set_max_collection_operand(&mut self, max_collection_operand: usize) {
+        self.max_collection_operand = max_collection_operand;
+    }
+
+    // the last value for a repeated key wins in both backends, but a literal
+    // key written twice is almost certainly a mistake
+    fn warn_duplicate_keys(&mut self, pairs: &[(Expression, Expression)]) {
+        let mut seen = HashSet::new();
+        for (key, _) in pairs {
+            if let Expression::Literal(
+                literal @ (Literal::Integer(_) | Literal::Boolean(_) | Literal::String(_)),
+            ) = key
+            {
+                if !seen.insert(format!("{:?}", literal)) {
+                    self.warnings
+                        .push(format!("duplicate hash key {}, the last value wins", key));
+                }
+            }
+        }
+    }
+
     fn enter_scope(&mut self) {
-        self.scopes.push(CompilationScope {
-            instructions: Instructions::new(vec![]),
-            last_instruction: EmittedInstruction {
-                opcode: Opcode::Constant,
-                position: 0,
-            },
-            previous_instruction: EmittedInstruction {
-                opcode: Opcode::Constant,
-                position: 0,
-            },
-        });
+        self.scopes.push(CompilationScope::new());
         self.scope_index += 1;
         let symbol_table = SymbolTable::new_enclosed(self.symbol_table.clone());
         self.symbol_table = symbol_table;
@@ -493,9 +845,55 @@ impl Compiler {
     }
 }
 
+// With no parameters the body has no locals of its own, and a single
+// expression without nested blocks has no `return` that could escape into the
+// caller once inlined.
+fn inlinable_body(function: &Expression) -> Option<&Expression> {
+    match function {
+        Expression::Function(None, parameters, body) if parameters.is_empty() => {
+            match body.as_slice() {
+                [Statement::Expression(expression, _)] | [Statement::Return(expression)]
+                    if is_block_free(expression) =>
+                {
+                    Some(expression)
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+// nested function literals are fine: a `return` inside one stays inside it
+fn is_block_free(expression: &Expression) -> bool {
+    match expression {
+        Expression::Identifier(_) | Expression::Function(..) => true,
+        Expression::Literal(Literal::Array(elements)) => elements.iter().all(is_block_free),
+        Expression::Literal(Literal::Hash(pairs)) => pairs
+            .iter()
+            .all(|(key, value)| is_block_free(key) && is_block_free(value)),
+        Expression::Literal(_) => true,
+        Expression::Prefix(_, right) => is_block_free(right),
+        Expression::Infix(left, _, right) | Expression::Index(left, right) => {
+            is_block_free(left) && is_block_free(right)
+        }
+        Expression::FunctionCall(function, arguments) => {
+            is_block_free(function) && arguments.iter().all(is_block_free)
+        }
+        // assigning to the caller's locals would turn what was a captured copy
+        // into a write to the caller's binding
+        Expression::If(..)
+        | Expression::While(..)
+        | Expression::Macro(..)
+        | Expression::Assign(..)
+        | Expression::IndexAssign(..) => false,
+    }
+}
+
 #[cfg(test)]
+#[allow(clippy::rc_clone_in_vec_init)]
 mod test {
-    use crate::{code::make, lexer::Lexer, parser::Parser};
+    use crate::{code::make, vm::VM};
 
     use super::*;
 
@@ -504,7 +902,7 @@ mod test {
         actual_instructions: Vec<Instructions>,
         actual_constants: Vec<Rc<Object>>,
     ) {
-        let lexer = Lexer::new(input.into());
+        let lexer = Lexer::new(input);
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program().unwrap();
         let mut compiler = Compiler::new();
@@ -645,8 +1043,44 @@ mod test {
         );
     }
 
+    #[test]
+    fn it_compiles_remainders() {
+        test_compilation(
+            "7 % 3",
+            vec![
+                make(Opcode::Constant, vec![0]).into(),
+                make(Opcode::Constant, vec![1]).into(),
+                make(Opcode::Mod, vec![]).into(),
+                make(Opcode::Pop, vec![]).into(),
+            ],
+            vec![Rc::new(Object::Integer(7)), Rc::new(Object::Integer(3))],
+        );
+    }
+
     #[test]
     fn it_compiles_comparison_operations() {
+        test_compilation(
+            "1 <= 2",
+            vec![
+                make(Opcode::Constant, vec![0]).into(),
+                make(Opcode::Constant, vec![1]).into(),
+                make(Opcode::LessEqual, vec![]).into(),
+                make(Opcode::Pop, vec![]).into(),
+            ],
+            vec![Rc::new(Object::Integer(1)), Rc::new(Object::Integer(2))],
+        );
+
+        test_compilation(
+            "1 >= 2",
+            vec![
+                make(Opcode::Constant, vec![0]).into(),
+                make(Opcode::Constant, vec![1]).into(),
+                make(Opcode::GreaterEqual, vec![]).into(),
+                make(Opcode::Pop, vec![]).into(),
+            ],
+            vec![Rc::new(Object::Integer(1)), Rc::new(Object::Integer(2))],
+        );
+
         test_compilation(
             "1 == 1",
             vec![
@@ -685,10 +1119,10 @@ mod test {
             vec![
                 make(Opcode::Constant, vec![0]).into(),
                 make(Opcode::Constant, vec![1]).into(),
-                make(Opcode::GreaterThan, vec![]).into(),
+                make(Opcode::LessThan, vec![]).into(),
                 make(Opcode::Pop, vec![]).into(),
             ],
-            vec![Rc::new(Object::Integer(2)), Rc::new(Object::Integer(1))],
+            vec![Rc::new(Object::Integer(1)), Rc::new(Object::Integer(2))],
         );
 
         test_compilation(
@@ -782,6 +1216,286 @@ mod test {
                 Rc::new(Object::Integer(3333)),
             ],
         );
+
+        test_compilation(
+            "if (true) {} else { let a = 1; };",
+            vec![
+                make(Opcode::True, vec![]).into(),
+                make(Opcode::JumpNotTruthy, vec![8]).into(),
+                make(Opcode::Null, vec![]).into(),
+                make(Opcode::Jump, vec![15]).into(),
+                make(Opcode::Constant, vec![0]).into(),
+                make(Opcode::SetGlobal, vec![0]).into(),
+                make(Opcode::Null, vec![]).into(),
+                make(Opcode::Pop, vec![]).into(),
+            ],
+            vec![Rc::new(Object::Integer(1))],
+        );
+    }
+
+    #[test]
+    fn it_compiles_logical_operators() {
+        test_compilation(
+            "true && false",
+            vec![
+                // 0000
+                make(Opcode::True, vec![]).into(),
+                // 0001
+                make(Opcode::JumpNotTruthy, vec![10]).into(),
+                // 0004
+                make(Opcode::False, vec![]).into(),
+                // 0005
+                make(Opcode::Bang, vec![]).into(),
+                // 0006
+                make(Opcode::Bang, vec![]).into(),
+                // 0007
+                make(Opcode::Jump, vec![11]).into(),
+                // 0010
+                make(Opcode::False, vec![]).into(),
+                // 0011
+                make(Opcode::Pop, vec![]).into(),
+            ],
+            vec![],
+        );
+
+        test_compilation(
+            "true || false",
+            vec![
+                // 0000
+                make(Opcode::True, vec![]).into(),
+                // 0001
+                make(Opcode::JumpNotTruthy, vec![8]).into(),
+                // 0004
+                make(Opcode::True, vec![]).into(),
+                // 0005
+                make(Opcode::Jump, vec![11]).into(),
+                // 0008
+                make(Opcode::False, vec![]).into(),
+                // 0009
+                make(Opcode::Bang, vec![]).into(),
+                // 0010
+                make(Opcode::Bang, vec![]).into(),
+                // 0011
+                make(Opcode::Pop, vec![]).into(),
+            ],
+            vec![],
+        );
+    }
+
+    #[test]
+    fn it_compiles_null_coalescing() {
+        test_compilation(
+            "null ?? 5",
+            vec![
+                // 0000
+                make(Opcode::Null, vec![]).into(),
+                // 0001
+                make(Opcode::JumpNotNull, vec![7]).into(),
+                // 0004
+                make(Opcode::Constant, vec![0]).into(),
+                // 0007
+                make(Opcode::Pop, vec![]).into(),
+            ],
+            vec![Rc::new(Object::Integer(5))],
+        );
+    }
+
+    #[test]
+    fn it_compiles_while_loops() {
+        test_compilation(
+            "let i = 0; while (i < 3) { let i = i + 1; }; i;",
+            vec![
+                make(Opcode::Constant, vec![0]).into(),
+                make(Opcode::SetGlobal, vec![0]).into(),
+                make(Opcode::GetGlobal, vec![0]).into(),
+                make(Opcode::Constant, vec![1]).into(),
+                make(Opcode::LessThan, vec![]).into(),
+                make(Opcode::JumpNotTruthy, vec![29]).into(),
+                make(Opcode::GetGlobal, vec![0]).into(),
+                make(Opcode::Constant, vec![2]).into(),
+                make(Opcode::Add, vec![]).into(),
+                make(Opcode::SetGlobal, vec![0]).into(),
+                make(Opcode::Jump, vec![6]).into(),
+                make(Opcode::Null, vec![]).into(),
+                make(Opcode::Pop, vec![]).into(),
+                make(Opcode::GetGlobal, vec![0]).into(),
+                make(Opcode::Pop, vec![]).into(),
+            ],
+            vec![
+                Rc::new(Object::Integer(0)),
+                Rc::new(Object::Integer(3)),
+                Rc::new(Object::Integer(1)),
+            ],
+        );
+
+        test_compilation(
+            "while (false) { while (true) { 1 } }",
+            vec![
+                make(Opcode::False, vec![]).into(),
+                make(Opcode::JumpNotTruthy, vec![20]).into(),
+                make(Opcode::True, vec![]).into(),
+                make(Opcode::JumpNotTruthy, vec![15]).into(),
+                make(Opcode::Constant, vec![0]).into(),
+                make(Opcode::Pop, vec![]).into(),
+                make(Opcode::Jump, vec![4]).into(),
+                make(Opcode::Null, vec![]).into(),
+                make(Opcode::Pop, vec![]).into(),
+                make(Opcode::Jump, vec![0]).into(),
+                make(Opcode::Null, vec![]).into(),
+                make(Opcode::Pop, vec![]).into(),
+            ],
+            vec![Rc::new(Object::Integer(1))],
+        );
+    }
+
+    #[test]
+    fn it_compiles_assignments() {
+        test_compilation(
+            "let x = 1; x = 2;",
+            vec![
+                make(Opcode::Constant, vec![0]).into(),
+                make(Opcode::SetGlobal, vec![0]).into(),
+                make(Opcode::Constant, vec![1]).into(),
+                make(Opcode::SetGlobal, vec![0]).into(),
+                make(Opcode::GetGlobal, vec![0]).into(),
+                make(Opcode::Pop, vec![]).into(),
+            ],
+            vec![Rc::new(Object::Integer(1)), Rc::new(Object::Integer(2))],
+        );
+
+        let tests = vec![
+            ("x = 1", "cannot assign to undefined variable x"),
+            ("len = 1", "cannot assign to builtin len"),
+            (
+                "let f = fn() { let y = 1; fn() { y = 2 } }",
+                "cannot assign to captured variable y",
+            ),
+            ("let f = fn() { f = 1 }", "cannot assign to function f"),
+            ("a[0] = 1", "cannot assign to undefined variable a"),
+            ("len[0] = 1", "cannot assign to builtin len"),
+            (
+                "let f = fn() { let a = [1]; fn() { a[0] = 2 } }",
+                "cannot assign to captured variable a",
+            ),
+        ];
+        for (input, expected) in tests {
+            let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+            let err = Compiler::new().compile(Node::Program(program)).unwrap_err();
+            assert_eq!(err.to_string(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn it_disassembles_every_function() {
+        let program = Parser::new(Lexer::new(
+            "let add = fn(a, b) { let c = a + b; c }; add(1, fn() { 2 }())",
+        ))
+        .parse_program()
+        .unwrap();
+        let mut compiler = Compiler::new();
+        compiler.compile(Node::Program(program)).unwrap();
+
+        let expected = "\
+== main ==
+0000 OpClosure 0 0
+0004 OpSetGlobal 0
+0007 OpGetGlobal 0
+0010 OpConstant 1
+0013 OpClosure 3 0
+0017 OpCall 0
+0019 OpCall 2
+0021 OpPop
+== constant[0] CompiledFunction (2 params, 3 locals) ==
+0000 OpGetLocal 0
+0002 OpGetLocal 1
+0004 OpAdd
+0005 OpSetLocal 2
+0007 OpGetLocal 2
+0009 OpReturnValue
+== constant[3] CompiledFunction (0 params, 0 locals) ==
+0000 OpConstant 2
+0003 OpReturnValue
+";
+        assert_eq!(compiler.bytecode().disassemble(), expected);
+    }
+
+    #[test]
+    fn it_compiles_quotes_as_constants() {
+        let program = Parser::new(Lexer::new("quote(1 + x)"))
+            .parse_program()
+            .unwrap();
+        let mut compiler = Compiler::new();
+        compiler.compile(Node::Program(program)).unwrap();
+        let bytecode = compiler.bytecode();
+        assert_eq!(
+            bytecode.instructions,
+            Instructions::from(
+                [make(Opcode::Constant, vec![0]), make(Opcode::Pop, vec![])].concat()
+            )
+        );
+        assert_eq!(bytecode.constants.borrow()[0].to_string(), "QUOTE(1 + x)");
+
+        let tests = vec![
+            ("quote(1, 2)", "quote takes exactly one argument"),
+            (
+                "let x = 1; quote(unquote(x) + 1)",
+                "unquote is only supported inside macros when compiling",
+            ),
+            (
+                "let f = fn() { let m = macro(x) { x }; m }",
+                "macros can only be defined by a top-level let",
+            ),
+            (
+                "macro(x) { x }",
+                "macros can only be defined by a top-level let",
+            ),
+        ];
+        for (input, expected) in tests {
+            let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+            let err = Compiler::new().compile(Node::Program(program)).unwrap_err();
+            assert_eq!(err.to_string(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn it_compiles_index_assignments() {
+        test_compilation(
+            "let a = [1]; a[0] = 2;",
+            vec![
+                make(Opcode::Constant, vec![0]).into(),
+                make(Opcode::Array, vec![1]).into(),
+                make(Opcode::SetGlobal, vec![0]).into(),
+                make(Opcode::GetGlobal, vec![0]).into(),
+                make(Opcode::Constant, vec![1]).into(),
+                make(Opcode::Constant, vec![2]).into(),
+                make(Opcode::SetIndex, vec![]).into(),
+                make(Opcode::SetGlobal, vec![0]).into(),
+                make(Opcode::Pop, vec![]).into(),
+            ],
+            vec![
+                Rc::new(Object::Integer(1)),
+                Rc::new(Object::Integer(0)),
+                Rc::new(Object::Integer(2)),
+            ],
+        );
+    }
+
+    #[test]
+    fn it_rejects_jumps_past_the_operand_limit() {
+        let compile = |input: &str, max_jump_target: usize| {
+            let mut compiler = Compiler::new();
+            compiler.set_max_jump_target(max_jump_target);
+            let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+            compiler.compile(Node::Program(program))
+        };
+
+        // the jump over the alternative lands at byte 11
+        assert!(compile("if (true) { 10 };", 11).is_ok());
+        assert_eq!(
+            compile("if (true) { 10 };", 10).unwrap_err().to_string(),
+            "jump target 11 is past the 10 byte limit for a single scope"
+        );
+        assert!(compile("fn() { if (true) { 10 } }", 10).is_err());
     }
 
     #[test]
@@ -846,6 +1560,38 @@ mod test {
                 Rc::new(Object::String("key".to_string())),
             ],
         );
+
+        test_compilation(
+            r#""key" + "key""#,
+            vec![
+                make(Opcode::Constant, vec![0]).into(),
+                make(Opcode::Constant, vec![0]).into(),
+                make(Opcode::Add, vec![]).into(),
+                make(Opcode::Pop, vec![]).into(),
+            ],
+            vec![Rc::new(Object::String("key".to_string()))],
+        );
+    }
+
+    #[test]
+    fn it_interns_string_constants() {
+        let mut compiler = Compiler::new();
+        compiler
+            .compile_line(r#"let a = "key"; let f = fn() { "key" }; ["key", f()]"#)
+            .unwrap();
+        assert_eq!(compiler.constants.borrow().len(), 2);
+
+        let mut machine = VM::new(compiler.bytecode());
+        machine.run().unwrap();
+        match &*machine.last_popped_stack_elem() {
+            Object::Array(elements) => assert!(Rc::ptr_eq(&elements[0], &elements[1])),
+            other => panic!("expected array, got {}", other),
+        }
+
+        let mut resumed =
+            Compiler::new_with_state(compiler.symbol_table.clone(), compiler.constants.clone());
+        resumed.compile_line(r#""key""#).unwrap();
+        assert_eq!(resumed.constants.borrow().len(), 2);
     }
 
     #[test]
@@ -901,6 +1647,33 @@ mod test {
         );
     }
 
+    #[test]
+    fn it_rejects_collections_past_the_operand_limit() {
+        let compile = |input: &str| {
+            let mut compiler = Compiler::new();
+            compiler.set_max_collection_operand(4);
+            let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+            compiler.compile(Node::Program(program))
+        };
+
+        assert!(compile("[1, 2, 3, 4]").is_ok());
+        assert!(compile("{1: 2, 3: 4}").is_ok());
+        assert_eq!(
+            compile("[1, 2, 3, 4, 5]").unwrap_err().to_string(),
+            "array literal needs 5 stack values, more than the 4 a single instruction can collect"
+        );
+        assert_eq!(
+            compile("{1: 2, 3: 4, 5: 6}").unwrap_err().to_string(),
+            "hash literal needs 6 stack values, more than the 4 a single instruction can collect"
+        );
+        assert_eq!(
+            compile("fn() { [[1, 2, 3, 4, 5]] }")
+                .unwrap_err()
+                .to_string(),
+            "array literal needs 5 stack values, more than the 4 a single instruction can collect"
+        );
+    }
+
     #[test]
     fn it_compiles_hash_expressions() {
         test_compilation(
@@ -957,6 +1730,24 @@ mod test {
                 Rc::new(Object::Integer(6)),
             ],
         );
+
+        test_compilation(
+            "{2: 1, 1: 2}",
+            vec![
+                make(Opcode::Constant, vec![0]).into(),
+                make(Opcode::Constant, vec![1]).into(),
+                make(Opcode::Constant, vec![2]).into(),
+                make(Opcode::Constant, vec![3]).into(),
+                make(Opcode::Hash, vec![4]).into(),
+                make(Opcode::Pop, vec![]).into(),
+            ],
+            vec![
+                Rc::new(Object::Integer(2)),
+                Rc::new(Object::Integer(1)),
+                Rc::new(Object::Integer(1)),
+                Rc::new(Object::Integer(2)),
+            ],
+        );
     }
 
     #[test]
@@ -1550,4 +2341,163 @@ mod test {
             ],
         );
     }
+
+    #[test]
+    fn it_compiles_lines_incrementally() {
+        let mut compiler = Compiler::new();
+        let globals = Rc::new(RefCell::new(vec![
+            Rc::new(Object::Null);
+            crate::vm::GLOBAL_SIZE
+        ]));
+
+        let first = compiler.compile_line("let a = 5;").unwrap();
+        test_instructions(
+            first.clone(),
+            vec![
+                make(Opcode::Constant, vec![0]).into(),
+                make(Opcode::SetGlobal, vec![0]).into(),
+            ],
+        );
+        let bytecode = Bytecode {
+            instructions: first,
+            constants: compiler.constants.clone(),
+        };
+        VM::new_with_global_store(bytecode, globals.clone())
+            .run()
+            .unwrap();
+
+        let second = compiler
+            .compile_line("if (a > 1) { a + 1 } else { 0 }")
+            .unwrap();
+        assert_eq!(second[0], Opcode::GetGlobal as u8);
+        let bytecode = Bytecode {
+            instructions: second,
+            constants: compiler.constants.clone(),
+        };
+        let mut machine = VM::new_with_global_store(bytecode, globals.clone());
+        machine.run().unwrap();
+        assert_eq!(*machine.last_popped_stack_elem(), Object::Integer(6));
+    }
+
+    #[test]
+    fn it_runs_relocated_lines_as_one_program() {
+        let mut compiler = Compiler::new();
+        let lines = [
+            "let a = if (false) { 1 } else { 2 };",
+            "let b = 0; while (b < a * 3) { let b = b + 1; };",
+            "if (a < b) { a + b } else { 0 }",
+        ]
+        .map(|line| compiler.compile_line(line).unwrap());
+
+        let bytecode = Bytecode {
+            instructions: Instructions::concat(&lines).unwrap(),
+            constants: compiler.constants.clone(),
+        };
+        let mut machine = VM::new(bytecode);
+        machine.run().unwrap();
+        assert_eq!(*machine.last_popped_stack_elem(), Object::Integer(8));
+    }
+
+    #[test]
+    fn it_keeps_the_last_value_on_the_stack() {
+        let tests = vec![
+            ("1; 2 + 3", Some(Object::Integer(5))),
+            ("if (true) { 1 } else { 2 }", Some(Object::Integer(1))),
+            ("if (false) { 1 }", Some(Object::Null)),
+            ("let x = fn() { 4; 5 }; x()", Some(Object::Integer(5))),
+            ("1; let x = 2;", None),
+        ];
+
+        for (input, expected) in tests {
+            let mut compiler = Compiler::new();
+            compiler.set_keep_last_value(true);
+            let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+            compiler.compile(Node::Program(program)).unwrap();
+            let bytecode = compiler.bytecode();
+            assert!(!compiler.last_instruction_is(Opcode::Pop), "{}", input);
+
+            let mut machine = VM::new(bytecode);
+            machine.run().unwrap();
+            assert_eq!(
+                machine.stack_top().map(|o| (*o).clone()),
+                expected,
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn it_inlines_immediately_invoked_functions() {
+        let tests = vec![
+            ("(fn() { 1 + 2 })();", true),
+            ("(fn() { return [1, 2][0] * 5; })();", true),
+            ("let x = 4; (fn() { (fn() { x * 2 })() })();", true),
+            ("let f = fn() { (fn() { 3 })() }; f();", true),
+            ("(fn() { fn() { return 7; } })()();", true),
+            ("(fn() { if (true) { return 1; }; 2 })();", false),
+            ("(fn() { if (true) { return 1 } else { 2 } })();", false),
+            ("(fn(a) { a })(9);", false),
+        ];
+
+        for (input, inlined) in tests {
+            let compile = |inline: bool| {
+                let mut compiler = Compiler::new();
+                compiler.set_inline_calls(inline);
+                let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+                compiler.compile(Node::Program(program)).unwrap();
+                compiler.bytecode()
+            };
+            let plain = compile(false);
+            let optimized = compile(true);
+
+            let count = |bytecode: &Bytecode| {
+                let mut count = bytecode.instructions.len();
+                for constant in bytecode.constants.borrow().iter() {
+                    if let Object::CompiledFunction(f) = &**constant {
+                        count += f.instructions.len();
+                    }
+                }
+                count
+            };
+            if inlined {
+                assert!(count(&optimized) < count(&plain), "{}", input);
+            } else {
+                assert_eq!(count(&optimized), count(&plain), "{}", input);
+            }
+
+            let run = |bytecode: Bytecode| {
+                let mut machine = VM::new(bytecode);
+                machine.run().unwrap();
+                (*machine.last_popped_stack_elem()).clone()
+            };
+            assert_eq!(run(optimized), run(plain), "{}", input);
+        }
+    }
+
+    #[test]
+    fn it_warns_about_duplicate_literal_hash_keys() {
+        let tests = vec![
+            (
+                "{1: 2, 1: 3}",
+                vec!["duplicate hash key 1, the last value wins"],
+            ),
+            (
+                r#"{"a": 1, "b": 2, "a": 3, true: 4, true: 5}"#,
+                vec![
+                    "duplicate hash key a, the last value wins",
+                    "duplicate hash key true, the last value wins",
+                ],
+            ),
+            (r#"{1: 1, "1": 2, true: 3}"#, vec![]),
+            ("let k = 1; {k: 1, k: 2, 1 + 0: 3}", vec![]),
+        ];
+
+        for (input, expected) in tests {
+            let mut compiler = Compiler::new();
+            let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+            compiler.compile(Node::Program(program)).unwrap();
+            assert_eq!(compiler.warnings(), expected, "{}", input);
+        }
+    }
 }
@@ -0,0 +1,437 @@
+// The on-disk form of `Bytecode`, for caching compiled programs:
+//
+//   b"MNKY" | version: u16 | instructions | constant count: u32 | constants...
+//
+// Instructions are a u32 length and the raw bytes. A constant is a tag byte
+// and its value, with functions carrying their own instructions. All numbers
+// are big endian, like instruction operands.
+
+use std::{
+    cell::RefCell,
+    io::{Cursor, Read},
+    rc::Rc,
+};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use super::{error::CompileError, Bytecode};
+use crate::code::{self, Instructions, Opcode};
+use crate::object::{builtin::Builtin, CompiledFunction, Object};
+use crate::vm::STACK_SIZE;
+
+const MAGIC: &[u8; 4] = b"MNKY";
+
+/// Bump whenever an opcode is added, removed or has its operands changed, so
+/// files written for a different instruction set are refused on load.
+pub const BYTECODE_VERSION: u16 = 1;
+
+const INTEGER: u8 = 0;
+const FLOAT: u8 = 1;
+const STRING: u8 = 2;
+const FUNCTION: u8 = 3;
+
+impl Bytecode {
+    pub fn serialize(&self) -> Result<Vec<u8>, CompileError> {
+        let mut out = MAGIC.to_vec();
+        out.write_u16::<BigEndian>(BYTECODE_VERSION).unwrap();
+        write_instructions(&mut out, &self.instructions)?;
+        let constants = self.constants.borrow();
+        write_len(&mut out, constants.len())?;
+        for constant in constants.iter() {
+            write_constant(&mut out, constant)?;
+        }
+        Ok(out)
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Bytecode, CompileError> {
+        let mut input = Cursor::new(bytes);
+        let mut magic = [0; 4];
+        input.read_exact(&mut magic).map_err(truncated)?;
+        if &magic != MAGIC {
+            return Err(CompileError::new("not a monkey bytecode file".to_string()));
+        }
+        let version = input.read_u16::<BigEndian>().map_err(truncated)?;
+        if version != BYTECODE_VERSION {
+            return Err(CompileError::new(format!(
+                "unsupported bytecode version {}, expected {}",
+                version, BYTECODE_VERSION
+            )));
+        }
+
+        let instructions = read_instructions(&mut input)?;
+        let count = input.read_u32::<BigEndian>().map_err(truncated)?;
+        let mut constants = vec![];
+        for _ in 0..count {
+            let constant = read_constant(&mut input, &constants)?;
+            constants.push(Rc::new(constant));
+        }
+        if input.position() as usize != bytes.len() {
+            return Err(CompileError::new(
+                "trailing bytes after bytecode".to_string(),
+            ));
+        }
+        check_constant_operands(&instructions, &constants)?;
+        for constant in &constants {
+            if let Object::CompiledFunction(function) = &**constant {
+                check_constant_operands(function.instructions(), &constants)?;
+            }
+        }
+
+        Ok(Bytecode {
+            instructions,
+            constants: Rc::new(RefCell::new(constants)),
+        })
+    }
+}
+
+fn write_len(out: &mut Vec<u8>, len: usize) -> Result<(), CompileError> {
+    let len = u32::try_from(len)
+        .map_err(|_| CompileError::new(format!("{} is too long to serialize", len)))?;
+    out.write_u32::<BigEndian>(len).unwrap();
+    Ok(())
+}
+
+fn write_instructions(out: &mut Vec<u8>, instructions: &Instructions) -> Result<(), CompileError> {
+    write_len(out, instructions.len())?;
+    out.extend_from_slice(instructions.as_slice());
+    Ok(())
+}
+
+fn write_constant(out: &mut Vec<u8>, constant: &Object) -> Result<(), CompileError> {
+    match constant {
+        Object::Integer(i) => {
+            out.push(INTEGER);
+            out.write_i64::<BigEndian>(*i).unwrap();
+        }
+        Object::Float(x) => {
+            out.push(FLOAT);
+            out.write_f64::<BigEndian>(*x).unwrap();
+        }
+        Object::String(s) => {
+            out.push(STRING);
+            write_len(out, s.len())?;
+            out.extend_from_slice(s.as_bytes());
+        }
+        Object::CompiledFunction(function) => {
+            out.push(FUNCTION);
+            write_len(out, function.num_parameters())?;
+            write_len(out, function.num_locals())?;
+            write_instructions(out, function.instructions())?;
+        }
+        _ => {
+            return Err(CompileError::new(format!(
+                "cannot serialize {} constants",
+                constant.type_tag()
+            )))
+        }
+    }
+    Ok(())
+}
+
+fn truncated(_: std::io::Error) -> CompileError {
+    CompileError::new("bytecode ends unexpectedly".to_string())
+}
+
+fn read_bytes(input: &mut Cursor<&[u8]>) -> Result<Vec<u8>, CompileError> {
+    let len = input.read_u32::<BigEndian>().map_err(truncated)? as usize;
+    let remaining = input.get_ref().len() - input.position() as usize;
+    if len > remaining {
+        return Err(truncated(std::io::ErrorKind::UnexpectedEof.into()));
+    }
+    let mut bytes = vec![0; len];
+    input.read_exact(&mut bytes).map_err(truncated)?;
+    Ok(bytes)
+}
+
+// Splits `bytes` into instructions, checking every opcode is known and has
+// all its operands, so the vm never reads past the end of a loaded function.
+fn decode(bytes: &[u8]) -> Result<Vec<(usize, Opcode, Vec<usize>)>, CompileError> {
+    let mut decoded = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let definition = code::lookup(bytes[i])
+            .ok_or_else(|| CompileError::new(format!("undefined opcode {} at {}", bytes[i], i)))?;
+        let op = Opcode::from(bytes[i]);
+        let (operands, n) = code::read_operands(&definition, &bytes[i + 1..]).map_err(|_| {
+            CompileError::new(format!("{} at {} is missing its operands", op.name(), i))
+        })?;
+        decoded.push((i, op, operands));
+        i += n + 1;
+    }
+    Ok(decoded)
+}
+
+// checks the parts of each instruction that only depend on its own function:
+// builtins exist, and jumps land on an instruction or just past the last one
+fn read_instructions(input: &mut Cursor<&[u8]>) -> Result<Instructions, CompileError> {
+    let bytes = read_bytes(input)?;
+    let decoded = decode(&bytes)?;
+    for (i, op, operands) in &decoded {
+        match op {
+            Opcode::GetBuiltin if operands[0] >= Builtin::variants().len() => {
+                return Err(CompileError::new(format!(
+                    "unknown builtin {} at {}",
+                    operands[0], i
+                )));
+            }
+            Opcode::Jump | Opcode::JumpNotTruthy | Opcode::JumpNotNull => {
+                let target = operands[0];
+                if target != bytes.len() && decoded.binary_search_by_key(&target, |d| d.0).is_err()
+                {
+                    return Err(CompileError::new(format!(
+                        "{} at {} jumps to {}, which is not an instruction",
+                        op.name(),
+                        i,
+                        target
+                    )));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(Instructions::from(bytes))
+}
+
+// checks constant operands once the whole pool is known, since functions in
+// the pool refer to each other
+fn check_constant_operands(
+    instructions: &Instructions,
+    constants: &[Rc<Object>],
+) -> Result<(), CompileError> {
+    for (i, op, operands) in decode(instructions.as_slice())? {
+        if !matches!(
+            op,
+            Opcode::Constant | Opcode::ConstantWide | Opcode::Closure
+        ) {
+            continue;
+        }
+        let constant = constants.get(operands[0]).ok_or_else(|| {
+            CompileError::new(format!(
+                "{} at {} refers to constant {}, but there are {}",
+                op.name(),
+                i,
+                operands[0],
+                constants.len()
+            ))
+        })?;
+        if op == Opcode::Closure && !matches!(**constant, Object::CompiledFunction(_)) {
+            return Err(CompileError::new(format!(
+                "{} at {} needs a function constant, got {}",
+                op.name(),
+                i,
+                constant.type_tag()
+            )));
+        }
+    }
+    Ok(())
+}
+
+// `constants` are the ones read so far, which include every function this
+// one creates, since the compiler adds those first
+fn read_constant(
+    input: &mut Cursor<&[u8]>,
+    constants: &[Rc<Object>],
+) -> Result<Object, CompileError> {
+    match input.read_u8().map_err(truncated)? {
+        INTEGER => Ok(Object::Integer(
+            input.read_i64::<BigEndian>().map_err(truncated)?,
+        )),
+        FLOAT => Ok(Object::Float(
+            input.read_f64::<BigEndian>().map_err(truncated)?,
+        )),
+        STRING => String::from_utf8(read_bytes(input)?)
+            .map(Object::String)
+            .map_err(|_| CompileError::new("string constant is not valid UTF-8".to_string())),
+        FUNCTION => {
+            let num_parameters = input.read_u32::<BigEndian>().map_err(truncated)? as usize;
+            let num_locals = input.read_u32::<BigEndian>().map_err(truncated)? as usize;
+            // parameters are the first locals, and every local needs a stack slot
+            if num_parameters > num_locals || num_locals > STACK_SIZE {
+                return Err(CompileError::new(format!(
+                    "function with {} parameters cannot have {} locals",
+                    num_parameters, num_locals
+                )));
+            }
+            let instructions = read_instructions(input)?;
+            Ok(Object::CompiledFunction(Rc::new(
+                CompiledFunction::with_constants(
+                    instructions,
+                    num_parameters,
+                    num_locals,
+                    constants,
+                ),
+            )))
+        }
+        tag => Err(CompileError::new(format!("unknown constant tag {}", tag))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::code::make;
+    use crate::compiler::Compiler;
+    use crate::lexer::Lexer;
+    use crate::parser::{ast::Node, Parser};
+    use crate::vm::VM;
+
+    fn compile(input: &str) -> Bytecode {
+        let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+        let mut compiler = Compiler::new();
+        compiler.compile(Node::Program(program)).unwrap();
+        compiler.bytecode()
+    }
+
+    fn run(bytecode: Bytecode) -> Rc<Object> {
+        let mut vm = VM::new(bytecode);
+        vm.run().unwrap();
+        vm.last_popped_stack_elem()
+    }
+
+    #[test]
+    fn it_round_trips_closures_through_bytes() {
+        let tests = vec![
+            "let add = fn(a) { fn(b) { fn(c) { a + b + c } } }; add(1)(2)(3)",
+            r#"let greet = fn(name) { fn(greeting) { greeting + ", " + name } }; greet("monkey")("hello")"#,
+            "let counter = fn(n) { fn() { n * 1.5 } }; map([counter(1), counter(2)], fn(f) { f() })",
+            "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(15)",
+            r#"let h = {"a": fn(x) { x * 2 }}; h["a"](21)"#,
+            "let i = 0; while (i < 5) { if (i == 2) { i = i + 2 } else { i = i + 1 } }; i",
+            "let f = fn(x) { x ?? 7 }; f(null) + f(1)",
+        ];
+        for input in tests {
+            let bytes = compile(input).serialize().unwrap();
+            assert_eq!(&bytes[..4], MAGIC);
+            let loaded = Bytecode::deserialize(&bytes).unwrap();
+            assert_eq!(loaded.serialize().unwrap(), bytes, "{}", input);
+            assert_eq!(run(loaded), run(compile(input)), "{}", input);
+        }
+    }
+
+    #[test]
+    fn it_rejects_malformed_bytecode() {
+        let bytes = compile("let f = fn(x) { x + 1 }; f(1)")
+            .serialize()
+            .unwrap();
+
+        let mut other_version = bytes.clone();
+        other_version[4..6].copy_from_slice(&(BYTECODE_VERSION + 1).to_be_bytes());
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+
+        let mut unknown_opcode = bytes.clone();
+        unknown_opcode[10] = 255;
+
+        let tests = vec![
+            (b"MONK".to_vec(), "not a monkey bytecode file"),
+            (other_version, "unsupported bytecode version 2, expected 1"),
+            (
+                bytes[..bytes.len() - 1].to_vec(),
+                "bytecode ends unexpectedly",
+            ),
+            (trailing, "trailing bytes after bytecode"),
+            (unknown_opcode, "undefined opcode 255 at 0"),
+        ];
+        for (input, expected) in tests {
+            let err = Bytecode::deserialize(&input).err().unwrap();
+            assert_eq!(err.msg, expected);
+        }
+
+        // an opcode cut off from its operands
+        let bytecode = Bytecode {
+            instructions: Instructions::from(make(Opcode::Constant, vec![0])[..2].to_vec()),
+            constants: Rc::new(RefCell::new(vec![])),
+        };
+        let err = Bytecode::deserialize(&bytecode.serialize().unwrap())
+            .err()
+            .unwrap();
+        assert_eq!(err.msg, "OpConstant at 0 is missing its operands");
+
+        // well formed, but would fail once the vm reached it
+        let function = |num_parameters, num_locals| {
+            Object::CompiledFunction(Rc::new(CompiledFunction::new(
+                Instructions::from(make(Opcode::Return, vec![])),
+                num_parameters,
+                num_locals,
+            )))
+        };
+        let tests = vec![
+            (
+                make(Opcode::GetBuiltin, vec![200]),
+                vec![],
+                "unknown builtin 200 at 0",
+            ),
+            (
+                vec![],
+                vec![function(0, STACK_SIZE + 1)],
+                "function with 0 parameters cannot have 2049 locals",
+            ),
+            (
+                vec![],
+                vec![function(2, 1)],
+                "function with 2 parameters cannot have 1 locals",
+            ),
+            // into the operand of the `OpConstant` at 0
+            (
+                [make(Opcode::Constant, vec![0]), make(Opcode::Jump, vec![1])].concat(),
+                vec![Object::Integer(1)],
+                "OpJump at 3 jumps to 1, which is not an instruction",
+            ),
+            (
+                make(Opcode::JumpNotTruthy, vec![4]),
+                vec![],
+                "OpJumpNotTruthy at 0 jumps to 4, which is not an instruction",
+            ),
+            (
+                make(Opcode::Constant, vec![1]),
+                vec![Object::Integer(1)],
+                "OpConstant at 0 refers to constant 1, but there are 1",
+            ),
+            (
+                make(Opcode::ConstantWide, vec![0]),
+                vec![],
+                "OpConstantWide at 0 refers to constant 0, but there are 0",
+            ),
+            (
+                make(Opcode::Closure, vec![0, 0]),
+                vec![Object::Integer(1)],
+                "OpClosure at 0 needs a function constant, got INTEGER",
+            ),
+            // checked inside functions as well as at the top level
+            (
+                vec![],
+                vec![Object::CompiledFunction(Rc::new(CompiledFunction::new(
+                    Instructions::from(make(Opcode::Closure, vec![1, 0])),
+                    0,
+                    0,
+                )))],
+                "OpClosure at 0 refers to constant 1, but there are 1",
+            ),
+        ];
+        for (instructions, constants, expected) in tests {
+            let bytecode = Bytecode {
+                instructions: Instructions::from(instructions),
+                constants: Rc::new(RefCell::new(constants.into_iter().map(Rc::new).collect())),
+            };
+            let err = Bytecode::deserialize(&bytecode.serialize().unwrap())
+                .err()
+                .unwrap();
+            assert_eq!(err.msg, expected);
+        }
+
+        let err = compile("quote(1)").serialize().unwrap_err();
+        assert_eq!(err.msg, "cannot serialize QUOTE constants");
+    }
+
+    // A reminder rather than a check of the format: changing the instruction
+    // set breaks files written by older builds, which the version has to say.
+    #[test]
+    fn it_bumps_the_version_with_the_instruction_set() {
+        assert_eq!(
+            (BYTECODE_VERSION, Opcode::all().len()),
+            (1, 37),
+            "the opcodes changed; bump BYTECODE_VERSION and this test"
+        );
+    }
+}
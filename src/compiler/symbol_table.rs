@@ -55,6 +55,14 @@ impl SymbolTable {
             None => Scope::Global,
         };
 
+        // rebinding a name reuses its slot, so a `let` in a loop body updates
+        // the value the loop condition reads
+        if let Some(existing) = self.symbols.get(&name) {
+            if existing.scope == scope {
+                return Rc::clone(existing);
+            }
+        }
+
         let symbol = Rc::new(Symbol {
             name: name.clone(),
             scope,
@@ -113,12 +121,12 @@ impl SymbolTable {
                             }
 
                             let free = self.define_free(symbol);
-                            return Some(free);
+                            Some(free)
                         }
-                        None => return None,
+                        None => None,
                     }
                 }
-                None => return None,
+                None => None,
             },
         }
     }
@@ -126,13 +134,12 @@ impl SymbolTable {
 
 #[cfg(test)]
 mod tests {
-    use std::iter::once;
 
     use super::*;
 
     #[test]
     fn it_defines_symbols() {
-        let mut global = SymbolTable::new();
+        let global = SymbolTable::new();
         let expected = vec![
             Symbol {
                 name: "a".to_string(),
@@ -2,6 +2,7 @@ pub mod error;
 
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::mem::discriminant;
 use std::rc::Rc;
 
 use self::error::EvaluatorError;
@@ -38,25 +39,24 @@ fn evaluate_statements(
 
 fn evaluate_statement(statement: &Statement, env: Env) -> Result<Rc<Object>, EvaluatorError> {
     match statement {
+        // A `let` name is only bound once its value is evaluated. Function bodies
+        // look names up when called, so a function bound by `let` can call itself,
+        // matching the vm's `OpCurrentClosure`.
         Statement::Let(name, expression) => {
             let value = evaluate_expression(expression, Rc::clone(&env))?;
-            let object = Rc::clone(&value);
-            env.borrow_mut().set(name.to_string(), object);
-            return Ok(value);
+            // `let x = if (c) { return 1; } ...` returns from the enclosing function
+            if let Object::ReturnValue(_) = *value {
+                return Ok(value);
+            }
+            env.borrow_mut().set(name.to_string(), value);
+            // a binding has no value of its own, as in the vm
+            Ok(Rc::new(Object::Null))
         }
         Statement::Return(expression) => {
             let value = evaluate_expression(expression, Rc::clone(&env))?;
-            return Ok(Rc::new(Object::ReturnValue(value)));
+            Ok(Rc::new(Object::ReturnValue(value)))
         }
-        Statement::Expression(expression) => evaluate_expression(expression, env),
-    }
-}
-
-fn is_truthy(object: &Object) -> bool {
-    match object {
-        Object::Null => false,
-        Object::Boolean(false) => false,
-        _ => true,
+        Statement::Expression(expression, _) => evaluate_expression(expression, env),
     }
 }
 
@@ -68,6 +68,45 @@ fn evaluate_expression(expression: &Expression, env: Env) -> Result<Rc<Object>,
             let right = evaluate_expression(expression, env)?;
             evaluate_prefix_expression(operator, &right)
         }
+        Expression::Assign(name, value) => {
+            let value = evaluate_expression(value, Rc::clone(&env))?;
+            if !env.borrow_mut().assign(name, Rc::clone(&value)) {
+                return Err(EvaluatorError::new(format!(
+                    "cannot assign to undefined variable {}",
+                    name
+                )));
+            }
+            Ok(value)
+        }
+        Expression::IndexAssign(target, value) => {
+            let (name, index) = target
+                .index_target()
+                .ok_or_else(|| EvaluatorError::new(format!("cannot assign to {}", target)))?;
+            let container = env.borrow().get(name).ok_or_else(|| {
+                EvaluatorError::new(format!("cannot assign to undefined variable {}", name))
+            })?;
+            let index = evaluate_expression(index, Rc::clone(&env))?;
+            let value = evaluate_expression(value, Rc::clone(&env))?;
+            let updated = container.with_index(&index, Rc::clone(&value))?;
+            env.borrow_mut().assign(name, updated);
+            Ok(value)
+        }
+        Expression::Infix(left, operator @ (Token::And | Token::Or), right) => {
+            // the right side only runs when the left one leaves the result open
+            let left = evaluate_expression(left, Rc::clone(&env))?.is_truthy();
+            let result = match operator {
+                Token::And => left && evaluate_expression(right, Rc::clone(&env))?.is_truthy(),
+                _ => left || evaluate_expression(right, Rc::clone(&env))?.is_truthy(),
+            };
+            Ok(Rc::new(Object::Boolean(result)))
+        }
+        Expression::Infix(left, Token::Coalesce, right) => {
+            let left = evaluate_expression(left, Rc::clone(&env))?;
+            match *left {
+                Object::Null => evaluate_expression(right, env),
+                _ => Ok(left),
+            }
+        }
         Expression::Infix(left, operator, right) => {
             let left = evaluate_expression(left, Rc::clone(&env))?;
             let right = evaluate_expression(right, Rc::clone(&env))?;
@@ -76,14 +115,23 @@ fn evaluate_expression(expression: &Expression, env: Env) -> Result<Rc<Object>,
 
         Expression::If(condition, consequence, alternative) => {
             let condition = evaluate_expression(condition, Rc::clone(&env))?;
-            if is_truthy(&condition) {
-                evaluate_block_statement(&consequence, Rc::clone(&env))
+            if condition.is_truthy() {
+                evaluate_block_statement(consequence, Rc::clone(&env))
             } else if let Some(alternative) = alternative {
-                evaluate_block_statement(&alternative, Rc::clone(&env))
+                evaluate_block_statement(alternative, Rc::clone(&env))
             } else {
                 Ok(Rc::new(Object::Null))
             }
         }
+        Expression::While(condition, body) => {
+            while evaluate_expression(condition, Rc::clone(&env))?.is_truthy() {
+                let result = evaluate_block_statement(body, Rc::clone(&env))?;
+                if let Object::ReturnValue(_) = *result {
+                    return Ok(result);
+                }
+            }
+            Ok(Rc::new(Object::Null))
+        }
         Expression::Function(_, parameters, body) => Ok(Rc::new(Object::Function(
             parameters.clone(),
             body.clone(),
@@ -116,27 +164,22 @@ fn quote(node: Node, env: Env) -> Result<Node, EvaluatorError> {
 }
 
 fn evaluate_unquote_call(node: Node, env: Env) -> Result<Node, EvaluatorError> {
-    let modifier = |node: Node| -> Node {
+    let modifier = |node: Node| -> Result<Node, EvaluatorError> {
         match &node {
-            Node::Expression(expression) => match expression {
-                Expression::FunctionCall(function, arguments) => {
-                    if **function != Expression::Identifier("unquote".to_string()) {
-                        return node;
-                    }
-                    if arguments.len() != 1 {
-                        return node;
-                    }
-                    convert_object_to_ast_node(
-                        &evaluate(Node::Expression(arguments[0].clone()), Rc::clone(&env)).unwrap(),
-                    )
+            Node::Expression(Expression::FunctionCall(function, arguments)) => {
+                if **function != Expression::Identifier("unquote".to_string()) {
+                    return Ok(node);
                 }
-                _ => node,
-            },
-
-            _ => node,
+                if arguments.len() != 1 {
+                    return Ok(node);
+                }
+                let unquoted = evaluate_expression(&arguments[0], Rc::clone(&env))?;
+                Ok(convert_object_to_ast_node(&unquoted))
+            }
+            _ => Ok(node),
         }
     };
-    Ok(modify(node, modifier))
+    ast::try_modify(node, modifier)
 }
 
 fn convert_object_to_ast_node(object: &Object) -> Node {
@@ -144,6 +187,7 @@ fn convert_object_to_ast_node(object: &Object) -> Node {
         Object::Integer(i) => Node::Expression(Expression::Literal(Literal::Integer(i))),
         Object::Boolean(b) => Node::Expression(Expression::Literal(Literal::Boolean(b))),
         Object::String(ref s) => Node::Expression(Expression::Literal(Literal::String(s.clone()))),
+        Object::Null => Node::Expression(Expression::Literal(Literal::Null)),
         Object::Quote(ref q) => q.clone(),
         _ => Node::Expression(Expression::Literal(Literal::Integer(0))),
     }
@@ -167,7 +211,7 @@ fn apply_function(
 ) -> Result<Rc<Object>, EvaluatorError> {
     match &*function {
         Object::Function(parameters, body, env) => {
-            let mut env = Environment::new_enclosed_environment(Rc::clone(&env));
+            let mut env = Environment::new_call_environment(Rc::clone(env), parameters.len());
             if parameters.len() != args.len() {
                 return Err(EvaluatorError::new(format!(
                     "wrong number of arguments: got={}, want={}",
@@ -178,14 +222,25 @@ fn apply_function(
             for (i, parameter) in parameters.iter().enumerate() {
                 env.set(parameter.to_string(), Rc::clone(&args[i]));
             }
-            let executed = evaluate_block_statement(&body, Rc::new(RefCell::new(env)))?;
+            let executed = evaluate_block_statement(body, Rc::new(RefCell::new(env)))?;
             match &*executed {
                 Object::ReturnValue(value) => Ok(Rc::clone(value)),
                 _ => Ok(executed),
             }
         }
-        Object::Builtin(builtin) => builtin.apply(args).map_err(EvaluatorError::from),
-        _ => Err(EvaluatorError::new(format!("not a function: {}", function))),
+        Object::Builtin(builtin) => {
+            builtin.apply(args, &mut |f, a| apply_function(Rc::clone(f), &a.to_vec()))
+        }
+        Object::HostFunction(host) => host.apply(args).map_err(EvaluatorError::from),
+        Object::Memoized(memo) => {
+            if let Some(result) = memo.lookup(args)? {
+                return Ok(result);
+            }
+            let result = apply_function(Rc::clone(memo.function()), args)?;
+            memo.store(args, Rc::clone(&result));
+            Ok(result)
+        }
+        _ => Err(EvaluatorError::new(function.not_callable_message())),
     }
 }
 
@@ -209,27 +264,34 @@ fn evaluate_block_statement(
     let mut result = Rc::new(Object::Null);
     for statement in block {
         let intermediate_value = evaluate_statement(statement, Rc::clone(&env))?;
-        match *result {
-            Object::ReturnValue(_) => return Ok(result),
+        match *intermediate_value {
+            Object::ReturnValue(_) => return Ok(intermediate_value),
             _ => result = intermediate_value,
         }
     }
     Ok(result)
 }
 
+// hash keys are never closures, so nothing in them mutates
+#[allow(clippy::mutable_key_type)]
 fn evaluate_literal(literal: &Literal, env: Env) -> Result<Rc<Object>, EvaluatorError> {
     match literal {
         Literal::Integer(integer) => Ok(Rc::new(Object::Integer(*integer))),
+        Literal::Float(float) => Ok(Rc::new(Object::Float(*float))),
         Literal::Boolean(boolean) => Ok(Rc::new(Object::Boolean(*boolean))),
-        Literal::String(string) => Ok(Rc::new(Object::String(string.clone()))),
+        Literal::Null => Ok(Rc::new(Object::Null)),
+        Literal::String(string) => Ok(Object::new_string(string.clone())),
         Literal::Array(elements) => {
             let elements = evaluate_expressions(elements, Rc::clone(&env))?;
-            Ok(Rc::new(Object::Array(elements)))
+            Ok(Object::new_array(elements))
         }
         Literal::Hash(pairs) => {
             let mut hash = HashMap::new();
             for (key, value) in pairs {
                 let key = evaluate_expression(key, Rc::clone(&env))?;
+                if !key.is_hashable() {
+                    return Err(EvaluatorError::new(key.unusable_key_message()));
+                }
                 let value = evaluate_expression(value, Rc::clone(&env))?;
                 hash.insert(key, value);
             }
@@ -258,30 +320,51 @@ fn evaluate_infix_expression(
         (Object::Integer(left), Object::Integer(right)) => {
             evaluate_integer_infix_operator(operator, *left, *right)
         }
+        (Object::Float(_), Object::Integer(_) | Object::Float(_))
+        | (Object::Integer(_), Object::Float(_)) => evaluate_float_infix_operator(
+            operator,
+            left.to_float().unwrap_or_default(),
+            right.to_float().unwrap_or_default(),
+        ),
         (Object::Boolean(left), Object::Boolean(right)) => {
             evaluate_boolean_infix_operator(operator, *left, *right)
         }
         (Object::String(left), Object::String(right)) => {
             evaluate_string_infix_operator(operator, left, right)
         }
-        _ => Err(EvaluatorError::new(format!(
-            "type mismatch between operands: {} {} {}",
-            left, operator, right
-        ))),
+        (Object::Array(_) | Object::Hash(_), _) if *operator == Token::Dash => left
+            .difference(right)
+            .ok_or_else(|| type_mismatch(operator, left, right)),
+        _ if discriminant(left) != discriminant(right) => match operator {
+            Token::Eq => Ok(Rc::new(Object::Boolean(false))),
+            Token::NotEq => Ok(Rc::new(Object::Boolean(true))),
+            Token::Lt | Token::Gt | Token::LtEq | Token::GtEq => {
+                Err(EvaluatorError::new(left.incomparable_message(right)))
+            }
+            _ => Err(type_mismatch(operator, left, right)),
+        },
+        _ => Err(type_mismatch(operator, left, right)),
     }
 }
 
+fn type_mismatch(operator: &Token, left: &Object, right: &Object) -> EvaluatorError {
+    EvaluatorError::new(format!(
+        "type mismatch between operands: {} {} {}",
+        left, operator, right
+    ))
+}
+
 fn evaluate_bang_prefix_operator(expression: &Object) -> Result<Rc<Object>, EvaluatorError> {
-    match expression {
-        Object::Boolean(b) => Ok(Rc::new(Object::Boolean(!b))),
-        Object::Null => Ok(Rc::new(Object::Boolean(true))),
-        _ => Ok(Rc::new(Object::Boolean(false))),
-    }
+    Ok(Rc::new(Object::Boolean(!expression.is_truthy())))
 }
 
 fn evaluate_dash_prefix_operator(expression: &Object) -> Result<Rc<Object>, EvaluatorError> {
     match expression {
-        Object::Integer(i) => Ok(Rc::new(Object::Integer(-i))),
+        Object::Integer(i) => match i.checked_neg() {
+            Some(negated) => Ok(Rc::new(Object::Integer(negated))),
+            None => Err(EvaluatorError::new("integer overflow".to_string())),
+        },
+        Object::Float(x) => Ok(Rc::new(Object::Float(-x))),
         _ => Err(EvaluatorError::new(format!(
             "unknown operator: -{}",
             expression
@@ -298,7 +381,7 @@ fn evaluate_string_infix_operator(
         Token::Plus => {
             let mut string = left.clone();
             string.push_str(right);
-            Ok(Rc::new(Object::String(string)))
+            Ok(Object::new_string(string))
         }
         Token::Eq => Ok(Rc::new(Object::Boolean(left == right))),
         Token::NotEq => Ok(Rc::new(Object::Boolean(left != right))),
@@ -315,9 +398,9 @@ fn evaluate_boolean_infix_operator(
     left: bool,
     right: bool,
 ) -> Result<Rc<Object>, EvaluatorError> {
-    let result = match operator {
-        &Token::Eq => Object::Boolean(left == right),
-        &Token::NotEq => Object::Boolean(left != right),
+    let result = match *operator {
+        Token::Eq => Object::Boolean(left == right),
+        Token::NotEq => Object::Boolean(left != right),
         _ => {
             return Err(EvaluatorError::new(format!(
                 "unknown operator: {} {} {}",
@@ -329,25 +412,62 @@ fn evaluate_boolean_infix_operator(
     Ok(Rc::new(result))
 }
 
+fn evaluate_float_infix_operator(
+    operator: &Token,
+    left: f64,
+    right: f64,
+) -> Result<Rc<Object>, EvaluatorError> {
+    let result = match *operator {
+        Token::Plus => Object::Float(left + right),
+        Token::Dash => Object::Float(left - right),
+        Token::Asterisk => Object::Float(left * right),
+        Token::Slash => Object::Float(left / right),
+        Token::Percent => Object::Float(left % right),
+        Token::Lt => Object::Boolean(left < right),
+        Token::Gt => Object::Boolean(left > right),
+        Token::LtEq => Object::Boolean(left <= right),
+        Token::GtEq => Object::Boolean(left >= right),
+        Token::Eq => Object::Boolean(left == right),
+        Token::NotEq => Object::Boolean(left != right),
+        _ => {
+            return Err(EvaluatorError::new(format!(
+                "unknown operator: {:?} {} {:?}",
+                left, operator, right
+            )))
+        }
+    };
+    Ok(Rc::new(result))
+}
+
 fn evaluate_integer_infix_operator(
     operator: &Token,
     left: i64,
     right: i64,
 ) -> Result<Rc<Object>, EvaluatorError> {
-    let result = match operator {
-        &Token::Plus => Object::Integer(left + right),
-        &Token::Dash => Object::Integer(left - right),
-        &Token::Asterisk => Object::Integer(left * right),
-        &Token::Slash => {
+    let overflow = || EvaluatorError::new("integer overflow".to_string());
+    let result = match *operator {
+        Token::Plus => Object::Integer(left.checked_add(right).ok_or_else(overflow)?),
+        Token::Dash => Object::Integer(left.checked_sub(right).ok_or_else(overflow)?),
+        Token::Asterisk => Object::Integer(left.checked_mul(right).ok_or_else(overflow)?),
+        Token::Slash => {
+            if right == 0 {
+                return Err(EvaluatorError::new("Division by zero".to_string()));
+            }
+            Object::Integer(left.checked_div(right).ok_or_else(overflow)?)
+        }
+        // like `/`, the remainder truncates toward zero and takes the sign of `left`
+        Token::Percent => {
             if right == 0 {
                 return Err(EvaluatorError::new("Division by zero".to_string()));
             }
-            Object::Integer(left / right)
+            Object::Integer(left.checked_rem(right).ok_or_else(overflow)?)
         }
-        &Token::Lt => Object::Boolean(left < right),
-        &Token::Gt => Object::Boolean(left > right),
-        &Token::Eq => Object::Boolean(left == right),
-        &Token::NotEq => Object::Boolean(left != right),
+        Token::Lt => Object::Boolean(left < right),
+        Token::Gt => Object::Boolean(left > right),
+        Token::LtEq => Object::Boolean(left <= right),
+        Token::GtEq => Object::Boolean(left >= right),
+        Token::Eq => Object::Boolean(left == right),
+        Token::NotEq => Object::Boolean(left != right),
         _ => {
             return Err(EvaluatorError::new(format!(
                 "unknown operator: {} {} {}",
@@ -368,6 +488,9 @@ fn evaluate_index_expression(left: &Object, index: &Object) -> Result<Rc<Object>
             }
             Ok(Rc::clone(&elements[i]))
         }
+        (Object::Hash(_), index) if !index.is_hashable() => {
+            Err(EvaluatorError::new(index.unusable_key_message()))
+        }
         (Object::Hash(hash), index) => {
             let key = index.clone();
             match hash.get(&key) {
@@ -383,82 +506,66 @@ fn evaluate_index_expression(left: &Object, index: &Object) -> Result<Rc<Object>
 }
 
 pub fn define_macros(program: &mut Vec<Statement>, env: Env) {
-    let mut definitions = Vec::new();
-    for (i, statement) in program.iter().enumerate() {
-        if is_macro_definition(statement) {
-            add_macro(statement, Rc::clone(&env));
-            definitions.push(i);
+    // move the definitions out of the program rather than cloning them
+    let statements = std::mem::take(program);
+    for statement in statements {
+        match statement {
+            Statement::Let(name, Expression::Macro(parameters, body)) => {
+                let macro_object = Object::Macro(parameters, body, Rc::clone(&env));
+                env.borrow_mut().set(name, Rc::new(macro_object));
+            }
+            statement => program.push(statement),
         }
     }
-    for &definition_index in definitions.iter().rev() {
-        program.remove(definition_index);
-    }
 }
 
-fn add_macro(statement: &Statement, env: Env) {
-    match statement {
-        Statement::Let(name, expression) => match expression {
-            Expression::Macro(parameters, body) => {
-                let macro_object = Object::Macro(parameters.clone(), body.clone(), Rc::clone(&env));
-                env.borrow_mut()
-                    .set(name.to_string(), Rc::new(macro_object));
-            }
-            _ => (),
-        },
-        _ => (),
-    }
+pub fn expand_macros(program: Node, env: Env) -> Result<Node, EvaluatorError> {
+    ast::try_modify(program, |node: Node| {
+        Ok(expand_macro_call(&node, Rc::clone(&env))?.unwrap_or(node))
+    })
 }
 
-fn is_macro_definition(statement: &Statement) -> bool {
-    match statement {
-        Statement::Let(_, expression) => match expression {
-            Expression::Macro(_, _) => true,
-            _ => false,
+fn expand_macro_call(node: &Node, env: Env) -> Result<Option<Node>, EvaluatorError> {
+    let (function, arguments) = match node {
+        Node::Expression(Expression::FunctionCall(function, arguments)) => (function, arguments),
+        _ => return Ok(None),
+    };
+    let macro_object = match &**function {
+        // Use a simple borrow here
+        Expression::Identifier(identifier) => env.borrow().get(identifier),
+        _ => None,
+    };
+    let macro_object = match macro_object {
+        Some(macro_object) => macro_object,
+        None => return Ok(None),
+    };
+    let body = match &*macro_object {
+        Object::Macro(_, body, _) => body,
+        _ => return Ok(None),
+    };
+
+    let args: Vec<Object> = arguments
+        .iter()
+        .map(|a| Object::Quote(Node::Expression(a.clone())))
+        .collect();
+    let extended_env = extend_macro_env(Rc::clone(&macro_object), args)?;
+    let evaluated = evaluate_statements(body, extended_env)?;
+    // the quote is normally only referenced here, so it can be moved out
+    match Rc::try_unwrap(evaluated) {
+        Ok(Object::Quote(quote)) => Ok(Some(quote)),
+        Ok(evaluated) => Err(unexpected_macro_result(&evaluated)),
+        Err(evaluated) => match &*evaluated {
+            Object::Quote(quote) => Ok(Some(quote.clone())),
+            _ => Err(unexpected_macro_result(&evaluated)),
         },
-        _ => false,
     }
 }
 
-pub fn expand_macros(program: Node, env: Env) -> Result<Node, EvaluatorError> {
-    Ok(ast::modify(program, |node: Node| -> Node {
-        match &node {
-            Node::Expression(expression) => match expression {
-                Expression::FunctionCall(function, arguments) => match &**function {
-                    Expression::Identifier(identifier) => {
-                        // Use a simple borrow here
-                        let macro_object = env.borrow().get(&identifier);
-                        match macro_object {
-                            Some(macro_obj) => match &*macro_obj {
-                                Object::Macro(_, body, _) => {
-                                    let args: Vec<Object> = arguments
-                                        .iter()
-                                        .map(|a| Object::Quote(Node::Expression(a.clone())))
-                                        .collect();
-                                    match extend_macro_env(Rc::clone(&macro_obj), args) {
-                                        Ok(extended_env) => {
-                                            match evaluate(Node::Program(body.clone()), extended_env) {
-                                                Ok(evaluated) => match &*evaluated {
-                                                    Object::Quote(quote) => return quote.clone(),
-                                                    _ => panic!("unexpected object type: {:?} - we only support returning AST-nodes from macros", evaluated),
-                                                },
-                                                Err(_) => return node,
-                                            }
-                                        }
-                                        Err(_) => return node,
-                                    }
-                                }
-                                _ => return node,
-                            },
-                            None => return node,
-                        }
-                    }
-                    _ => return node,
-                },
-                _ => return node,
-            },
-            _ => return node,
-        }
-    }))
+fn unexpected_macro_result(evaluated: &Object) -> EvaluatorError {
+    EvaluatorError::new(format!(
+        "unexpected object type: {} - we only support returning AST-nodes from macros",
+        evaluated
+    ))
 }
 
 fn extend_macro_env(
@@ -467,30 +574,31 @@ fn extend_macro_env(
 ) -> Result<Env, EvaluatorError> {
     if let Object::Macro(macro_args, _, env) = &*macro_object {
         if arguments.iter().all(|arg| matches!(arg, Object::Quote(_))) {
-            let mut extended_env = Environment::new_enclosed_environment(Rc::clone(env));
-            for (i, macro_arg) in macro_args.iter().enumerate() {
-                let arg = arguments[i].clone();
-
+            let mut extended_env =
+                Environment::new_call_environment(Rc::clone(env), macro_args.len());
+            for (macro_arg, arg) in macro_args.iter().zip(arguments) {
                 extended_env.set(macro_arg.to_string(), Rc::new(arg));
             }
-            return Ok(Rc::new(RefCell::new(extended_env)));
+            Ok(Rc::new(RefCell::new(extended_env)))
         } else {
-            return Err(EvaluatorError::new(
+            Err(EvaluatorError::new(
                 "arguments to macro must be quoted".to_string(),
-            ));
+            ))
         }
     } else {
-        return Err(EvaluatorError::new(
+        Err(EvaluatorError::new(
             "only macros can be extended".to_string(),
-        ));
+        ))
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::mutable_key_type)]
 mod test {
 
     use super::*;
     use crate::lexer::Lexer;
+    use crate::object::error::ObjectError;
     use crate::parser::Parser;
 
     fn test_eval(input: String) -> Result<Rc<Object>, EvaluatorError> {
@@ -517,9 +625,10 @@ mod test {
         match (outcome, expected) {
             (Ok(object), Ok(expected_object)) => match (&**object, &**expected_object) {
                 (Object::Integer(i), Object::Integer(j)) => assert_eq!(i, j),
+                (Object::Float(x), Object::Float(y)) => assert_eq!(x, y),
                 (Object::Boolean(b), Object::Boolean(c)) => assert_eq!(b, c),
                 (Object::String(s), Object::String(t)) => assert_eq!(s, t),
-                (Object::Null, Object::Null) => assert!(true),
+                (Object::Null, Object::Null) => {}
                 (Object::ReturnValue(v1), Object::ReturnValue(v2)) => {
                     test_object_is_expected(&Ok(v1.clone()), &Ok(v2.clone()));
                 }
@@ -535,7 +644,7 @@ mod test {
                         test_object_is_expected(&Ok(v.clone()), &Ok(b[k].clone()));
                     }
                 }
-                (Object::Quote(a), Object::Quote(b)) => match (&*a, &*b) {
+                (Object::Quote(a), Object::Quote(b)) => match (a, b) {
                     (Node::Expression(a), Node::Expression(b)) => {
                         assert_eq!(a, b);
                     }
@@ -567,9 +676,131 @@ mod test {
         }
     }
 
+    #[test]
+    fn it_short_circuits_logical_operators() {
+        let tests = vec![
+            ("true && true", true),
+            ("true && false", false),
+            ("false || true", true),
+            ("false || false", false),
+            ("1 && \"a\"", true),
+            ("if (false) { 1 } || false", false),
+            ("false && (1 / 0 == 0)", false),
+            ("true || (1 / 0 == 0)", true),
+            ("let f = fn() { 1 / 0 }; 1 > 2 && f()", false),
+        ];
+
+        for (input, expected) in tests {
+            let evaluated = test_eval(input.to_string());
+            test_object_is_expected(&evaluated, &Ok(Rc::new(Object::Boolean(expected))));
+        }
+
+        for input in ["true && (1 / 0 == 0)", "false || (1 / 0 == 0)"] {
+            test_object_is_expected(
+                &test_eval(input.to_string()),
+                &Err(EvaluatorError::new("Division by zero".to_string())),
+            );
+        }
+    }
+
+    #[test]
+    fn it_evaluates_remainders() {
+        let tests = vec![
+            ("7 % 3", Object::Integer(1)),
+            ("-7 % 3", Object::Integer(-1)),
+            ("7 % -3", Object::Integer(1)),
+            ("-7 % -3", Object::Integer(-1)),
+            ("6 % 3", Object::Integer(0)),
+            ("2 + 7 % 4 * 2", Object::Integer(8)),
+            ("7.5 % 2", Object::Float(1.5)),
+            ("-7.5 % 2", Object::Float(-1.5)),
+        ];
+
+        for (input, expected) in tests {
+            test_object_is_expected(&test_eval(input.to_string()), &Ok(Rc::new(expected)));
+        }
+
+        test_object_is_expected(
+            &test_eval("5 % 0".to_string()),
+            &Err(EvaluatorError::new("Division by zero".to_string())),
+        );
+        test_object_is_expected(
+            &test_eval("let min = -9223372036854775807 - 1; min % -1".to_string()),
+            &Err(EvaluatorError::new("integer overflow".to_string())),
+        );
+    }
+
+    #[test]
+    fn it_rejects_integer_overflow() {
+        let tests = vec![
+            "9223372036854775807 * 2",
+            "4611686018427387904 * 4611686018427387904",
+            "9223372036854775807 + 1",
+            "-9223372036854775807 - 2",
+            "let min = -9223372036854775807 - 1; min / -1",
+            "let min = -9223372036854775807 - 1; -min",
+        ];
+
+        for input in tests {
+            test_object_is_expected(
+                &test_eval(input.to_string()),
+                &Err(EvaluatorError::new("integer overflow".to_string())),
+            );
+        }
+        test_object_is_expected(
+            &test_eval("9223372036854775807 - 1 + 1".to_string()),
+            &Ok(Rc::new(Object::Integer(i64::MAX))),
+        );
+    }
+
+    #[test]
+    fn it_evaluates_float_expressions() {
+        let tests = vec![
+            ("2.75", Object::Float(2.75)),
+            ("-2.5", Object::Float(-2.5)),
+            ("1.5 + 1", Object::Float(2.5)),
+            ("1 + 1.5", Object::Float(2.5)),
+            ("2.0 * 3", Object::Float(6.0)),
+            ("5 - 0.5", Object::Float(4.5)),
+            ("3.0 / 2", Object::Float(1.5)),
+            ("3 / 2", Object::Integer(1)),
+            ("1.0 / 0", Object::Float(f64::INFINITY)),
+            ("1.5 < 2", Object::Boolean(true)),
+            ("2 > 2.5", Object::Boolean(false)),
+            ("2.5 >= 2.5", Object::Boolean(true)),
+            ("3 <= 2.5", Object::Boolean(false)),
+            ("1 == 1.0", Object::Boolean(true)),
+            ("0.1 + 0.2 != 0.3", Object::Boolean(true)),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(
+                *test_eval(input.to_string()).unwrap(),
+                expected,
+                "{}",
+                input
+            );
+        }
+
+        assert_eq!(
+            test_eval("1.5 + true".to_string()).unwrap_err().to_string(),
+            "Evaluator error: type mismatch between operands: 1.5 + true"
+        );
+        assert!(!Object::Float(1.0).is_hashable());
+    }
+
     #[test]
     fn it_evaluates_boolean_expressions() {
-        let tests = vec![("true", true), ("false", false)];
+        let tests = vec![
+            ("true", true),
+            ("false", false),
+            ("1 <= 2", true),
+            ("2 <= 2", true),
+            ("3 <= 2", false),
+            ("1 >= 2", false),
+            ("2 >= 2", true),
+            ("3 >= 2", true),
+        ];
 
         for (input, expected) in tests {
             let evaluated = test_eval(input.to_string());
@@ -677,6 +908,134 @@ mod test {
         }
     }
 
+    #[test]
+    fn it_evaluates_assignments() {
+        let tests = vec![
+            ("let x = 1; x = 2; x", 2.into()),
+            ("let x = 0; x = 7", 7.into()),
+            ("let a = 1; let b = 2; a = b = 3; a + b", 6.into()),
+            ("let x = 1; let f = fn() { x = 5; }; f(); x", 5.into()),
+            ("let f = fn() { let y = 1; y = y + 1; y }; f()", 2.into()),
+            ("let f = fn(n) { n = n * 2; n }; f(4)", 8.into()),
+            ("let i = 0; while (i < 3) { i = i + 1 }; i", 3.into()),
+        ];
+
+        for (input, expected) in tests {
+            test_object_is_expected(&test_eval(input.to_string()), &Ok(Rc::new(expected)));
+        }
+
+        test_object_is_expected(
+            &test_eval("x = 1".to_string()),
+            &Err(EvaluatorError::new(
+                "cannot assign to undefined variable x".to_string(),
+            )),
+        );
+        test_object_is_expected(
+            &test_eval("let f = fn() { let y = 1; }; f(); y = 2".to_string()),
+            &Err(EvaluatorError::new(
+                "cannot assign to undefined variable y".to_string(),
+            )),
+        );
+    }
+
+    #[test]
+    fn it_evaluates_index_assignments() {
+        let tests = vec![
+            ("let a = [1, 2, 3]; a[1] = 5; a", "[1, 5, 3]"),
+            ("let a = [1, 2, 3]; a[0] = a[2] * 2", "6"),
+            (r#"let h = {"a": 1}; h["a"] = 2; h"#, "{a: 2}"),
+            (r#"let h = {"a": 1}; h["b"] = 2; h"#, "{a: 1, b: 2}"),
+            ("let a = [1]; let b = a; a[0] = 2; [a, b]", "[[2], [1]]"),
+            (
+                "let f = fn() { let a = [0, 0]; a[1] = 7; a }; f()",
+                "[0, 7]",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let result = test_eval(input.to_string()).unwrap();
+            assert_eq!(result.to_string(), expected, "{}", input);
+        }
+
+        let errors = vec![
+            (
+                "let a = [1]; a[1] = 2",
+                "index 1 out of bounds for array of length 1",
+            ),
+            (
+                "let a = [1]; a[-1] = 2",
+                "index -1 out of bounds for array of length 1",
+            ),
+            (
+                r#"let a = [1]; a["x"] = 2"#,
+                "array index must be INTEGER, got STRING",
+            ),
+            ("let h = {}; h[[1]] = 2", "unusable as hash key: ARRAY"),
+            (
+                "let s = \"ab\"; s[0] = 1",
+                "index assignment not supported: STRING",
+            ),
+            ("a[0] = 1", "cannot assign to undefined variable a"),
+        ];
+        for (input, expected) in errors {
+            let err = test_eval(input.to_string()).unwrap_err();
+            let msg = match err {
+                EvaluatorError::Native(msg) => msg,
+                EvaluatorError::Object(e) => e.msg,
+            };
+            assert_eq!(msg, expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn it_evaluates_while_loops() {
+        let tests = vec![
+            ("while (false) { 1 }", Object::Null),
+            ("let i = 0; while (i < 10) { let i = i + 1; }; i;", 10.into()),
+            (
+                "let sum = 0; let i = 0; while (i < 5) { let sum = sum + i; let i = i + 1; }; sum;",
+                10.into(),
+            ),
+            (
+                "let total = 0; let i = 0; while (i < 3) { let j = 0; while (j < 4) { let total = total + 1; let j = j + 1; }; let i = i + 1; }; total;",
+                12.into(),
+            ),
+            (
+                "let f = fn(n) { let i = 0; while (true) { if (i == n) { return i * 2; }; let i = i + 1; } }; f(5);",
+                10.into(),
+            ),
+            ("let i = 0; while (i < 5000) { let i = i + 1; }; i;", 5000.into()),
+        ];
+
+        for (input, expected) in tests {
+            let evaluated = test_eval(input.to_string());
+            test_object_is_expected(&evaluated, &Ok(Rc::new(expected)));
+        }
+    }
+
+    #[test]
+    fn it_binds_conditional_results() {
+        let tests = vec![
+            ("let x = if (1 < 2) { 10 } else { 20 }; x * 2", 20.into()),
+            ("let x = if (1 > 2) { 10 } else { 20 }; x * 2", 40.into()),
+            ("let x = if (1 > 2) { 10 }; x", Object::Null),
+            ("let x = if (true) {} else { 1 }; x", Object::Null),
+            (
+                "let f = fn(c) { let x = if (c) { return 1; } else { 2 }; x * 10 }; f(true) + f(false)",
+                21.into(),
+            ),
+            (
+                "let f = fn() { if (true) { return 1; let y = missing; } }; f()",
+                1.into(),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let evaluated = test_eval(input.to_string());
+            test_object_is_expected(&evaluated, &Ok(Rc::new(expected)));
+        }
+    }
+
     #[test]
     fn it_evaluates_return_statements() {
         let tests = vec![
@@ -753,6 +1112,7 @@ mod test {
             ("let a = 5 * 5; a;", 25.into()),
             ("let a = 5; let b = a; b;", 5.into()),
             ("let a = 5; let b = a; let c = a + b + 5; c;", 15.into()),
+            ("let naïve = 2; let 名前 = naïve * 3; 名前;", 6.into()),
         ];
         for (input, expected) in tests {
             let evaluated = test_eval(input.to_string());
@@ -760,6 +1120,42 @@ mod test {
         }
     }
 
+    #[test]
+    fn it_resolves_let_bindings_only_after_definition() {
+        let tests = vec![
+            (
+                "let f = fn(n) { if (n == 0) { 0 } else { f(n - 1) } }; f(3);",
+                Ok(0.into()),
+            ),
+            (
+                r#"
+                let outer = fn() {
+                    let inner = fn(n) { if (n == 0) { 7 } else { inner(n - 1) } };
+                    inner(3);
+                };
+                outer();
+                "#,
+                Ok(7.into()),
+            ),
+            (
+                "let x = 1; let f = fn() { let x = x + 1; x }; f();",
+                Ok(2.into()),
+            ),
+            ("x; let x = 1;", Err("identifier not found: x")),
+            ("let x = x;", Err("identifier not found: x")),
+        ];
+        for (input, expected) in tests {
+            let evaluated = test_eval(input.to_string());
+            match expected {
+                Ok(expected) => test_object_is_expected(&evaluated, &Ok(Rc::new(expected))),
+                Err(expected) => assert_eq!(
+                    evaluated.unwrap_err(),
+                    EvaluatorError::new(expected.to_string())
+                ),
+            }
+        }
+    }
+
     #[test]
     fn it_evaluates_functions() {
         let tests = vec![
@@ -786,7 +1182,7 @@ mod test {
                 r#"
                 let intSeq = fn() {
                     let i = 0;
-                    return fn() { i = i + 1; };
+                    return fn() { i + 1; };
                 };
 
                 let seq = intSeq();
@@ -812,6 +1208,20 @@ mod test {
         }
     }
 
+    #[test]
+    fn it_evaluates_method_calls_on_builtins() {
+        let tests = vec![
+            ("[1, 2].len() == 2", true.into()),
+            (r#""four".len()"#, 4.into()),
+            ("[1, 2].push(3).last()", 3.into()),
+            ("let double = fn(x) { x * 2 }; 4.double()", 8.into()),
+        ];
+        for (input, expected) in tests {
+            let evaluated = test_eval(input.to_string());
+            test_object_is_expected(&evaluated, &Ok(Rc::new(expected)));
+        }
+    }
+
     #[test]
     fn it_evaluates_builtin_len() {
         let test = vec![
@@ -872,7 +1282,7 @@ mod test {
 
     #[test]
     fn it_evaluates_builtin_rest() {
-        let tests = vec![
+        let tests = [
             ("rest([1, 2, 3])", vec![2, 3]),
             ("rest([1])", vec![]),
             ("rest([])", vec![]),
@@ -897,7 +1307,7 @@ mod test {
 
     #[test]
     fn it_evaluates_builtin_first() {
-        let tests = vec![("first([1, 2, 3])", 1), ("first([1])", 1), ("first([])", 0)];
+        let tests = [("first([1, 2, 3])", 1), ("first([1])", 1), ("first([])", 0)];
 
         test_object_is_expected(
             &test_eval(tests[0].0.to_string()),
@@ -915,7 +1325,7 @@ mod test {
 
     #[test]
     fn it_evaluates_builtin_last() {
-        let tests = vec![("last([1, 2, 3])", 3), ("last([1])", 1), ("last([])", 0)];
+        let tests = [("last([1, 2, 3])", 3), ("last([1])", 1), ("last([])", 0)];
 
         test_object_is_expected(
             &test_eval(tests[0].0.to_string()),
@@ -949,6 +1359,320 @@ mod test {
         }
     }
 
+    #[test]
+    fn it_evaluates_builtin_stack_and_queue_operations() {
+        let tests = vec![
+            ("unshift([], 1)", "[1]"),
+            ("unshift([2, 3], 1)", "[1, 2, 3]"),
+            ("pop([1, 2, 3])", "[3, [1, 2]]"),
+            ("pop([1])", "[1, []]"),
+            ("shift([1, 2, 3])", "[1, [2, 3]]"),
+            // a stack: push onto the end, pop off the end
+            (
+                "let stack = push(push(push([], 1), 2), 3);
+                 let popped = pop(stack); stack = popped[1]; let top = popped[0];
+                 popped = pop(stack); stack = popped[1];
+                 [top, popped[0], stack]",
+                "[3, 2, [1]]",
+            ),
+            // a queue: push onto the end, shift off the front
+            (
+                "let queue = push(push(push([], 1), 2), 3);
+                 let shifted = shift(queue); queue = push(shifted[1], 4); let front = shifted[0];
+                 shifted = shift(queue); queue = shifted[1];
+                 [front, shifted[0], queue]",
+                "[1, 2, [3, 4]]",
+            ),
+            (
+                "let stack = [1]; let popped = pop(stack); stack = popped[1]; pop(stack)",
+                "null",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let evaluated = test_eval(input.to_string()).unwrap();
+            assert_eq!(evaluated.to_string(), expected, "{}", input);
+        }
+
+        let tests = vec![
+            ("pop([])", Ok(Rc::new(Object::Null))),
+            ("shift([])", Ok(Rc::new(Object::Null))),
+            (
+                "pop(1)",
+                Err(EvaluatorError::Object(ObjectError::new(
+                    "argument to `pop` must be ARRAY, got 1".to_string(),
+                ))),
+            ),
+            (
+                "shift(\"a\")",
+                Err(EvaluatorError::Object(ObjectError::new(
+                    "argument to `shift` must be ARRAY, got a".to_string(),
+                ))),
+            ),
+            (
+                "unshift(1, 2)",
+                Err(EvaluatorError::Object(ObjectError::new(
+                    "argument to `unshift` must be ARRAY, got 1".to_string(),
+                ))),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            test_object_is_expected(&test_eval(input.to_string()), &expected);
+        }
+    }
+
+    #[test]
+    fn it_evaluates_builtin_doc() {
+        assert!(!Builtin::Len.signature().is_empty());
+        assert!(!Builtin::Len.doc().is_empty());
+
+        let help = format!("{}\n{}", Builtin::Len.signature(), Builtin::Len.doc());
+        let tests = vec![
+            ("doc(len)", Ok(Rc::new(Object::String(help.clone())))),
+            ("let f = len; doc(f)", Ok(Rc::new(Object::String(help)))),
+            (
+                "doc(1)",
+                Err(EvaluatorError::Object(ObjectError::new(
+                    "argument to `doc` must be BUILTIN, got 1".to_string(),
+                ))),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            test_object_is_expected(&test_eval(input.to_string()), &expected);
+        }
+    }
+
+    #[test]
+    fn it_evaluates_builtin_chars() {
+        let tests = vec![
+            (r#"chars("")"#, vec![]),
+            (r#"chars("abc")"#, vec!["a", "b", "c"]),
+            (r#"chars("héllo")"#, vec!["h", "é", "l", "l", "o"]),
+        ];
+
+        for (input, expected) in tests {
+            let evaluated = test_eval(input.to_string());
+            let expected_objects = expected
+                .into_iter()
+                .map(|c| Rc::new(Object::String(c.to_string())))
+                .collect();
+            test_object_is_expected(&evaluated, &Ok(Rc::new(Object::Array(expected_objects))));
+        }
+
+        let evaluated = test_eval(r#"len(chars("héllo"))"#.to_string());
+        test_object_is_expected(&evaluated, &Ok(Rc::new(5.into())));
+    }
+
+    #[test]
+    fn it_evaluates_builtin_map() {
+        let ints = |values: &[i64]| {
+            Ok(Rc::new(Object::Array(
+                values.iter().map(|&i| Rc::new(i.into())).collect(),
+            )))
+        };
+        let tests = vec![
+            ("map([1, 2, 3], fn(x) { x * 2 })", ints(&[2, 4, 6])),
+            ("map([], fn(x) { x * 2 })", ints(&[])),
+            ("let n = 10; map([1, 2], fn(x) { x + n })", ints(&[11, 12])),
+            (r#"map(["a", "bc"], len)"#, ints(&[1, 2])),
+            ("map([[1], [2, 3]], first)", ints(&[1, 2])),
+            (
+                "map([1], fn(x) { y })",
+                Err(EvaluatorError::new("identifier not found: y".to_string())),
+            ),
+            (
+                "map(1, len)",
+                Err(EvaluatorError::Object(ObjectError::new(
+                    "argument to `map` must be ARRAY, got 1".to_string(),
+                ))),
+            ),
+            (
+                "map([1], 2)",
+                Err(EvaluatorError::Object(ObjectError::new(
+                    "second argument to `map` must be FUNCTION, got 2".to_string(),
+                ))),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            test_object_is_expected(&test_eval(input.to_string()), &expected);
+        }
+    }
+
+    #[test]
+    fn it_evaluates_builtin_filter_and_reduce() {
+        let ints = |values: &[i64]| {
+            Ok(Rc::new(Object::Array(
+                values.iter().map(|&i| Rc::new(i.into())).collect(),
+            )))
+        };
+        let tests = vec![
+            ("filter([1, 2, 3, 4], fn(x) { x % 2 == 0 })", ints(&[2, 4])),
+            ("filter([1, 2], fn(x) { x })", ints(&[1, 2])),
+            ("filter([1, 2], fn(x) { if (x > 1) { x } })", ints(&[2])),
+            ("filter([], fn(x) { true })", ints(&[])),
+            (
+                "reduce([1, 2, 3, 4], 0, fn(acc, x) { acc + x })",
+                Ok(Rc::new(10.into())),
+            ),
+            (
+                "reduce([1, 2, 3], [], fn(acc, x) { unshift(acc, x) })",
+                ints(&[3, 2, 1]),
+            ),
+            (
+                "reduce([], 7, fn(acc, x) { acc + x })",
+                Ok(Rc::new(7.into())),
+            ),
+            (
+                "filter([1], 2)",
+                Err(EvaluatorError::Object(ObjectError::new(
+                    "second argument to `filter` must be FUNCTION, got 2".to_string(),
+                ))),
+            ),
+            (
+                "reduce([1], 0, 2)",
+                Err(EvaluatorError::Object(ObjectError::new(
+                    "third argument to `reduce` must be FUNCTION, got 2".to_string(),
+                ))),
+            ),
+            (
+                "reduce(1, 0, fn(acc, x) { acc })",
+                Err(EvaluatorError::Object(ObjectError::new(
+                    "argument to `reduce` must be ARRAY, got 1".to_string(),
+                ))),
+            ),
+            (
+                "reduce([1], fn(acc, x) { acc })",
+                Err(EvaluatorError::Object(ObjectError::new(
+                    "wrong number of arguments. expected=3, got=2".to_string(),
+                ))),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            test_object_is_expected(&test_eval(input.to_string()), &expected);
+        }
+    }
+
+    #[test]
+    fn it_evaluates_builtin_split_and_join() {
+        let strings = |values: &[&str]| {
+            Ok(Rc::new(Object::Array(
+                values
+                    .iter()
+                    .map(|s| Rc::new(Object::String(s.to_string())))
+                    .collect(),
+            )))
+        };
+        let string = |s: &str| Ok(Rc::new(Object::String(s.to_string())));
+        let tests = vec![
+            (r#"split("a,b,c", ",")"#, strings(&["a", "b", "c"])),
+            (r#"split("a, b", ", ")"#, strings(&["a", "b"])),
+            (r#"split("a,,b,", ",")"#, strings(&["a", "", "b", ""])),
+            (r#"split("héy", "")"#, strings(&["h", "é", "y"])),
+            (r#"split("", ",")"#, strings(&[""])),
+            (r#"join(["a", "b"], "-")"#, string("a-b")),
+            (
+                r#"join(["a", "b"], "-") == "a-b""#,
+                Ok(Rc::new(true.into())),
+            ),
+            (r#"join([], "-")"#, string("")),
+            (r#"join(split("a,b,c", ","), "")"#, string("abc")),
+            (
+                r#"split(1, ",")"#,
+                Err(EvaluatorError::Object(ObjectError::new(
+                    "argument to `split` must be STRING, got 1".to_string(),
+                ))),
+            ),
+            (
+                r#"split("a", 1)"#,
+                Err(EvaluatorError::Object(ObjectError::new(
+                    "second argument to `split` must be STRING, got 1".to_string(),
+                ))),
+            ),
+            (
+                r#"join(["a", 1], ",")"#,
+                Err(EvaluatorError::Object(ObjectError::new(
+                    "elements passed to `join` must be STRING, got 1".to_string(),
+                ))),
+            ),
+            (
+                r#"join("a", ",")"#,
+                Err(EvaluatorError::Object(ObjectError::new(
+                    "argument to `join` must be ARRAY, got a".to_string(),
+                ))),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            test_object_is_expected(&test_eval(input.to_string()), &expected);
+        }
+    }
+
+    #[test]
+    fn it_evaluates_builtin_keys_and_values() {
+        let tests = vec![
+            ("keys({3: 0, -1: 0, 20: 0})", "[-1, 3, 20]"),
+            (r#"keys({"b": 0, "a": 0, "ab": 0})"#, "[a, ab, b]"),
+            ("keys({true: 0, false: 0})", "[false, true]"),
+            (r#"keys({"x": 0, 2: 0, true: 0, 1: 0})"#, "[true, 1, 2, x]"),
+            (r#"values({"x": 1, 2: 2, true: 3, 1: 4})"#, "[3, 4, 2, 1]"),
+            ("keys({})", "[]"),
+            ("values({})", "[]"),
+        ];
+
+        for (input, expected) in tests {
+            match test_eval(input.to_string()) {
+                Ok(result) => assert_eq!(result.to_string(), expected, "{}", input),
+                Err(e) => panic!("{}: {}", input, e),
+            }
+        }
+
+        test_object_is_expected(
+            &test_eval("keys([1])".to_string()),
+            &Err(EvaluatorError::Object(ObjectError::new(
+                "argument to `keys` must be HASH, got [1]".to_string(),
+            ))),
+        );
+    }
+
+    #[test]
+    fn it_evaluates_builtin_int() {
+        let tests = vec![
+            (r#"int("42") == 42"#, Ok(Rc::new(true.into()))),
+            (r#"int("-7")"#, Ok(Rc::new((-7).into()))),
+            (r#"int(" 8 ")"#, Ok(Rc::new(8.into()))),
+            (r#"int("0")"#, Ok(Rc::new(0.into()))),
+            ("int(true) == 1", Ok(Rc::new(true.into()))),
+            ("int(false)", Ok(Rc::new(0.into()))),
+            ("int(5)", Ok(Rc::new(5.into()))),
+            (
+                r#"int("abc")"#,
+                Err(EvaluatorError::Object(ObjectError::new(
+                    r#"could not parse "abc" as INTEGER"#.to_string(),
+                ))),
+            ),
+            (
+                r#"int("")"#,
+                Err(EvaluatorError::Object(ObjectError::new(
+                    r#"could not parse "" as INTEGER"#.to_string(),
+                ))),
+            ),
+            (
+                "int([1])",
+                Err(EvaluatorError::Object(ObjectError::new(
+                    "argument to `int` not supported, got [1]".to_string(),
+                ))),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            test_object_is_expected(&test_eval(input.to_string()), &expected);
+        }
+    }
+
     #[test]
     fn it_evaluates_hash_literals() {
         let tests = vec![(
@@ -981,6 +1705,57 @@ mod test {
         }
     }
 
+    #[test]
+    fn it_evaluates_collection_differences() {
+        let int_array = |values: &[i64]| {
+            Object::Array(
+                values
+                    .iter()
+                    .map(|v| Rc::new(Object::Integer(*v)))
+                    .collect(),
+            )
+        };
+        let int_hash = |pairs: &[(i64, i64)]| {
+            Object::Hash(
+                pairs
+                    .iter()
+                    .map(|(k, v)| (Rc::new(Object::Integer(*k)), Rc::new(Object::Integer(*v))))
+                    .collect(),
+            )
+        };
+        let tests = vec![
+            ("[1, 2, 3] - [2]", Ok(int_array(&[1, 3]))),
+            ("[1, 2, 1, 3, 1] - [1]", Ok(int_array(&[2, 3]))),
+            ("[1, 2] - []", Ok(int_array(&[1, 2]))),
+            ("[1, 2] - [1, 2]", Ok(int_array(&[]))),
+            ("{1: 2, 3: 4} - [1]", Ok(int_hash(&[(3, 4)]))),
+            ("{1: 2, 3: 4} - {3: 0, 5: 6}", Ok(int_hash(&[(1, 2)]))),
+            ("{1: 2} - [7]", Ok(int_hash(&[(1, 2)]))),
+            (
+                "[1, 2] - 1",
+                Err("type mismatch between operands: [1, 2] - 1"),
+            ),
+            (
+                "{1: 2} - 1",
+                Err("type mismatch between operands: {1: 2} - 1"),
+            ),
+            (
+                "[1] - {1: 2}",
+                Err("type mismatch between operands: [1] - {1: 2}"),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            match (test_eval(input.to_string()), expected) {
+                (Ok(evaluated), Ok(expected)) => assert_eq!(*evaluated, expected, "{}", input),
+                (Err(err), Err(expected)) => {
+                    assert_eq!(err.to_string(), format!("Evaluator error: {}", expected))
+                }
+                (got, _) => panic!("unexpected result for {}: {:?}", input, got),
+            }
+        }
+    }
+
     #[test]
     fn it_evaluates_hash_index_expressions() {
         let tests = vec![
@@ -991,6 +1766,7 @@ mod test {
             (r#"{5: 5}[5]"#, Object::Integer(5)),
             (r#"{true: 5}[true]"#, Object::Integer(5)),
             (r#"{false: 5}[false]"#, Object::Integer(5)),
+            (r#"{[1 + 1]: "two"}[2]"#, Object::String("two".into())),
         ];
 
         for (input, expected) in &tests {
@@ -1147,7 +1923,7 @@ mod test {
                 assert_eq!(parameters[1].to_string(), "y");
                 assert_eq!(body.len(), 1);
                 match &body[0] {
-                    Statement::Expression(Expression::Infix(left, Token::Plus, right)) => {
+                    Statement::Expression(Expression::Infix(left, Token::Plus, right), _) => {
                         assert_eq!(**left, Expression::Identifier("x".to_string()));
                         assert_eq!(**right, Expression::Identifier("y".to_string()));
                     }
@@ -1166,14 +1942,14 @@ mod test {
             let infixExpression = macro() { quote(1 + 2); };
             infixExpression();
             "#,
-                "(1 + 2)",
+                "(1 + 2);",
             ),
             (
                 r#"
                 let reverse = macro(a, b) { quote(unquote(b) - unquote(a)); };
                 reverse(2 + 2, 10 - 5);
                 "#,
-                "(10 - 5) - (2 + 2)",
+                "(10 - 5) - (2 + 2);",
             ),
             (
                 r#"
@@ -1193,7 +1969,7 @@ mod test {
                     puts("not greater");
                 } else {
                     puts("greater");
-                }
+                };
                 "#,
             ),
         ];
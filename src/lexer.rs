@@ -1,27 +1,121 @@
-use crate::token::Token;
+use std::io::{self, Read};
+
+use crate::token::{Span, Token};
 
 pub struct Lexer {
     position: usize,
     read_position: usize,
     ch: u8,
     input: Vec<u8>,
+    line: usize,
+    // characters between the start of the line and `position`
+    column: usize,
+    span: Span,
+}
+
+/// A saved lexer position, see `Lexer::snapshot`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Snapshot {
+    position: usize,
+    read_position: usize,
+    ch: u8,
+    line: usize,
+    column: usize,
 }
 
 impl Lexer {
     pub fn new(input: &str) -> Lexer {
+        Lexer::from_bytes(input.as_bytes().to_vec())
+    }
+
+    /// Takes ownership of `input`, reusing its buffer instead of copying it.
+    pub fn from_string(input: String) -> Lexer {
+        Lexer::from_bytes(input.into_bytes())
+    }
+
+    /// Reads all of `reader` into the lexer's buffer. Tokens are still produced
+    /// lazily, but the source itself is held in memory.
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Lexer> {
+        let mut input = Vec::new();
+        reader.read_to_end(&mut input)?;
+        Ok(Lexer::from_bytes(input))
+    }
+
+    fn from_bytes(input: Vec<u8>) -> Lexer {
         let mut lex = Lexer {
             position: 0,
             read_position: 0,
             ch: 0,
-            input: input.as_bytes().to_vec(),
+            input,
+            line: 1,
+            column: 0,
+            span: Span::default(),
         };
         lex.read_char();
-        return lex;
+        lex
+    }
+
+    /// Byte offset of the next character `next_token` will look at.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// The next character `next_token` will look at, without consuming it.
+    pub fn peek_char(&self) -> Option<char> {
+        self.current_char()
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            position: self.position,
+            read_position: self.read_position,
+            ch: self.ch,
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    /// Rewinds (or fast-forwards) to a snapshot taken from this same lexer.
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        self.position = snapshot.position;
+        self.read_position = snapshot.read_position;
+        self.ch = snapshot.ch;
+        self.line = snapshot.line;
+        self.column = snapshot.column;
+    }
+
+    /// Span of the token most recently returned by `next_token`.
+    pub fn span(&self) -> Span {
+        self.span
     }
 
     pub fn next_token(&mut self) -> Token {
-        self.skip_whitespace();
+        let (start, line, col, tok) = loop {
+            self.skip_whitespace();
 
+            let start = self.position.min(self.input.len());
+            let (line, col) = (self.line, self.column());
+            if self.ch == b'/' && matches!(self.peek(), b'/' | b'*') {
+                if self.skip_comment() {
+                    continue;
+                }
+                let tok = Token::Illegal("unterminated block comment".to_string());
+                break (start, line, col, tok);
+            }
+            break (start, line, col, self.read_token());
+        };
+        let end = self.position.min(self.input.len());
+        self.span = Span {
+            line,
+            col,
+            len: String::from_utf8_lossy(&self.input[start..end])
+                .chars()
+                .count(),
+        };
+        tok
+    }
+
+    fn read_token(&mut self) -> Token {
         let tok = match self.ch {
             b'=' => self.single_or_double(b'=', Token::Assign, Token::Eq),
             b'!' => self.single_or_double(b'=', Token::Bang, Token::NotEq),
@@ -36,7 +130,8 @@ impl Lexer {
             b'{' => Token::Lbrace,
             b'}' => Token::Rbrace,
             b':' => Token::Colon,
-            b'a'..=b'z' | b'A'..=b'Z' | b'_' => {
+            b'.' => Token::Dot,
+            _ if self.at_ident_start() => {
                 let ident = self.read_ident();
                 return match ident.as_str() {
                     "fn" => Token::Function,
@@ -45,35 +140,128 @@ impl Lexer {
                     "if" => Token::If,
                     "else" => Token::Else,
                     "return" => Token::Return,
+                    "while" => Token::While,
+                    "break" => Token::Break,
+                    "continue" => Token::Continue,
                     "false" => Token::False,
                     "true" => Token::True,
+                    "null" => Token::Null,
                     _ => Token::Ident(ident),
                 };
             }
-            b'0'..=b'9' => return Token::Int(self.read_int().parse::<i64>().unwrap()),
-            b'<' => Token::Lt,
-            b'>' => Token::Gt,
+            b'0'..=b'9' => return self.read_number(),
+            b'&' => self.single_or_double(
+                b'&',
+                Token::Illegal("illegal character: &".to_string()),
+                Token::And,
+            ),
+            b'|' => self.single_or_double(
+                b'|',
+                Token::Illegal("illegal character: |".to_string()),
+                Token::Or,
+            ),
+            b'?' => self.single_or_double(
+                b'?',
+                Token::Illegal("illegal character: ?".to_string()),
+                Token::Coalesce,
+            ),
+            b'<' => self.single_or_double(b'=', Token::Lt, Token::LtEq),
+            b'>' => self.single_or_double(b'=', Token::Gt, Token::GtEq),
             b'*' => Token::Asterisk,
+            b'%' => Token::Percent,
             b'/' => Token::Slash,
-            b'"' => Token::String(self.read_string()),
+            b'"' => self.read_string(),
 
             0 => Token::Eof,
-            _ => Token::Illegal,
+            c => {
+                // consume the whole character so a multi-byte one is reported once
+                let ch = self.current_char();
+                for _ in 1..ch.map_or(1, char::len_utf8) {
+                    self.read_char();
+                }
+                Token::Illegal(format!("illegal character: {}", ch.unwrap_or(c as char)))
+            }
         };
 
         self.read_char();
-        return tok;
+        tok
     }
 
-    fn read_string(&mut self) -> String {
-        let position = self.position + 1;
+    // skips the `//` or `/* */` comment starting at the current position,
+    // returning false when a block comment runs off the end of the input
+    fn skip_comment(&mut self) -> bool {
+        if self.peek() == b'/' {
+            while self.ch != b'\n' && self.ch != 0 {
+                self.read_char();
+            }
+            return true;
+        }
+
+        // block comments nest, so commenting out code that already has one works
+        let mut depth = 0;
         loop {
+            match (self.ch, self.peek()) {
+                (0, _) => return false,
+                (b'/', b'*') => {
+                    depth += 1;
+                    self.read_char();
+                }
+                (b'*', b'/') => {
+                    depth -= 1;
+                    self.read_char();
+                    if depth == 0 {
+                        self.read_char();
+                        return true;
+                    }
+                }
+                _ => {}
+            }
             self.read_char();
-            if self.ch == b'"' || self.ch == 0 {
-                break;
+        }
+    }
+
+    fn read_string(&mut self) -> Token {
+        let mut bytes = vec![];
+        // a bad escape is reported once the whole literal has been read, so
+        // lexing picks up again after its closing quote
+        let mut bad_escape = None;
+        loop {
+            self.read_char();
+            match self.ch {
+                b'"' => break,
+                0 => {
+                    return Token::Illegal("unterminated string literal".to_string());
+                }
+                b'\\' => {
+                    self.read_char();
+                    match self.ch {
+                        b'n' => bytes.push(b'\n'),
+                        b't' => bytes.push(b'\t'),
+                        b'r' => bytes.push(b'\r'),
+                        b'0' => bytes.push(0),
+                        b'\\' | b'"' => bytes.push(self.ch),
+                        0 => {
+                            return Token::Illegal("unterminated string literal".to_string());
+                        }
+                        _ => {
+                            let escape = self.current_char().unwrap_or('?');
+                            bad_escape
+                                .get_or_insert(format!("unknown escape sequence \\{}", escape));
+                        }
+                    }
+                }
+                ch => bytes.push(ch),
             }
         }
-        return String::from_utf8_lossy(&self.input[position..self.position]).to_string();
+        match bad_escape {
+            Some(msg) => Token::Illegal(msg),
+            None => Token::String(String::from_utf8_lossy(&bytes).to_string()),
+        }
+    }
+
+    // 1-based column, in characters, of the current position
+    fn column(&self) -> usize {
+        self.column + 1
     }
 
     fn single_or_double(
@@ -91,21 +279,66 @@ impl Lexer {
 
     fn read_ident(&mut self) -> String {
         let position = self.position;
-        while self.ch.is_ascii_alphanumeric() || self.ch == b'_' {
-            self.read_char();
+        while let Some(c) = self.current_char().filter(|c| is_ident_char(*c)) {
+            for _ in 0..c.len_utf8() {
+                self.read_char();
+            }
         }
-        return String::from_utf8_lossy(&self.input[position..self.position]).to_string();
+        String::from_utf8_lossy(&self.input[position..self.position]).to_string()
     }
 
-    fn read_int(&mut self) -> String {
+    fn read_number(&mut self) -> Token {
         let position = self.position;
-        while self.ch.is_ascii_alphanumeric() {
+        while self.ch.is_ascii_digit() {
+            self.read_char();
+        }
+
+        // a dot only continues the number when a digit follows it, so
+        // `1.len()` still lexes as a method call on an integer
+        let is_float = self.ch == b'.' && self.peek().is_ascii_digit();
+        if is_float {
             self.read_char();
+            while self.ch.is_ascii_digit() {
+                self.read_char();
+            }
+        }
+
+        // a digit run running straight into identifier characters is almost
+        // always a typo, so swallow the whole word and report it as one token
+        let malformed = self.at_ident_start();
+        while let Some(c) = self.current_char().filter(|c| is_ident_char(*c)) {
+            for _ in 0..c.len_utf8() {
+                self.read_char();
+            }
+        }
+
+        let literal = String::from_utf8_lossy(&self.input[position..self.position]).to_string();
+        if malformed {
+            return Token::Illegal(format!("invalid number literal: {}", literal));
+        }
+        if is_float {
+            return match literal.parse::<f64>() {
+                Ok(x) => Token::Float(x),
+                Err(_) => Token::Illegal(format!("invalid number literal: {}", literal)),
+            };
+        }
+        match literal.parse::<i64>() {
+            Ok(i) => Token::Int(i),
+            Err(_) => Token::Illegal(format!("invalid number literal: {}", literal)),
         }
-        return String::from_utf8_lossy(&self.input[position..self.position]).to_string();
     }
 
     fn read_char(&mut self) {
+        if self.ch == b'\n' {
+            self.line += 1;
+            self.column = 0;
+        } else if self.read_position > 0
+            && self.position < self.input.len()
+            && !is_continuation_byte(self.input[self.position])
+        {
+            self.column += 1;
+        }
+
         if self.read_position >= self.input.len() {
             self.ch = 0;
         } else {
@@ -122,26 +355,51 @@ impl Lexer {
         }
     }
 
+    // decodes the (possibly multi-byte) character starting at `position`
+    fn current_char(&self) -> Option<char> {
+        let bytes = self.input.get(self.position..)?;
+        let len = match *bytes.first()? {
+            b if b < 0x80 => 1,
+            b if b >= 0xf0 => 4,
+            b if b >= 0xe0 => 3,
+            _ => 2,
+        };
+        std::str::from_utf8(bytes.get(..len)?).ok()?.chars().next()
+    }
+
+    fn at_ident_start(&self) -> bool {
+        matches!(self.current_char(), Some(c) if c.is_alphabetic() || c == '_')
+    }
+
     fn peek(&mut self) -> u8 {
         if self.read_position >= self.input.len() {
-            return 0;
+            0
         } else {
-            return self.input[self.read_position];
+            self.input[self.read_position]
         }
     }
 }
 
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// the trailing bytes of a multi-byte character, which don't start a column
+fn is_continuation_byte(b: u8) -> bool {
+    b & 0xC0 == 0x80
+}
+
 #[cfg(test)]
 mod test {
     use super::Lexer;
-    use crate::token::Token;
+    use crate::token::{Span, Token};
     use anyhow::Result;
 
     #[test]
     fn it_gets_next_token_correctly() -> Result<()> {
         let input = "=+(){},;";
 
-        let mut lexer = Lexer::new(input.into());
+        let mut lexer = Lexer::new(input);
 
         let tokens = vec![
             Token::Assign,
@@ -160,7 +418,7 @@ mod test {
             assert_eq!(token, next_token);
         }
 
-        return Ok(());
+        Ok(())
     }
 
     #[test]
@@ -171,7 +429,7 @@ mod test {
                 x + y;
             };
             let result = add(five, ten);
-        !-/*5;
+        !-/ *5;
         5 < 10 > 5;
         if (5 < 10) {
             return true;
@@ -186,8 +444,9 @@ mod test {
         [1, 2];
         {"foo": "bar"}
         macro(x, y) { x + y; };
+        x.len();
         "#;
-        let mut lexer = Lexer::new(input.into());
+        let mut lexer = Lexer::new(input);
 
         let tokens = vec![
             Token::Let,
@@ -289,6 +548,12 @@ mod test {
             Token::Semicolon,
             Token::Rbrace,
             Token::Semicolon,
+            Token::Ident(String::from("x")),
+            Token::Dot,
+            Token::Ident(String::from("len")),
+            Token::Lparen,
+            Token::Rparen,
+            Token::Semicolon,
             Token::Eof,
         ];
 
@@ -298,6 +563,512 @@ mod test {
             assert_eq!(token, next_token);
         }
 
-        return Ok(());
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_invalid_number_literals() -> Result<()> {
+        let input = "123abc 12_3 99999999999999999999 42";
+        let mut lexer = Lexer::new(input);
+
+        let tokens = vec![
+            Token::Illegal("invalid number literal: 123abc".to_string()),
+            Token::Illegal("invalid number literal: 12_3".to_string()),
+            Token::Illegal("invalid number literal: 99999999999999999999".to_string()),
+            Token::Int(42),
+            Token::Eof,
+        ];
+
+        for token in tokens {
+            let next_token = lexer.next_token();
+            assert_eq!(token, next_token);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_lexes_float_literals() -> Result<()> {
+        let input = "2.75 0.5 10.0 1.len() 2.5x";
+        let mut lexer = Lexer::new(input);
+
+        let tokens = vec![
+            Token::Float(2.75),
+            Token::Float(0.5),
+            Token::Float(10.0),
+            Token::Int(1),
+            Token::Dot,
+            Token::Ident("len".to_string()),
+            Token::Lparen,
+            Token::Rparen,
+            Token::Illegal("invalid number literal: 2.5x".to_string()),
+            Token::Eof,
+        ];
+
+        for token in tokens {
+            assert_eq!(lexer.next_token(), token);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_decodes_string_escapes() -> Result<()> {
+        let tests = vec![
+            (r#""line1\nline2""#, "line1\nline2"),
+            (r#""a\tb""#, "a\tb"),
+            (r#""a\rb""#, "a\rb"),
+            (r#""back\\slash""#, "back\\slash"),
+            (r#""say \"hi\"""#, "say \"hi\""),
+            (r#""nul\0""#, "nul\0"),
+            (r#""\\n""#, "\\n"),
+            (r#""é\n""#, "é\n"),
+        ];
+        for (input, expected) in tests {
+            let mut lexer = Lexer::new(input);
+            assert_eq!(
+                lexer.next_token(),
+                Token::String(expected.to_string()),
+                "{}",
+                input
+            );
+            assert_eq!(lexer.next_token(), Token::Eof, "{}", input);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_unknown_string_escapes() -> Result<()> {
+        let mut lexer = Lexer::new(r#"let s = "a\qb\z"; s"#);
+        assert_eq!(lexer.next_token(), Token::Let);
+        assert_eq!(lexer.next_token(), Token::Ident("s".to_string()));
+        assert_eq!(lexer.next_token(), Token::Assign);
+        assert_eq!(
+            lexer.next_token(),
+            Token::Illegal("unknown escape sequence \\q".to_string())
+        );
+        assert_eq!(lexer.span().start(), (1, 9));
+        // the rest of the literal is skipped rather than lexed as code
+        assert_eq!(lexer.next_token(), Token::Semicolon);
+        assert_eq!(lexer.next_token(), Token::Ident("s".to_string()));
+
+        let mut lexer = Lexer::new(r#""trailing\"#);
+        assert_eq!(
+            lexer.next_token(),
+            Token::Illegal("unterminated string literal".to_string())
+        );
+        assert_eq!(lexer.next_token(), Token::Eof);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_unterminated_string_literals() -> Result<()> {
+        let mut lexer = Lexer::new("let s = \"hello");
+        assert_eq!(lexer.next_token(), Token::Let);
+        assert_eq!(lexer.next_token(), Token::Ident("s".to_string()));
+        assert_eq!(lexer.next_token(), Token::Assign);
+        assert_eq!(
+            lexer.next_token(),
+            Token::Illegal("unterminated string literal".to_string())
+        );
+        assert_eq!(lexer.next_token(), Token::Eof);
+
+        let mut lexer = Lexer::new("\"ok\";\n  é; \"open\n");
+        assert_eq!(lexer.next_token(), Token::String("ok".to_string()));
+        assert_eq!(lexer.next_token(), Token::Semicolon);
+        assert_eq!(lexer.next_token(), Token::Ident("é".to_string()));
+        assert_eq!(lexer.next_token(), Token::Semicolon);
+        assert_eq!(
+            lexer.next_token(),
+            Token::Illegal("unterminated string literal".to_string())
+        );
+        // the parser reports the position from the span
+        assert_eq!(lexer.span().start(), (2, 6));
+        assert_eq!(lexer.next_token(), Token::Eof);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_lexes_unicode_identifiers() -> Result<()> {
+        let input = "let naïve = 1; 名前 _x2 1é € ok";
+        let mut lexer = Lexer::new(input);
+
+        let tokens = vec![
+            Token::Let,
+            Token::Ident(String::from("naïve")),
+            Token::Assign,
+            Token::Int(1),
+            Token::Semicolon,
+            Token::Ident(String::from("名前")),
+            Token::Ident(String::from("_x2")),
+            Token::Illegal("invalid number literal: 1é".to_string()),
+            Token::Illegal("illegal character: €".to_string()),
+            Token::Ident(String::from("ok")),
+            Token::Eof,
+        ];
+
+        for token in tokens {
+            let next_token = lexer.next_token();
+            assert_eq!(token, next_token);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_lexes_from_readers_and_owned_strings() -> Result<()> {
+        let input = "let x = 5;";
+        let expected = vec![
+            Token::Let,
+            Token::Ident(String::from("x")),
+            Token::Assign,
+            Token::Int(5),
+            Token::Semicolon,
+            Token::Eof,
+        ];
+
+        let mut from_reader = Lexer::from_reader(std::io::Cursor::new(input))?;
+        let mut from_string = Lexer::from_string(input.to_string());
+        for token in expected {
+            assert_eq!(token, from_reader.next_token());
+            assert_eq!(token, from_string.next_token());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_rewinds_to_a_snapshot() -> Result<()> {
+        let mut lexer = Lexer::new("let x = 5;");
+        assert_eq!(lexer.position(), 0);
+        assert_eq!(lexer.peek_char(), Some('l'));
+        assert_eq!(lexer.next_token(), Token::Let);
+
+        let snapshot = lexer.snapshot();
+        assert_eq!(lexer.next_token(), Token::Ident("x".to_string()));
+        assert_eq!(lexer.next_token(), Token::Assign);
+        assert_eq!(lexer.peek_char(), Some(' '));
+        assert_eq!(lexer.position(), 7);
+
+        lexer.restore(snapshot);
+        assert_eq!(lexer.position(), 3);
+        assert_eq!(lexer.next_token(), Token::Ident("x".to_string()));
+        assert_eq!(lexer.next_token(), Token::Assign);
+        assert_eq!(lexer.next_token(), Token::Int(5));
+        assert_eq!(lexer.next_token(), Token::Semicolon);
+        assert_eq!(lexer.peek_char(), None);
+        assert_eq!(lexer.next_token(), Token::Eof);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_tracks_token_spans_across_lines() -> Result<()> {
+        let input = "let x = 10;\n  \"two\"\n\nx";
+        let mut lexer = Lexer::new(input);
+
+        let expected = vec![
+            (
+                Token::Let,
+                Span {
+                    line: 1,
+                    col: 1,
+                    len: 3,
+                },
+            ),
+            (
+                Token::Ident("x".to_string()),
+                Span {
+                    line: 1,
+                    col: 5,
+                    len: 1,
+                },
+            ),
+            (
+                Token::Assign,
+                Span {
+                    line: 1,
+                    col: 7,
+                    len: 1,
+                },
+            ),
+            (
+                Token::Int(10),
+                Span {
+                    line: 1,
+                    col: 9,
+                    len: 2,
+                },
+            ),
+            (
+                Token::Semicolon,
+                Span {
+                    line: 1,
+                    col: 11,
+                    len: 1,
+                },
+            ),
+            (
+                Token::String("two".to_string()),
+                Span {
+                    line: 2,
+                    col: 3,
+                    len: 5,
+                },
+            ),
+            (
+                Token::Ident("x".to_string()),
+                Span {
+                    line: 4,
+                    col: 1,
+                    len: 1,
+                },
+            ),
+            (
+                Token::Eof,
+                Span {
+                    line: 4,
+                    col: 2,
+                    len: 0,
+                },
+            ),
+        ];
+
+        for (token, span) in expected {
+            assert_eq!(lexer.next_token(), token);
+            assert_eq!(lexer.span(), span, "{}", token);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_counts_columns_in_characters() -> Result<()> {
+        let mut lexer = Lexer::new("\"héllo\" + 名前;\nλ");
+
+        let expected = vec![
+            (
+                Token::String("héllo".to_string()),
+                Span {
+                    line: 1,
+                    col: 1,
+                    len: 7,
+                },
+            ),
+            (
+                Token::Plus,
+                Span {
+                    line: 1,
+                    col: 9,
+                    len: 1,
+                },
+            ),
+            (
+                Token::Ident("名前".to_string()),
+                Span {
+                    line: 1,
+                    col: 11,
+                    len: 2,
+                },
+            ),
+            (
+                Token::Semicolon,
+                Span {
+                    line: 1,
+                    col: 13,
+                    len: 1,
+                },
+            ),
+            (
+                Token::Ident("λ".to_string()),
+                Span {
+                    line: 2,
+                    col: 1,
+                    len: 1,
+                },
+            ),
+        ];
+
+        for (token, span) in expected {
+            assert_eq!(lexer.next_token(), token);
+            assert_eq!(lexer.span(), span, "{}", token);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_restores_line_tracking_from_a_snapshot() -> Result<()> {
+        let mut lexer = Lexer::new("a\nb\nc");
+        lexer.next_token();
+        let snapshot = lexer.snapshot();
+        lexer.next_token();
+        lexer.next_token();
+        assert_eq!(lexer.span().line, 3);
+
+        lexer.restore(snapshot);
+        assert_eq!(lexer.next_token(), Token::Ident("b".to_string()));
+        assert_eq!(
+            lexer.span(),
+            Span {
+                line: 2,
+                col: 1,
+                len: 1
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_skips_comments() -> Result<()> {
+        let tests = vec![
+            ("1 // one\n2", vec![Token::Int(1), Token::Int(2)]),
+            (
+                "x; // trailing comment",
+                vec![Token::Ident("x".to_string()), Token::Semicolon],
+            ),
+            ("// only a comment", vec![]),
+            (
+                "1 /* spans\nlines */ + 2",
+                vec![Token::Int(1), Token::Plus, Token::Int(2)],
+            ),
+            (
+                "/* outer /* inner */ still outer */ a",
+                vec![Token::Ident("a".to_string())],
+            ),
+            ("4 / 2", vec![Token::Int(4), Token::Slash, Token::Int(2)]),
+            ("\"http://x\"", vec![Token::String("http://x".to_string())]),
+            (
+                "\"/* not a comment */\"",
+                vec![Token::String("/* not a comment */".to_string())],
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let mut lexer = Lexer::new(input);
+            for token in expected {
+                assert_eq!(lexer.next_token(), token, "{}", input);
+            }
+            assert_eq!(lexer.next_token(), Token::Eof, "{}", input);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_rejects_unterminated_block_comments() -> Result<()> {
+        let mut lexer = Lexer::new("1\n  /* never /* closed */");
+        assert_eq!(lexer.next_token(), Token::Int(1));
+        assert_eq!(
+            lexer.next_token(),
+            Token::Illegal("unterminated block comment".to_string())
+        );
+        assert_eq!(lexer.span().start(), (2, 3));
+        assert_eq!(lexer.next_token(), Token::Eof);
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_tracks_spans_past_comments() -> Result<()> {
+        let mut lexer = Lexer::new("/* a\nb */ x // c\n  y");
+        assert_eq!(lexer.next_token(), Token::Ident("x".to_string()));
+        assert_eq!(
+            lexer.span(),
+            Span {
+                line: 2,
+                col: 6,
+                len: 1
+            }
+        );
+        assert_eq!(lexer.next_token(), Token::Ident("y".to_string()));
+        assert_eq!(
+            lexer.span(),
+            Span {
+                line: 3,
+                col: 3,
+                len: 1
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn it_disambiguates_one_and_two_character_operators() -> Result<()> {
+        let tests = vec![
+            ("=", vec![Token::Assign]),
+            ("==", vec![Token::Eq]),
+            ("!", vec![Token::Bang]),
+            ("!=", vec![Token::NotEq]),
+            ("<", vec![Token::Lt]),
+            ("<=", vec![Token::LtEq]),
+            (">", vec![Token::Gt]),
+            (">=", vec![Token::GtEq]),
+            ("===", vec![Token::Eq, Token::Assign]),
+            ("!==", vec![Token::NotEq, Token::Assign]),
+            ("= =", vec![Token::Assign, Token::Assign]),
+            ("<==", vec![Token::LtEq, Token::Assign]),
+            ("!!=", vec![Token::Bang, Token::NotEq]),
+            ("a=b", vec![ident("a"), Token::Assign, ident("b")]),
+            ("a==b", vec![ident("a"), Token::Eq, ident("b")]),
+            (
+                "a>=!b",
+                vec![ident("a"), Token::GtEq, Token::Bang, ident("b")],
+            ),
+            ("x =", vec![ident("x"), Token::Assign]),
+            ("x <", vec![ident("x"), Token::Lt]),
+            ("x !", vec![ident("x"), Token::Bang]),
+            ("a%b", vec![ident("a"), Token::Percent, ident("b")]),
+            ("a??b", vec![ident("a"), Token::Coalesce, ident("b")]),
+            (
+                "???",
+                vec![
+                    Token::Coalesce,
+                    Token::Illegal("illegal character: ?".to_string()),
+                ],
+            ),
+            ("null", vec![Token::Null]),
+            ("nullable", vec![ident("nullable")]),
+        ];
+
+        for (input, expected) in tests {
+            let mut lexer = Lexer::new(input);
+            for token in expected {
+                assert_eq!(lexer.next_token(), token, "{}", input);
+            }
+            assert_eq!(lexer.next_token(), Token::Eof, "{}", input);
+        }
+
+        Ok(())
+    }
+
+    fn ident(name: &str) -> Token {
+        Token::Ident(name.to_string())
+    }
+
+    #[test]
+    fn it_lexes_logical_operators() -> Result<()> {
+        let mut lexer = Lexer::new("a && b || c & d |");
+        let tokens = vec![
+            ident("a"),
+            Token::And,
+            ident("b"),
+            Token::Or,
+            ident("c"),
+            Token::Illegal("illegal character: &".to_string()),
+            ident("d"),
+            Token::Illegal("illegal character: |".to_string()),
+            Token::Eof,
+        ];
+        for token in tokens {
+            assert_eq!(lexer.next_token(), token);
+        }
+
+        Ok(())
     }
 }
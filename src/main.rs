@@ -1,9 +1,11 @@
-use ::monkey::monkey::ExecMode;
+use ::monkey::monkey::{ExecMode, ResultFormat};
+use ::monkey::object::output;
+use ::monkey::parser::trace;
 use ::monkey::utils;
-use clap::arg;
 use clap::crate_version;
 use clap::Parser;
 use monkey::monkey;
+use std::time::Duration;
 
 /// monkey is the binary for executing the monkey programming language
 #[derive(Debug, Parser)]
@@ -25,35 +27,113 @@ struct MonkeyCmd {
     /// Enter interactive mode after executing 'script'
     #[arg(short = 'i', long = "interactive", required = false, global = true)]
     script: Option<String>,
+
+    /// Don't print results that evaluate to a bare null
+    #[arg(long = "suppress-null", required = false, global = true)]
+    suppress_null: bool,
+
+    /// How repl results are printed (plain, typed or json)
+    #[arg(
+        long = "repl-result-format",
+        default_value = "plain",
+        required = false,
+        global = true
+    )]
+    repl_result_format: ResultFormat,
+
+    /// Stop with an error once a program has printed this many bytes
+    #[arg(long = "max-output", required = false, global = true)]
+    max_output: Option<usize>,
+
+    /// Only parse and compile 'path', reporting errors without running it
+    #[arg(long = "check", required = false, global = true)]
+    check: bool,
+
+    /// Print the bytecode 'path' compiles to instead of running it
+    #[arg(long = "disasm", required = false, global = true)]
+    disasm: bool,
+
+    /// Run 'path', then run it again whenever the file changes
+    #[arg(long = "watch", required = false, global = true)]
+    watch: bool,
+
+    /// Log each expression the parser enters and leaves to stderr
+    #[arg(long = "trace-parse", required = false, global = true)]
+    trace_parse: bool,
 }
 
 fn main() {
     let args = MonkeyCmd::parse();
+    output::set_limit(args.max_output);
+    if args.trace_parse {
+        trace::set_sink(Some(Box::new(std::io::stderr())));
+    }
+
+    if let (true, Some(path)) = (args.watch, &args.path) {
+        let changes = monkey::poll_changes(path, Duration::from_millis(250));
+        monkey::watch(path, changes, |contents| {
+            monkey::interpret_chunk(
+                args.mode.clone(),
+                contents,
+                args.suppress_null,
+                args.repl_result_format,
+            )
+        });
+        return;
+    }
 
-    match args.path {
-        Some(path) => match utils::load_monkey(path) {
-            Ok(contents) => match monkey::interpret_chunk(args.mode, contents) {
-                Ok(_) => return,
+    if let Some(path) = args.path {
+        match utils::load_monkey(path) {
+            Ok(contents) if args.check => {
+                if let Err(e) = monkey::check(contents) {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+                return;
+            }
+            Ok(contents) if args.disasm => match monkey::disassemble(contents) {
+                Ok(listing) => {
+                    print!("{}", listing);
+                    return;
+                }
                 Err(e) => {
                     eprintln!("Error: {}", e);
                     std::process::exit(1);
                 }
             },
+            Ok(contents) => {
+                match monkey::interpret_chunk(
+                    args.mode,
+                    contents,
+                    args.suppress_null,
+                    args.repl_result_format,
+                ) {
+                    Ok(_) => return,
+                    Err(e) => {
+                        eprintln!("Error: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
             Err(e) => {
                 eprintln!("Error: {}", e);
                 std::process::exit(1);
             }
-        },
-        None => {}
+        }
     };
 
     // repl mode
     match args.script {
-        Some(path) => match monkey::repl(Some(path), args.mode) {
+        Some(path) => match monkey::repl(
+            Some(path),
+            args.mode,
+            args.suppress_null,
+            args.repl_result_format,
+        ) {
             Ok(_) => {}
             Err(e) => eprintln!("Error: {}", e),
         },
-        None => match monkey::repl(None, args.mode) {
+        None => match monkey::repl(None, args.mode, args.suppress_null, args.repl_result_format) {
             Ok(_) => {}
             Err(e) => eprintln!("Error: {}", e),
         },
@@ -0,0 +1,158 @@
+// Runs one corpus of programs through both backends and checks they agree,
+// so a change to either one can't quietly drift from the other.
+
+use std::rc::Rc;
+
+use super::error::MonkeyError;
+use super::interpreter::Interpreter;
+use super::ExecMode;
+use crate::evaluator::error::EvaluatorError;
+use crate::object::Object;
+
+const CORPUS: &[&str] = &[
+    // arithmetic
+    "1 + 2 * 3 - 4 / 2",
+    "(5 + 10 * 2 + 15 / 3) * 2 + -10",
+    "7 % 3 + -7 % 3",
+    "1.5 * 2 + 1",
+    "10 / 4",
+    "9223372036854775807 + 1",
+    "1 / 0",
+    "1 < 2 && 2 <= 2 || false",
+    "!(1 >= 2) == true",
+    // strings
+    r#""mon" + "key""#,
+    r#"len("hello")"#,
+    r#"join(split("a,b,c", ","), "-")"#,
+    r#""count: " + str(5)"#,
+    r#""a" == "a" && "a" != "b""#,
+    r#"int("42") + int(true)"#,
+    r#"int("4x2")"#,
+    // arrays
+    "[1, 2 * 2, 3 + 3][1]",
+    "[1, 2, 3][5]",
+    "let a = [1, 2, 3]; push(a, 4); a",
+    "rest(push([1], 2))",
+    "[pop([1, 2, 3]), shift([1, 2, 3]), pop([]), shift([])]",
+    "let s = [1, 2, 3]; let p = pop(s); s = p[1]; let q = shift(s); [p[0], q[0], q[1]]",
+    "let a = [1, 2, 3]; a[0] = 9; a",
+    "let a = [1]; a[3] = 2",
+    "map([1, 2, 3], fn(x) { x * x })",
+    "filter([1, 2, 3, 4], fn(x) { x % 2 == 0 })",
+    "reduce([1, 2, 3, 4], 0, fn(acc, x) { acc + x })",
+    "[1, 2, 3, 2] - [2]",
+    "first([])",
+    "len(1)",
+    // hashes
+    r#"{"one": 1, "two": 2}["two"]"#,
+    r#"let h = {1: "a", true: "b"}; h[true]"#,
+    "{1: 2}[3] ?? 0",
+    r#"keys({"b": 1, "a": 2, 3: 4})"#,
+    r#"values({"b": 1, "a": 2})"#,
+    "{1: 2, 3: 4} - [1]",
+    "{fn() { 1 }: 2}",
+    "{1: 2}[[1]]",
+    r#"let h = {}; h["k"] = 1; h["k"] = h["k"] + 1; h"#,
+    // closures
+    "let add = fn(a) { fn(b) { a + b } }; add(2)(3)",
+    "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(15)",
+    "let apply = fn(f, x) { f(x) }; apply(len, [1, 2])",
+    "let f = fn(x) { return x * 2; 100 }; f(4)",
+    "return 5; 10",
+    "let x = 2; if (x > 1) { return x * 10; }; 0",
+    "fn(x) { x }",
+    "let fib = memoize(fn(n) { n }); fib(3)",
+    "memoize(fn() { fn(x) { x * 2 } })()(4)",
+    "let f = fn() { fn(x) { x } }; f()(1) + f()(2)",
+    "type(fn(x) { x })",
+    // control flow
+    "if (1 > 2) { 10 } else { 20 }",
+    "if (false) { 10 }",
+    "let i = 0; let total = 0; while (i < 5) { i = i + 1; total = total + i }; total",
+    "let i = 0; let evens = 0; while (i < 10) { i = i + 1; if (i % 2 == 0) { evens = evens + i } }; [i, evens]",
+    "let x = 1; x = x + 1; x",
+    "if (true) { len([1, 2]) } else { 1 / 0 }",
+    // macros
+    "let unless = macro(c, a, b) { quote(if (!(unquote(c))) { unquote(a) } else { unquote(b) }) }; unless(10 > 5, 1, 2)",
+    "let twice = macro(x) { quote(unquote(x) + unquote(x)) }; let n = 4; twice(n * 2)",
+    "quote(1 + 2)",
+    // errors
+    "5 > \"5\"",
+    "5()",
+    "{1: 2}[1](3)",
+    "5 + true",
+    "null * null",
+    "[1] * [2]",
+    "[1, 2] - 1",
+    "true + false",
+    "true > false",
+    r#""a" - "b""#,
+    r#""a" < "b""#,
+    "-true",
+    "fn(a, b) { a }(1)",
+];
+
+// Programs whose results differ between the backends on purpose. Each lists
+// what the evaluator gives, then what the vm gives.
+const EXCEPTIONS: &[(&str, &str, &str)] = &[
+    // the compiler resolves names before anything runs
+    (
+        "let x = 1; y",
+        "identifier not found: y",
+        "undefined variable: y",
+    ),
+    // closures capture by value in the vm, so writing through one is refused
+    (
+        "let counter = fn() { let n = 0; fn() { n = n + 1; n } }; let c = counter(); c(); c()",
+        "INTEGER 2",
+        "cannot assign to captured variable n",
+    ),
+];
+
+// A backend's outcome in a form both can be compared by. Functions are
+// `Function` objects in the evaluator but closures in the vm, so only their
+// type is kept.
+fn describe(result: Result<Rc<Object>, MonkeyError>) -> String {
+    match result {
+        Ok(object) => match &*object {
+            Object::ReturnValue(value) => describe(Ok(Rc::clone(value))),
+            Object::Function(..)
+            | Object::Closure(..)
+            | Object::CompiledFunction(..)
+            | Object::Memoized(..) => object.type_tag().to_string(),
+            _ => format!("{} {}", object.type_tag(), object),
+        },
+        // the evaluator tags its errors with where they came from
+        Err(MonkeyError::Evaluator(EvaluatorError::Native(msg))) => msg,
+        Err(MonkeyError::Evaluator(EvaluatorError::Object(e))) => e.msg,
+        Err(e) => e.to_string(),
+    }
+}
+
+fn run_direct(input: &str) -> String {
+    describe(Interpreter::with_mode(ExecMode::Direct).eval(input))
+}
+
+fn run_vm(input: &str) -> String {
+    describe(Interpreter::new().eval(input))
+}
+
+#[test]
+fn it_agrees_across_backends() {
+    let mut divergent = vec![];
+    for input in CORPUS {
+        let (direct, vm) = (run_direct(input), run_vm(input));
+        if direct != vm {
+            divergent.push(format!("{}\n  direct: {}\n  vm:     {}", input, direct, vm));
+        }
+    }
+    assert!(divergent.is_empty(), "\n{}", divergent.join("\n"));
+}
+
+#[test]
+fn it_keeps_documented_exceptions() {
+    for (input, direct, vm) in EXCEPTIONS {
+        assert_eq!(run_direct(input), *direct, "{}", input);
+        assert_eq!(run_vm(input), *vm, "{}", input);
+    }
+}
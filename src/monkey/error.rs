@@ -0,0 +1,23 @@
+use thiserror::Error;
+
+use crate::compiler::error::CompileError;
+use crate::evaluator::error::EvaluatorError;
+use crate::parser::errors::ParserErrors;
+use crate::vm::error::VmError;
+
+#[derive(Debug, Clone, Error)]
+pub enum MonkeyError {
+    #[error("{}", join_parser_errors(.0))]
+    Parse(ParserErrors),
+    #[error("{0}")]
+    Evaluator(#[from] EvaluatorError),
+    #[error("{0}")]
+    Compile(#[from] CompileError),
+    #[error("{0}")]
+    Vm(#[from] VmError),
+}
+
+fn join_parser_errors(errors: &ParserErrors) -> String {
+    let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    messages.join("\n")
+}
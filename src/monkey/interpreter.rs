@@ -0,0 +1,204 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::error::MonkeyError;
+use super::ExecMode;
+use crate::compiler::symbol_table::SymbolTable;
+use crate::compiler::Compiler;
+use crate::evaluator::{define_macros, evaluate, expand_macros};
+use crate::lexer::Lexer;
+use crate::object::builtin::Builtin;
+use crate::object::environment::{Env, Environment};
+use crate::object::Object;
+use crate::parser::ast::{Node, Statement};
+use crate::parser::Parser;
+use crate::vm::{GLOBAL_SIZE, VM};
+
+/// Runs source on either backend, keeping globals, constants and macros
+/// between calls. The repl, the file runner and `check` all go through one.
+pub struct Interpreter {
+    mode: ExecMode,
+    pub(super) env: Env,
+    pub(super) macro_env: Env,
+    pub(super) symbol_table: Rc<RefCell<SymbolTable>>,
+    pub(super) constants: Rc<RefCell<Vec<Rc<Object>>>>,
+    pub(super) globals: Rc<RefCell<Vec<Rc<Object>>>>,
+    // kept between runs so its stack and frames are only allocated once
+    vm: Option<VM>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self::with_mode(ExecMode::VM)
+    }
+
+    // globals start out sharing a single `Null`
+    #[allow(clippy::rc_clone_in_vec_init)]
+    pub fn with_mode(mode: ExecMode) -> Self {
+        let symbol_table = SymbolTable::new();
+        for (i, v) in Builtin::variants().iter().enumerate() {
+            symbol_table.borrow_mut().define_builtin(i, v.to_string());
+        }
+
+        Interpreter {
+            mode,
+            env: Rc::new(RefCell::new(Environment::new())),
+            macro_env: Rc::new(RefCell::new(Environment::new())),
+            symbol_table,
+            constants: Rc::new(RefCell::new(vec![])),
+            globals: Rc::new(RefCell::new(vec![Rc::new(Object::Null); GLOBAL_SIZE])),
+            vm: None,
+        }
+    }
+
+    pub fn mode(&self) -> &ExecMode {
+        &self.mode
+    }
+
+    /// The direct backend's global environment.
+    pub fn env(&self) -> &Env {
+        &self.env
+    }
+
+    /// Binds `name` for code run afterwards, in whichever backend runs it.
+    pub fn define_global(&mut self, name: &str, value: Object) {
+        let value = Rc::new(value);
+        self.env
+            .borrow_mut()
+            .set(name.to_string(), Rc::clone(&value));
+        let symbol = self.symbol_table.borrow_mut().define(name.to_string());
+        self.globals.borrow_mut()[symbol.index] = value;
+    }
+
+    /// Returns the value of `src`'s final expression, or null when it ends in
+    /// a statement.
+    pub fn eval(&mut self, src: &str) -> Result<Rc<Object>, MonkeyError> {
+        let program = parse(Lexer::new(src))?;
+        let (result, _) = self.run(program)?;
+        Ok(result.unwrap_or_else(|| Rc::new(Object::Null)))
+    }
+
+    /// Runs a parsed program, returning the value of its final expression, if
+    /// any, alongside the compiler's warnings.
+    pub fn run(
+        &mut self,
+        program: Vec<Statement>,
+    ) -> Result<(Option<Rc<Object>>, Vec<String>), MonkeyError> {
+        let expanded = self.expand(program)?;
+        match self.mode {
+            ExecMode::Direct => {
+                let result = evaluate(expanded, Rc::clone(&self.env))?;
+                // a top-level `return` ends the program with its value
+                let result = match &*result {
+                    Object::ReturnValue(value) => Rc::clone(value),
+                    _ => result,
+                };
+                Ok((Some(result), vec![]))
+            }
+            ExecMode::VM => {
+                let compiler = self.compile(expanded)?;
+                let warnings = compiler.warnings().to_vec();
+                let mut machine = match self.vm.take() {
+                    Some(mut machine) => {
+                        machine.reset(compiler.bytecode());
+                        machine
+                    }
+                    None => {
+                        VM::new_with_global_store(compiler.bytecode(), Rc::clone(&self.globals))
+                    }
+                };
+                let result = machine.run();
+                let top = machine.stack_top();
+                self.vm = Some(machine);
+                result?;
+                Ok((top, warnings))
+            }
+        }
+    }
+
+    /// Compiles `src` without running it, so undefined names are reported
+    /// even inside functions that are never called. Returns the warnings.
+    pub fn check(&mut self, src: &str) -> Result<Vec<String>, MonkeyError> {
+        let expanded = self.expand(parse(Lexer::new(src))?)?;
+        Ok(self.compile(expanded)?.warnings().to_vec())
+    }
+
+    /// Compiles `src` the way running it would and lists the bytecode of the
+    /// program and every function in it.
+    pub fn disassemble(&mut self, src: &str) -> Result<String, MonkeyError> {
+        let expanded = self.expand(parse(Lexer::new(src))?)?;
+        Ok(self.compile(expanded)?.bytecode().disassemble())
+    }
+
+    fn expand(&self, mut program: Vec<Statement>) -> Result<Node, MonkeyError> {
+        define_macros(&mut program, Rc::clone(&self.macro_env));
+        Ok(expand_macros(
+            Node::Program(program),
+            Rc::clone(&self.macro_env),
+        )?)
+    }
+
+    fn compile(&self, program: Node) -> Result<Compiler, MonkeyError> {
+        let mut compiler =
+            Compiler::new_with_state(Rc::clone(&self.symbol_table), Rc::clone(&self.constants));
+        compiler.set_keep_last_value(true);
+        compiler.compile(program)?;
+        Ok(compiler)
+    }
+}
+
+pub(super) fn parse(lexer: Lexer) -> Result<Vec<Statement>, MonkeyError> {
+    Parser::new(lexer)
+        .parse_program()
+        .map_err(MonkeyError::Parse)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::object::host::HostFunction;
+
+    #[test]
+    fn it_keeps_state_between_evals() {
+        let mut interpreter = Interpreter::new();
+        interpreter.define_global("base", Object::Integer(10));
+        interpreter.define_global(
+            "twice",
+            Object::HostFunction(HostFunction::new("twice", |args| match &*args[0] {
+                Object::Integer(i) => Ok(Rc::new(Object::Integer(i * 2))),
+                _ => Ok(Rc::new(Object::Null)),
+            })),
+        );
+
+        let result = interpreter
+            .eval("let add = fn(a, b) { a + b }; let x = add(base, 5);")
+            .unwrap();
+        assert_eq!(*result, Object::Null);
+
+        let result = interpreter.eval("twice(x) + len([1, 2])").unwrap();
+        assert_eq!(*result, Object::Integer(32));
+
+        let err = interpreter.eval("let y = ;").unwrap_err();
+        assert!(matches!(err, MonkeyError::Parse(_)));
+        assert_eq!(
+            err.to_string(),
+            "error at line 1, col 9: parse error: no prefix parse function for ; found"
+        );
+
+        let err = interpreter.eval("y").unwrap_err();
+        assert!(matches!(err, MonkeyError::Compile(_)));
+        assert_eq!(err.to_string(), "undefined variable: y");
+
+        let err = interpreter.eval("add(1)").unwrap_err();
+        assert!(matches!(err, MonkeyError::Vm(_)));
+        assert_eq!(
+            interpreter.eval("add(x, 1)").unwrap(),
+            Rc::new(Object::Integer(16))
+        );
+    }
+}
@@ -1,22 +1,26 @@
+pub mod error;
+pub mod interpreter;
+
+#[cfg(test)]
+mod differential;
+
 use anyhow::Result;
 use signal_hook::{consts::SIGINT, iterator::Signals};
 use strum_macros::{Display, EnumString};
 
-use crate::compiler::symbol_table::SymbolTable;
-use crate::compiler::Compiler;
-use crate::evaluator::{define_macros, evaluate, expand_macros};
+use self::error::MonkeyError;
+use self::interpreter::Interpreter;
 use crate::object::builtin::Builtin;
-use crate::object::environment::Environment;
 use crate::object::Object;
 use crate::utils;
-use crate::vm::{GLOBAL_SIZE, VM};
 
 use crate::lexer::Lexer;
-use crate::parser::ast::Node;
-use crate::parser::Parser;
+use crate::parser::ast::Statement;
+use crate::parser::errors::ParserErrors;
 use std::thread;
+use std::time::{Duration, SystemTime};
 use std::{
-    cell::RefCell,
+    fs,
     io::{self, Write},
     rc::Rc,
 };
@@ -29,51 +33,42 @@ pub enum ExecMode {
     Direct,
 }
 
+#[derive(Debug, Clone, Copy, EnumString, Display)]
+pub enum ResultFormat {
+    #[strum(serialize = "plain")]
+    Plain,
+    #[strum(serialize = "typed")]
+    Typed,
+    #[strum(serialize = "json")]
+    Json,
+}
+
 const PROMPT: &str = ">> ";
+const ENV_DUMP_DEPTH: usize = 4;
 
-pub fn repl(path: Option<String>, mode: ExecMode) -> Result<()> {
-    let env = Rc::new(RefCell::new(Environment::new()));
-    let macro_env = Rc::new(RefCell::new(Environment::new()));
+pub fn repl(
+    path: Option<String>,
+    mode: ExecMode,
+    suppress_null: bool,
+    format: ResultFormat,
+) -> Result<()> {
+    let mut interpreter = Interpreter::with_mode(mode);
     println!("Welcome to the Mokey Programming Language REPL!",);
 
-    let mut signals = Signals::new(&[SIGINT])?;
+    let mut signals = Signals::new([SIGINT])?;
 
     thread::spawn(move || {
         for sig in signals.forever() {
-            match sig {
-                SIGINT => {
-                    println!("Exiting REPL");
-                    std::process::exit(0);
-                }
-                _ => {}
+            if sig == SIGINT {
+                println!("Exiting REPL");
+                std::process::exit(0);
             }
         }
     });
 
-    let constants = Rc::new(RefCell::new(vec![]));
-    let symbol_table = SymbolTable::new();
-    for (i, v) in Builtin::variants().iter().enumerate() {
-        symbol_table.borrow_mut().define_builtin(i, v.to_string());
-    }
-    let globals = Rc::new(RefCell::new(vec![Rc::new(Object::Null); GLOBAL_SIZE]));
-
     if let Some(path) = path {
         let contents = utils::load_monkey(path)?;
-
-        let result = match mode {
-            ExecMode::Direct => {
-                interpret_direct(contents, Some(Rc::clone(&env)), Some(Rc::clone(&macro_env)))
-            }
-            ExecMode::VM => interpret_vm(
-                contents,
-                Some(Rc::clone(&macro_env)),
-                symbol_table.clone(),
-                constants.clone(),
-                globals.clone(),
-            ),
-        };
-
-        if let Err(err) = result {
+        if let Err(err) = run_chunk(&mut interpreter, contents, suppress_null, format) {
             eprintln!("{}", err);
         }
     }
@@ -89,120 +84,970 @@ pub fn repl(path: Option<String>, mode: ExecMode) -> Result<()> {
             std::process::exit(0);
         }
 
-        let result = match mode {
-            ExecMode::Direct => {
-                interpret_direct(line, Some(Rc::clone(&env)), Some(Rc::clone(&macro_env)))
+        if let Some(name) = line.trim().strip_prefix(":help") {
+            print_help(name.trim());
+            continue;
+        }
+
+        if line.trim() == ":env" {
+            match interpreter.mode() {
+                ExecMode::Direct => print!("{}", interpreter.env().borrow().dump(ENV_DUMP_DEPTH)),
+                ExecMode::VM => eprintln!(":env is only available in direct mode"),
             }
-            ExecMode::VM => interpret_vm(
-                line,
-                Some(Rc::clone(&macro_env)),
-                symbol_table.clone(),
-                constants.clone(),
-                globals.clone(),
-            ),
-        };
+            continue;
+        }
 
-        if let Err(err) = result {
+        if let Err(err) = run_chunk(&mut interpreter, line, suppress_null, format) {
             eprintln!("{}", err);
         }
     }
 }
 
-pub fn interpret_chunk(mode: ExecMode, contents: String) -> Result<()> {
-    let env = Rc::new(RefCell::new(Environment::new()));
-    let macro_env = Rc::new(RefCell::new(Environment::new()));
-
-    let constants = Rc::new(RefCell::new(vec![]));
-    let symbol_table = SymbolTable::new();
-    for (i, v) in Builtin::variants().iter().enumerate() {
-        symbol_table.borrow_mut().define_builtin(i, v.to_string());
+// `:help` lists every builtin's signature, `:help name` describes one
+fn print_help(name: &str) {
+    if name.is_empty() {
+        for variant in Builtin::variants() {
+            if let Some(Object::Builtin(builtin)) = Builtin::lookup(variant) {
+                println!("{}", builtin.signature());
+            }
+        }
+        return;
     }
-    let globals = Rc::new(RefCell::new(vec![Rc::new(Object::Null); GLOBAL_SIZE]));
 
-    let result = match mode {
-        ExecMode::Direct => {
-            interpret_direct(contents, Some(Rc::clone(&env)), Some(Rc::clone(&macro_env)))
-        }
-        ExecMode::VM => interpret_vm(
-            contents,
-            Some(Rc::clone(&macro_env)),
-            symbol_table.clone(),
-            constants.clone(),
-            globals.clone(),
-        ),
-    };
+    match Builtin::lookup(name) {
+        Some(Object::Builtin(builtin)) => println!("{}", builtin.help()),
+        _ => eprintln!("no builtin named {}", name),
+    }
+}
 
-    if let Err(err) = result {
+pub fn interpret_chunk(
+    mode: ExecMode,
+    contents: String,
+    suppress_null: bool,
+    format: ResultFormat,
+) -> Result<()> {
+    let mut interpreter = Interpreter::with_mode(mode);
+    if let Err(err) = run_chunk(&mut interpreter, contents, suppress_null, format) {
         eprintln!("{}", err);
     }
-
     Ok(())
 }
 
-pub fn interpret_direct(
+// Runs one repl line or file, printing parser errors, warnings and, on the
+// vm, the result.
+fn run_chunk(
+    interpreter: &mut Interpreter,
     contents: String,
-    env: Option<Rc<RefCell<Environment>>>,
-    macro_env: Option<Rc<RefCell<Environment>>>,
+    suppress_null: bool,
+    format: ResultFormat,
 ) -> Result<()> {
-    let env = env.unwrap_or_else(|| Rc::new(RefCell::new(Environment::new())));
-    let macro_env = macro_env.unwrap_or_else(|| Rc::new(RefCell::new(Environment::new())));
-
-    let lexer = Lexer::new(&contents);
-    let mut parser = Parser::new(lexer.into());
-    let program = parser.parse_program();
-    if let Ok(mut program) = program {
-        define_macros(&mut program, Rc::clone(&macro_env));
-        let expanded =
-            expand_macros(Node::Program(program.clone()), Rc::clone(&macro_env)).unwrap();
-        evaluate(expanded, Rc::clone(&env))?;
-    } else if let Err(err) = &program {
-        println!("Woops! We ran into some monkey business here!");
-        println!("parser errors:");
-        for e in err {
-            eprintln!("\t{}", e);
+    let program = match interpreter::parse(Lexer::from_string(contents)) {
+        Ok(program) => program,
+        Err(MonkeyError::Parse(errors)) => {
+            print_parser_errors(&errors);
+            return Ok(());
         }
+        Err(err) => return Err(err.into()),
+    };
+
+    let silenced = ends_with_semicolon(&program);
+    let (result, warnings) = interpreter.run(program)?;
+    for warning in warnings {
+        eprintln!("warning: {}", warning);
+    }
+    if silenced || matches!(interpreter.mode(), ExecMode::Direct) {
+        return Ok(());
+    }
+    if let Some(output) = result.and_then(|r| format_result(&r, suppress_null, format)) {
+        println!("{}", output);
     }
     Ok(())
 }
 
-pub fn interpret_vm(
-    contents: String,
-    macro_env: Option<Rc<RefCell<Environment>>>,
-    symbol_table: Rc<RefCell<SymbolTable>>,
-    constants: Rc<RefCell<Vec<Rc<Object>>>>,
-    globals: Rc<RefCell<Vec<Rc<Object>>>>,
-) -> Result<()> {
-    // let env = env.unwrap_or_else(|| Rc::new(RefCell::new(Environment::new())));
-    let macro_env = macro_env.unwrap_or_else(|| Rc::new(RefCell::new(Environment::new())));
-
-    let lexer = Lexer::new(&contents);
-    let mut parser = Parser::new(lexer.into());
-    let program = parser.parse_program();
-
-    match program {
-        Ok(program) => {
-            // expand macros
-            define_macros(&mut program.clone(), Rc::clone(&macro_env));
-            let expanded = expand_macros(Node::Program(program), Rc::clone(&macro_env)).unwrap();
-
-            // compile
-            let mut compiler = Compiler::new_with_state(symbol_table, constants);
-            compiler.compile(expanded)?;
-
-            let code = compiler.bytecode();
-
-            let mut machine = VM::new_with_global_store(code, globals);
-            machine.run()?;
-            let last_elem = machine.last_popped_stack_elem();
-            println!("{}", last_elem);
-        }
-        Err(err) => {
-            println!("Woops! We ran into some monkey business here!");
-            println!("parser errors:");
-            for e in err {
-                eprintln!("\t{}", e);
+/// Runs `src` with nothing defined beforehand and returns the value of its
+/// final expression, or null when it ends in a statement. Nothing is printed
+/// apart from what the program itself outputs.
+pub fn run_string(mode: ExecMode, src: &str) -> Result<Rc<Object>> {
+    Ok(Interpreter::with_mode(mode).eval(src)?)
+}
+
+fn print_parser_errors(errors: &ParserErrors) {
+    println!("Woops! We ran into some monkey business here!");
+    println!("parser errors:");
+    for e in errors {
+        eprintln!("\t{}", e);
+    }
+}
+
+// `40 + 2;` runs like `40 + 2` but the repl keeps quiet about its value
+fn ends_with_semicolon(program: &[Statement]) -> bool {
+    matches!(program.last(), Some(Statement::Expression(_, true)))
+}
+
+/// Parses, expands and compiles `contents` without running it, so undefined
+/// names are reported even inside functions that are never called.
+pub fn check(contents: String) -> Result<()> {
+    for warning in Interpreter::new().check(&contents)? {
+        eprintln!("warning: {}", warning);
+    }
+    Ok(())
+}
+
+/// Compiles `contents` the way running it would and lists the bytecode of the
+/// program and every function in it.
+pub fn disassemble(contents: String) -> Result<String> {
+    Ok(Interpreter::new().disassemble(&contents)?)
+}
+
+/// Runs the file at `path`, then runs it again each time `changes` yields,
+/// clearing the screen in between. Errors are printed and watching carries on.
+pub fn watch<F>(path: &str, changes: impl IntoIterator<Item = ()>, mut run: F)
+where
+    F: FnMut(String) -> Result<()>,
+{
+    run_watched(path, &mut run);
+    for () in changes {
+        print!("\x1b[2J\x1b[H");
+        let _ = io::stdout().flush();
+        run_watched(path, &mut run);
+    }
+}
+
+fn run_watched<F>(path: &str, run: &mut F)
+where
+    F: FnMut(String) -> Result<()>,
+{
+    if let Err(e) = utils::load_monkey(path.to_string()).and_then(run) {
+        eprintln!("Error: {}", e);
+    }
+}
+
+/// Yields whenever the modification time of `path` changes, checking every
+/// `interval`. A file that disappears and comes back counts as a change.
+pub fn poll_changes(path: &str, interval: Duration) -> impl Iterator<Item = ()> {
+    let path = path.to_string();
+    let modified = |path: &str| -> Option<SystemTime> { fs::metadata(path).ok()?.modified().ok() };
+    let mut last = modified(&path);
+    std::iter::from_fn(move || loop {
+        thread::sleep(interval);
+        let current = modified(&path);
+        if current != last {
+            last = current;
+            return Some(());
+        }
+    })
+}
+
+/// Renders an evaluation result for printing, or `None` if it should be hidden.
+/// With `suppress_null` set, a bare `null` (e.g. the result of `echoln`) is not shown.
+pub fn format_result(result: &Object, suppress_null: bool, format: ResultFormat) -> Option<String> {
+    match result {
+        Object::Null if suppress_null => None,
+        _ => Some(match format {
+            ResultFormat::Plain => result.to_string(),
+            ResultFormat::Typed => format!("{} : {}", result, result.type_name()),
+            ResultFormat::Json => result.to_json(),
+        }),
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::rc_clone_in_vec_init)]
+mod test {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::evaluator::error::EvaluatorError;
+    use crate::evaluator::evaluate;
+    use crate::object::environment::Environment;
+    use crate::object::error::ObjectError;
+    use crate::object::host::HostFunction;
+    use crate::object::output;
+    use crate::parser::ast::Node;
+    use crate::parser::Parser;
+    use crate::vm::VM;
+    use anyhow::anyhow;
+    use std::cell::RefCell;
+
+    fn run_direct(input: &str) -> Rc<Object> {
+        let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+        let env = Rc::new(RefCell::new(Environment::new()));
+        evaluate(Node::Program(program), env).unwrap()
+    }
+
+    fn run_vm(input: &str) -> Rc<Object> {
+        let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+        let mut compiler = Compiler::new();
+        compiler.compile(Node::Program(program)).unwrap();
+        let mut machine = VM::new(compiler.bytecode());
+        machine.run().unwrap();
+        machine.last_popped_stack_elem()
+    }
+
+    #[test]
+    fn it_reruns_watched_files_on_each_change() {
+        let path = std::env::temp_dir().join(format!("monkey-watch-{}.monkey", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        fs::write(&path, "1 + 1").unwrap();
+
+        // each injected change rewrites the file before the watcher sees it
+        let mut edits = vec!["let;", "2 * 3"].into_iter();
+        let changes = std::iter::from_fn(|| {
+            fs::write(&path, edits.next()?).unwrap();
+            Some(())
+        });
+
+        let mut runs = vec![];
+        watch(&path, changes, |contents| {
+            let program = Parser::new(Lexer::from_string(contents.clone()))
+                .parse_program()
+                .map_err(|_| anyhow!("parse error"));
+            runs.push(contents);
+            let env = Rc::new(RefCell::new(Environment::new()));
+            evaluate(Node::Program(program?), env)?;
+            Ok(())
+        });
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(runs, vec!["1 + 1", "let;", "2 * 3"]);
+    }
+
+    #[test]
+    fn it_silences_results_of_semicolon_terminated_statements() {
+        let shown = |input: &str| {
+            let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+            if ends_with_semicolon(&program) {
+                None
+            } else {
+                format_result(&run_vm(input), false, ResultFormat::Plain)
             }
+        };
+
+        assert_eq!(shown("40 + 2"), Some("42".to_string()));
+        assert_eq!(shown("40 + 2;"), None);
+        assert_eq!(shown("40 + 2; 1"), Some("1".to_string()));
+        assert_eq!(shown("if (true) { 1; }"), Some("1".to_string()));
+    }
+
+    #[test]
+    fn it_converts_values_to_strings_identically_across_backends() {
+        let tests = vec![
+            ("str(5)", "5"),
+            ("str(-5)", "-5"),
+            ("str(true)", "true"),
+            (r#"str("hi")"#, "hi"),
+            ("str(null)", "null"),
+            ("str([1, 2, 3])", "[1, 2, 3]"),
+            ("str([])", "[]"),
+            ("str({1: 2})", "{1: 2}"),
+            (r#"str({"b": 2, "a": 1, 3: 0})"#, "{3: 0, a: 1, b: 2}"),
+            (
+                r#"str({"outer": {2: [true], 1: {}}})"#,
+                "{outer: {1: {}, 2: [true]}}",
+            ),
+            (r#""count: " + str(5)"#, "count: 5"),
+            (r#"str(str(1) + str(2))"#, "12"),
+        ];
+
+        for (input, expected) in tests {
+            let expected = Object::String(expected.to_string());
+            assert_eq!(*run_direct(input), expected, "{}", input);
+            assert_eq!(*run_vm(input), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn it_names_types_identically_across_backends() {
+        let tests = vec![
+            ("type(1)", "INTEGER"),
+            ("type(1.5)", "FLOAT"),
+            ("type(true)", "BOOLEAN"),
+            (r#"type("")"#, "STRING"),
+            ("type([])", "ARRAY"),
+            ("type({})", "HASH"),
+            ("type(if (false) { 1 })", "NULL"),
+            ("type(fn(x) { x })", "FUNCTION"),
+            ("let n = 1; type(fn() { n })", "FUNCTION"),
+            ("type(memoize(fn(x) { x }))", "FUNCTION"),
+            ("type(len)", "BUILTIN"),
+            ("type(type(1))", "STRING"),
+        ];
+
+        for (input, expected) in tests {
+            let expected = Object::String(expected.to_string());
+            assert_eq!(*run_direct(input), expected, "{}", input);
+            assert_eq!(*run_vm(input), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn it_builds_hash_literals_identically_across_backends() {
+        let tests = vec![
+            "{2: 1, 1: 2}",
+            "{1: 2, 1: 3}",
+            r#"{"b": 1, "a": 2, true: 3, 0: 4}"#,
+            r#"{[1 + 1]: "two"}[2]"#,
+            r#"let k = "key"; {[k + "s"]: 1, [len(k)]: 2}"#,
+        ];
+
+        for input in tests {
+            assert_eq!(run_direct(input), run_vm(input), "{}", input);
+        }
+    }
+
+    #[test]
+    fn it_rejects_unhashable_keys_identically_across_backends() {
+        let tests = vec![
+            ("{fn(x) { x }: 1}", "unusable as hash key: FUNCTION"),
+            ("{1: 1, len: 2}", "unusable as hash key: BUILTIN"),
+            ("{[[1]]: 1}", "unusable as hash key: ARRAY"),
+            ("{{}: 1}", "unusable as hash key: HASH"),
+            ("{1.5: 1}", "unusable as hash key: FLOAT"),
+            ("{null: 1}", "unusable as hash key: NULL"),
+            ("{1: 2}[[1]]", "unusable as hash key: ARRAY"),
+            (
+                "let f = fn() { 1 }; {1: 2}[f]",
+                "unusable as hash key: FUNCTION",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+            let env = Rc::new(RefCell::new(Environment::new()));
+            let direct = evaluate(Node::Program(program.clone()), env).unwrap_err();
+            assert_eq!(
+                direct,
+                EvaluatorError::new(expected.to_string()),
+                "{}",
+                input
+            );
+
+            let mut compiler = Compiler::new();
+            compiler.compile(Node::Program(program)).unwrap();
+            let vm = VM::new(compiler.bytecode()).run().unwrap_err();
+            assert_eq!(vm.msg, expected, "{}", input);
+        }
+
+        let hashable = r#"let h = {1: "a", true: "b", "k": "c"}; h[1] + h[true] + h["k"]"#;
+        let expected = Object::String("abc".to_string());
+        assert_eq!(*run_direct(hashable), expected);
+        assert_eq!(*run_vm(hashable), expected);
+    }
+
+    #[test]
+    fn it_does_not_match_hash_keys_across_types() {
+        let tests = vec![
+            (r#"{1: "a"}[1]"#, Object::String("a".to_string())),
+            (r#"{[1 + 1]: "two"}[2]"#, Object::String("two".to_string())),
+            (r#"{1: "a"}[true]"#, Object::Null),
+            (r#"{true: "a"}[1]"#, Object::Null),
+            (r#"{1: "a"}["1"]"#, Object::Null),
+            (r#"{"true": "a"}[true]"#, Object::Null),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(*run_direct(input), expected, "{}", input);
+            assert_eq!(*run_vm(input), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn it_calls_registered_host_functions() {
+        for mode in [ExecMode::Direct, ExecMode::VM] {
+            let mut interpreter = Interpreter::with_mode(mode.clone());
+            let double = HostFunction::new("double", |args| match &*args[0] {
+                Object::Integer(i) => Ok(Rc::new(Object::Integer(i * 2))),
+                other => Err(ObjectError::new(format!(
+                    "argument to `double` not supported, got {}",
+                    other
+                ))),
+            });
+            interpreter.define_global("double", Object::HostFunction(double));
+
+            let input = "let x = fn(y) { double(y) + 1 }; x(double(5));";
+            assert_eq!(
+                *interpreter.eval(input).unwrap(),
+                Object::Integer(21),
+                "{}",
+                mode
+            );
+
+            let err = interpreter.eval("double(true)").unwrap_err();
+            assert!(
+                err.to_string()
+                    .contains("argument to `double` not supported, got true"),
+                "{}: {}",
+                mode,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn it_resolves_self_references_identically_across_backends() {
+        let tests = vec![
+            "let f = fn(n) { if (n == 0) { 0 } else { f(n - 1) } }; f(3);",
+            "let o = fn() { let i = fn(n) { if (n == 0) { 7 } else { i(n - 1) } }; i(3) }; o();",
+            "let x = 1; let f = fn() { let x = x + 1; x }; f();",
+            "let x = 1; let x = x + 1; x;",
+        ];
+
+        for input in tests {
+            assert_eq!(run_direct(input), run_vm(input), "{}", input);
+        }
+
+        let program = Parser::new(Lexer::new("let x = x;"))
+            .parse_program()
+            .unwrap();
+        let err = Compiler::new().compile(Node::Program(program)).unwrap_err();
+        assert_eq!(err.to_string(), "undefined variable: x");
+    }
+
+    #[test]
+    fn it_balances_conditionals_identically_across_backends() {
+        let tests = vec![
+            // value position
+            "let x = if (true) { 1 } else { 2 }; x;",
+            "let x = if (false) { 1 } else { 2 }; x;",
+            "let x = if (false) { 1 }; x;",
+            "let x = if (true) {}; x;",
+            "let x = if (true) { let y = 1; }; x;",
+            "let x = if (false) { 1 } else { let y = 2; }; x;",
+            "let f = fn(c) { let x = if (c) { 1 } else { 2 }; x * 10 }; [f(true), f(false)];",
+            "let f = fn() { if (true) { let a = 1; } }; f();",
+            "let f = fn() { let a = 1; }; f();",
+            "let f = fn(c) { let x = if (c) { return 1; } else { 2 }; x * 10 }; f(true) + f(false);",
+            "[if (true) {}, if (false) { 1 }, if (true) { 1; 2 }];",
+            // statement position
+            "1; if (true) {}; 2;",
+            "if (true) { let y = 1; }; if (false) { 1 } else {}; 3;",
+            "let f = fn() { if (true) {}; if (false) { 1 }; 4 }; f();",
+        ];
+
+        for input in tests {
+            assert_eq!(run_direct(input), run_vm(input), "{}", input);
+
+            let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+            let mut compiler = Compiler::new();
+            compiler.compile(Node::Program(program)).unwrap();
+            let mut machine = VM::new(compiler.bytecode());
+            machine.run().unwrap();
+            assert_eq!(machine.sp, 0, "{}", input);
+        }
+    }
+
+    #[test]
+    fn it_lets_the_last_duplicate_hash_key_win() {
+        let input = "{1: 2, 1: 3}[1] == 3";
+        assert_eq!(run_direct(input), Rc::new(Object::Boolean(true)));
+        assert_eq!(run_vm(input), Rc::new(Object::Boolean(true)));
+
+        let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+        let mut compiler = Compiler::new();
+        compiler.compile(Node::Program(program)).unwrap();
+        assert_eq!(
+            compiler.warnings(),
+            ["duplicate hash key 1, the last value wins"]
+        );
+    }
+
+    #[test]
+    fn it_agrees_on_truthiness_across_backends() {
+        let tests = vec![
+            ("0", true),
+            ("1", true),
+            ("\"\"", true),
+            ("\"a\"", true),
+            ("[]", true),
+            ("[0]", true),
+            ("{}", true),
+            ("fn() {}", true),
+            ("true", true),
+            ("false", false),
+            ("if (false) { 1 }", false),
+        ];
+
+        for (value, truthy) in tests {
+            let branch = format!("if ({}) {{ true }} else {{ false }}", value);
+            let bang = format!("!!({})", value);
+            for input in [&branch, &bang] {
+                let expected = Rc::new(Object::Boolean(truthy));
+                assert_eq!(run_direct(input), expected, "{}", input);
+                assert_eq!(run_vm(input), expected, "{}", input);
+            }
+        }
+    }
+
+    #[test]
+    fn it_reports_calls_on_non_callables_identically_across_backends() {
+        let tests = vec![
+            ("5();", "not callable: int (5)"),
+            (r#""hi"(1);"#, "not callable: string (hi)"),
+            ("let x = [1]; x[0]();", "not callable: int (1)"),
+        ];
+
+        for (input, expected) in tests {
+            let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+            let env = Rc::new(RefCell::new(Environment::new()));
+            let direct = evaluate(Node::Program(program.clone()), env).unwrap_err();
+            assert_eq!(
+                direct,
+                EvaluatorError::new(expected.to_string()),
+                "{}",
+                input
+            );
+
+            let mut compiler = Compiler::new();
+            compiler.compile(Node::Program(program)).unwrap();
+            let vm = VM::new(compiler.bytecode()).run().unwrap_err();
+            assert_eq!(vm.msg, expected, "{}", input);
+        }
+
+        assert!(!Object::Integer(5).is_callable());
+        assert!(Object::Builtin(Builtin::Len).is_callable());
+    }
+
+    #[test]
+    fn it_compares_mismatched_types_identically_across_backends() {
+        let operands = vec![
+            ("5", "INTEGER"),
+            ("1.5", "FLOAT"),
+            ("true", "BOOLEAN"),
+            (r#""5""#, "STRING"),
+            ("[5]", "ARRAY"),
+            ("{5: 5}", "HASH"),
+            ("if (false) { 1 }", "NULL"),
+        ];
+
+        for (left, left_type) in &operands {
+            for (right, right_type) in &operands {
+                let numeric = ["INTEGER", "FLOAT"];
+                if left_type == right_type
+                    || (numeric.contains(left_type) && numeric.contains(right_type))
+                {
+                    continue;
+                }
+
+                let input = format!("({}) == ({})", left, right);
+                assert_eq!(*run_direct(&input), Object::Boolean(false), "{}", input);
+                assert_eq!(*run_vm(&input), Object::Boolean(false), "{}", input);
+                let input = format!("({}) != ({})", left, right);
+                assert_eq!(*run_direct(&input), Object::Boolean(true), "{}", input);
+                assert_eq!(*run_vm(&input), Object::Boolean(true), "{}", input);
+
+                let expected = format!("cannot compare {} and {}", left_type, right_type);
+                for operator in ["<", ">", "<=", ">="] {
+                    let input = format!("({}) {} ({})", left, operator, right);
+                    let program = Parser::new(Lexer::from_string(input.clone()))
+                        .parse_program()
+                        .unwrap();
+                    let env = Rc::new(RefCell::new(Environment::new()));
+                    let direct = evaluate(Node::Program(program.clone()), env).unwrap_err();
+                    assert_eq!(direct, EvaluatorError::new(expected.clone()), "{}", input);
+
+                    let mut compiler = Compiler::new();
+                    compiler.compile(Node::Program(program)).unwrap();
+                    let vm = VM::new(compiler.bytecode()).run().unwrap_err();
+                    assert_eq!(vm.msg, expected, "{}", input);
+                }
+            }
+        }
+    }
+
+    // runs `input` in both backends with a `tick()` host function, returning
+    // each backend's result and how many times `tick` was called
+    fn run_counting_ticks(input: &str) -> Vec<(Rc<Object>, usize)> {
+        [ExecMode::Direct, ExecMode::VM]
+            .into_iter()
+            .map(|mode| {
+                let ticks = Rc::new(RefCell::new(0));
+                let counter = Rc::clone(&ticks);
+                let tick = HostFunction::new("tick", move |_| {
+                    *counter.borrow_mut() += 1;
+                    Ok(Rc::new(Object::Null))
+                });
+                let mut interpreter = Interpreter::with_mode(mode);
+                interpreter.define_global("tick", Object::HostFunction(tick));
+                let result = interpreter.eval(input).unwrap();
+                let ticks = *ticks.borrow();
+                (result, ticks)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn it_coalesces_null_identically_across_backends() {
+        let tests = vec![
+            ("null ?? 5 == 5", Object::Boolean(true)),
+            ("(null ?? 5) == 5", Object::Boolean(true)),
+            ("1 ?? 5", Object::Integer(1)),
+            ("false ?? 5", Object::Boolean(false)),
+            ("{}[1] ?? 0", Object::Integer(0)),
+            ("null ?? null ?? 3", Object::Integer(3)),
+            ("let x = if (false) { 1 }; x ?? 2", Object::Integer(2)),
+            ("null", Object::Null),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(*run_direct(input), expected, "{}", input);
+            assert_eq!(*run_vm(input), expected, "{}", input);
+        }
+
+        // the right side only runs when the left one is null
+        for (result, ticks) in run_counting_ticks("1 ?? tick()") {
+            assert_eq!(*result, Object::Integer(1));
+            assert_eq!(ticks, 0);
+        }
+        for (_, ticks) in run_counting_ticks("null ?? tick()") {
+            assert_eq!(ticks, 1);
+        }
+    }
+
+    #[test]
+    fn it_memoizes_pure_functions() {
+        let plain = "let fib = fn(n) { tick(); if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(15);";
+        let memoized = "let fib = 0; fib = memoize(fn(n) { tick(); if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }); fib(15); fib(15);";
+
+        for (result, ticks) in run_counting_ticks(plain) {
+            assert_eq!(*result, Object::Integer(610));
+            assert_eq!(ticks, 1973);
+        }
+        // one call per distinct argument, and none for the repeated `fib(15)`
+        for (result, ticks) in run_counting_ticks(memoized) {
+            assert_eq!(*result, Object::Integer(610));
+            assert_eq!(ticks, 16);
+        }
+    }
+
+    #[test]
+    fn it_refuses_to_memoize_impure_functions() {
+        let tests = vec![
+            (
+                "memoize(fn(x) { echo(x); x })",
+                "argument to `memoize` must be a pure function, got ",
+            ),
+            (
+                "let total = 0; memoize(fn(x) { total = total + x })",
+                "argument to `memoize` must be a pure function, got ",
+            ),
+            // effects in a function the argument creates count too
+            (
+                "memoize(fn() { fn() { echo(1) } })",
+                "argument to `memoize` must be a pure function, got ",
+            ),
+            (
+                "let total = 0; memoize(fn() { fn(x) { fn() { total = x } } })",
+                "argument to `memoize` must be a pure function, got ",
+            ),
+            (
+                "memoize(echoln)",
+                "argument to `memoize` must be a pure function, got echoln",
+            ),
+            (
+                "memoize(5)",
+                "argument to `memoize` must be FUNCTION, got 5",
+            ),
+            (
+                "memoize(fn(x) { x })([1])",
+                "memoized function arguments must be hashable, got array",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+            let env = Rc::new(RefCell::new(Environment::new()));
+            let direct = evaluate(Node::Program(program.clone()), env).unwrap_err();
+            assert!(
+                direct.to_string().contains(expected),
+                "{}: {}",
+                input,
+                direct
+            );
+
+            let mut compiler = Compiler::new();
+            compiler.compile(Node::Program(program)).unwrap();
+            let vm = VM::new(compiler.bytecode()).run().unwrap_err();
+            assert!(vm.msg.contains(expected), "{}: {}", input, vm);
+        }
+
+        // locals and builtins without effects are fine
+        let input = "let f = memoize(fn(x) { let y = len([x]); y + x }); [f(1), f(1), memoize(len)(\"ab\")]";
+        assert_eq!(run_direct(input), run_vm(input));
+    }
+
+    #[test]
+    fn it_evaluates_comparison_operands_in_source_order() {
+        let input =
+            "[tick(1) < tick(2), tick(3) > tick(4), tick(5) == tick(6), tick(7) != tick(8)];";
+        let expected = ["1", "2", "3", "4", "5", "6", "7", "8"];
+
+        let mut results = vec![];
+        for mode in [ExecMode::Direct, ExecMode::VM] {
+            let calls = Rc::new(RefCell::new(Vec::new()));
+            let recorded = Rc::clone(&calls);
+            let tick = HostFunction::new("tick", move |args| {
+                recorded.borrow_mut().push(args[0].to_string());
+                Ok(Rc::clone(&args[0]))
+            });
+            let mut interpreter = Interpreter::with_mode(mode.clone());
+            interpreter.define_global("tick", Object::HostFunction(tick));
+            results.push(interpreter.eval(input).unwrap());
+            assert_eq!(*calls.borrow(), expected, "{}", mode);
+        }
+        assert_eq!(results[0], results[1]);
+    }
+
+    #[test]
+    fn it_checks_functions_that_are_never_called() {
+        let input = "let f = fn(x) { x + y }; 1;";
+        run_string(ExecMode::Direct, input).unwrap();
+        assert_eq!(
+            check(input.to_string()).unwrap_err().to_string(),
+            "undefined variable: y"
+        );
+
+        let input = "let unused = macro(a) { quote(unquote(a) + z) }; let f = fn(x) { len(x) };";
+        assert!(check(input.to_string()).is_ok());
+        assert_eq!(
+            check("let x = ;".to_string()).unwrap_err().to_string(),
+            "error at line 1, col 9: parse error: no prefix parse function for ; found"
+        );
+    }
+
+    #[test]
+    fn it_keeps_macros_across_repl_lines() {
+        let lines = [
+            "let unless = macro(c, a, b) { quote(if (!(unquote(c))) { unquote(a) } else { unquote(b) }) };",
+            "let r = unless(1 > 2, 10, 20);",
+            "let s = unless(r > 2, 30, 40);",
+        ];
+
+        for mode in [ExecMode::Direct, ExecMode::VM] {
+            let mut interpreter = Interpreter::with_mode(mode.clone());
+            for line in lines {
+                run_chunk(
+                    &mut interpreter,
+                    line.to_string(),
+                    true,
+                    ResultFormat::Plain,
+                )
+                .unwrap();
+            }
+            assert_eq!(
+                *interpreter.eval("r").unwrap(),
+                Object::Integer(10),
+                "{}",
+                mode
+            );
+            assert_eq!(
+                *interpreter.eval("s").unwrap(),
+                Object::Integer(40),
+                "{}",
+                mode
+            );
+        }
+    }
+
+    #[test]
+    fn it_keeps_the_repl_going_after_runtime_errors() {
+        let mut interpreter = Interpreter::new();
+        let mut run = |line: &str| {
+            run_chunk(
+                &mut interpreter,
+                line.to_string(),
+                true,
+                ResultFormat::Plain,
+            )
+        };
+
+        run("let a = 6;").unwrap();
+        let err = run("a / 0").unwrap_err();
+        assert_eq!(err.to_string(), "Division by zero");
+        let err = run("let f = fn(n) { 1 + f(n + 1) }; f(0)").unwrap_err();
+        assert_eq!(err.to_string(), "stack overflow");
+        run("let b = a / 2;").unwrap();
+
+        assert_eq!(*interpreter.eval("b").unwrap(), Object::Integer(3));
+    }
+
+    #[test]
+    fn it_removes_macro_definitions_before_running() {
+        let input = r#"
+        let unless = macro(c, a, b) { quote(if (!(unquote(c))) { unquote(a) } else { unquote(b) }) };
+        unless(1 > 2, 10, 20);
+        "#;
+
+        let mut interpreter = Interpreter::with_mode(ExecMode::Direct);
+        interpreter.eval(input).unwrap();
+        assert_eq!(interpreter.env.borrow().get("unless"), None);
+
+        let mut interpreter = Interpreter::new();
+        interpreter.eval(input).unwrap();
+        assert_eq!(
+            interpreter.symbol_table.borrow_mut().resolve("unless"),
+            None
+        );
+        assert_eq!(
+            *interpreter.constants.borrow(),
+            vec![
+                Rc::new(Object::Integer(1)),
+                Rc::new(Object::Integer(2)),
+                Rc::new(Object::Integer(10)),
+                Rc::new(Object::Integer(20))
+            ]
+        );
+    }
+
+    #[test]
+    fn it_stops_programs_that_print_too_much() {
+        #[derive(Clone, Default)]
+        struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+        impl Write for Buffer {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.borrow_mut().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let buffer = Buffer::default();
+        output::set_sink(Box::new(buffer.clone()));
+
+        let input = r#"echoln("first"); echo("second"); echo("never")"#;
+        let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+
+        output::set_limit(Some(10));
+        let env = Rc::new(RefCell::new(Environment::new()));
+        let err = evaluate(Node::Program(program.clone()), env).unwrap_err();
+        assert_eq!(err.to_string(), "Object error: output limit exceeded");
+        assert_eq!(*buffer.0.borrow(), b"first\nseco");
+
+        buffer.0.borrow_mut().clear();
+        output::set_limit(Some(10));
+        let mut compiler = Compiler::new();
+        compiler.compile(Node::Program(program)).unwrap();
+        let err = VM::new(compiler.bytecode()).run().unwrap_err();
+        assert_eq!(err.to_string(), "output limit exceeded");
+        assert_eq!(*buffer.0.borrow(), b"first\nseco");
+    }
+
+    #[test]
+    fn it_returns_results_from_run_string() {
+        for mode in [ExecMode::VM, ExecMode::Direct] {
+            let run = |src: &str| run_string(mode.clone(), src).unwrap();
+            assert_eq!(*run("1+2"), Object::Integer(3), "{}", mode);
+            assert_eq!(*run("let x = 5; x * 2;"), Object::Integer(10), "{}", mode);
+            assert_eq!(*run("let x = 5;"), Object::Null, "{}", mode);
+            assert_eq!(run(r#"[1, "two"]"#).to_string(), "[1, two]", "{}", mode);
+            assert_eq!(
+                *run("if (true) { return 5; }; 10"),
+                Object::Integer(5),
+                "{}",
+                mode
+            );
+            assert_eq!(
+                *run("let unless = macro(c, a, b) { quote(if (!(unquote(c))) { unquote(a) } else { unquote(b) }) }; unless(false, 1, 2)"),
+                Object::Integer(1),
+                "{}",
+                mode
+            );
+
+            // every call starts from nothing
+            run_string(mode.clone(), "let leftover = 1;").unwrap();
+            assert!(run_string(mode.clone(), "leftover").is_err(), "{}", mode);
+
+            let err = run_string(mode.clone(), "let = 1").unwrap_err();
+            assert!(err.to_string().contains("parse error"), "{}", err);
+            let err = run_string(mode.clone(), "1 / 0").unwrap_err();
+            assert!(
+                err.to_string().to_lowercase().contains("division by zero"),
+                "{}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn it_reports_macros_that_do_not_return_quotes() {
+        let input = "let bad = macro() { 1 }; bad();";
+        let expected =
+            "unexpected object type: 1 - we only support returning AST-nodes from macros";
+
+        for mode in [ExecMode::Direct, ExecMode::VM] {
+            let err = run_string(mode, input).unwrap_err();
+            assert!(err.to_string().contains(expected), "{}", err);
+        }
+    }
+
+    #[test]
+    fn it_reports_errors_from_unquoted_expressions() {
+        let tests = vec![
+            (
+                "let m = macro() { quote(unquote(1 / 0)) }; m()",
+                "division by zero",
+            ),
+            (
+                "let m = macro() { quote(unquote(undefinedthing)) }; m()",
+                "identifier not found: undefinedthing",
+            ),
+        ];
+        for mode in [ExecMode::VM, ExecMode::Direct] {
+            for (input, expected) in &tests {
+                let err = run_string(mode.clone(), input).unwrap_err();
+                assert!(
+                    err.to_string().to_lowercase().contains(expected),
+                    "{}: {}",
+                    mode,
+                    err
+                );
+            }
+        }
+
+        // outside a macro only the evaluator runs quotes
+        let err = run_string(ExecMode::Direct, "quote(unquote(1 / 0))").unwrap_err();
+        assert!(
+            err.to_string().to_lowercase().contains("division by zero"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn it_suppresses_null_results() {
+        let format = ResultFormat::Plain;
+        assert_eq!(format_result(&Object::Null, true, format), None);
+        assert_eq!(
+            format_result(&Object::Null, false, format),
+            Some("null".to_string())
+        );
+        assert_eq!(
+            format_result(&Object::Integer(5), true, format),
+            Some("5".to_string())
+        );
+    }
+
+    #[test]
+    fn it_formats_results() {
+        let integer = run_vm("1 + 2");
+        let array = run_vm(r#"[1, "two", [true]]"#);
+        let tests = vec![
+            (&integer, ResultFormat::Plain, "3"),
+            (&integer, ResultFormat::Typed, "3 : int"),
+            (&integer, ResultFormat::Json, "3"),
+            (&array, ResultFormat::Plain, "[1, two, [true]]"),
+            (&array, ResultFormat::Typed, "[1, two, [true]] : array"),
+            (&array, ResultFormat::Json, r#"[1,"two",[true]]"#),
+        ];
+
+        for (result, format, expected) in tests {
+            assert_eq!(
+                format_result(result, false, format),
+                Some(expected.to_string()),
+                "{}",
+                format
+            );
         }
     }
-    Ok(())
 }
@@ -2,8 +2,13 @@ use std::fmt;
 use std::rc::Rc;
 
 use super::error::ObjectError;
+use super::memo::Memo;
+use super::output;
+use super::purity;
 use super::Object;
 
+pub type Call<'a, E> = dyn FnMut(&Rc<Object>, &[Rc<Object>]) -> Result<Rc<Object>, E> + 'a;
+
 #[derive(Debug, PartialEq, Clone)]
 #[repr(u8)]
 pub enum Builtin {
@@ -14,6 +19,22 @@ pub enum Builtin {
     Push,
     Echo,
     Echoln,
+    Chars,
+    Pop,
+    Shift,
+    Unshift,
+    Doc,
+    Memoize,
+    Map,
+    Filter,
+    Reduce,
+    Split,
+    Join,
+    Keys,
+    Values,
+    Type,
+    Str,
+    Int,
 }
 
 impl From<u8> for Builtin {
@@ -26,6 +47,22 @@ impl From<u8> for Builtin {
             4 => Builtin::Push,
             5 => Builtin::Echo,
             6 => Builtin::Echoln,
+            7 => Builtin::Chars,
+            8 => Builtin::Pop,
+            9 => Builtin::Shift,
+            10 => Builtin::Unshift,
+            11 => Builtin::Doc,
+            12 => Builtin::Memoize,
+            13 => Builtin::Map,
+            14 => Builtin::Filter,
+            15 => Builtin::Reduce,
+            16 => Builtin::Split,
+            17 => Builtin::Join,
+            18 => Builtin::Keys,
+            19 => Builtin::Values,
+            20 => Builtin::Type,
+            21 => Builtin::Str,
+            22 => Builtin::Int,
             _ => panic!("unknown builtin index"),
         }
     }
@@ -33,7 +70,11 @@ impl From<u8> for Builtin {
 
 impl Builtin {
     pub fn variants() -> Vec<&'static str> {
-        vec!["len", "first", "last", "rest", "push", "echo", "echoln"]
+        vec![
+            "len", "first", "last", "rest", "push", "echo", "echoln", "chars", "pop", "shift",
+            "unshift", "doc", "memoize", "map", "filter", "reduce", "split", "join", "keys",
+            "values", "type", "str", "int",
+        ]
     }
 
     pub fn lookup(name: &str) -> Option<Object> {
@@ -45,10 +86,153 @@ impl Builtin {
             "push" => Some(Object::Builtin(Builtin::Push)),
             "echo" => Some(Object::Builtin(Builtin::Echo)),
             "echoln" => Some(Object::Builtin(Builtin::Echoln)),
+            "chars" => Some(Object::Builtin(Builtin::Chars)),
+            "pop" => Some(Object::Builtin(Builtin::Pop)),
+            "shift" => Some(Object::Builtin(Builtin::Shift)),
+            "unshift" => Some(Object::Builtin(Builtin::Unshift)),
+            "doc" => Some(Object::Builtin(Builtin::Doc)),
+            "memoize" => Some(Object::Builtin(Builtin::Memoize)),
+            "map" => Some(Object::Builtin(Builtin::Map)),
+            "filter" => Some(Object::Builtin(Builtin::Filter)),
+            "reduce" => Some(Object::Builtin(Builtin::Reduce)),
+            "split" => Some(Object::Builtin(Builtin::Split)),
+            "join" => Some(Object::Builtin(Builtin::Join)),
+            "keys" => Some(Object::Builtin(Builtin::Keys)),
+            "values" => Some(Object::Builtin(Builtin::Values)),
+            "type" => Some(Object::Builtin(Builtin::Type)),
+            "str" => Some(Object::Builtin(Builtin::Str)),
+            "int" => Some(Object::Builtin(Builtin::Int)),
             _ => None,
         }
     }
-    pub fn apply(&self, args: &Vec<Rc<Object>>) -> Result<Rc<Object>, ObjectError> {
+
+    pub fn signature(&self) -> &'static str {
+        match self {
+            Builtin::Len => "len(value)",
+            Builtin::First => "first(array)",
+            Builtin::Last => "last(array)",
+            Builtin::Rest => "rest(array)",
+            Builtin::Push => "push(array, value)",
+            Builtin::Echo => "echo(values...)",
+            Builtin::Echoln => "echoln(values...)",
+            Builtin::Chars => "chars(string)",
+            Builtin::Pop => "pop(array)",
+            Builtin::Shift => "shift(array)",
+            Builtin::Unshift => "unshift(array, value)",
+            Builtin::Doc => "doc(builtin)",
+            Builtin::Memoize => "memoize(function)",
+            Builtin::Map => "map(array, function)",
+            Builtin::Filter => "filter(array, predicate)",
+            Builtin::Reduce => "reduce(array, initial, function)",
+            Builtin::Split => "split(string, separator)",
+            Builtin::Join => "join(array, separator)",
+            Builtin::Keys => "keys(hash)",
+            Builtin::Values => "values(hash)",
+            Builtin::Type => "type(value)",
+            Builtin::Str => "str(value)",
+            Builtin::Int => "int(value)",
+        }
+    }
+
+    pub fn doc(&self) -> &'static str {
+        match self {
+            Builtin::Len => "Returns the length of a string (in bytes) or an array.",
+            Builtin::First => "Returns the first element of an array, or null if it is empty.",
+            Builtin::Last => "Returns the last element of an array, or null if it is empty.",
+            Builtin::Rest => "Returns an array without its first element, or null if it is empty.",
+            Builtin::Push => "Returns a copy of the array with the value appended.",
+            Builtin::Echo => "Prints its arguments without a trailing newline.",
+            Builtin::Echoln => "Prints its arguments followed by a newline.",
+            Builtin::Chars => "Splits a string into an array of one-character strings.",
+            Builtin::Pop => {
+                "Returns [last, rest]: the last element and the array without it, or null if it is empty."
+            }
+            Builtin::Shift => {
+                "Returns [first, rest]: the first element and the array without it, or null if it is empty."
+            }
+            Builtin::Unshift => "Returns a copy of the array with the value prepended.",
+            Builtin::Doc => "Returns the signature and description of a builtin.",
+            Builtin::Memoize => {
+                "Wraps a pure function so repeated calls with the same arguments reuse its result."
+            }
+            Builtin::Map => "Returns a new array of the function applied to each element.",
+            Builtin::Filter => "Returns a new array of the elements the predicate is truthy for.",
+            Builtin::Reduce => {
+                "Folds the array from left to right, starting from the initial value."
+            }
+            Builtin::Split => {
+                "Splits a string on a separator, or into characters if the separator is empty."
+            }
+            Builtin::Join => {
+                "Concatenates an array of strings, putting the separator between them."
+            }
+            Builtin::Keys => "Returns the keys of a hash, sorted by type and then by value.",
+            Builtin::Values => "Returns the values of a hash, in the order of its sorted keys.",
+            Builtin::Type => {
+                "Returns the name of a value's type, such as \"INTEGER\" or \"FUNCTION\"."
+            }
+            Builtin::Str => "Returns the text `echo` would print for a value.",
+            Builtin::Int => "Parses a string as an integer; booleans become 1 or 0.",
+        }
+    }
+
+    // whether calling this does anything besides returning a value
+    pub fn has_effects(&self) -> bool {
+        matches!(self, Builtin::Echo | Builtin::Echoln)
+    }
+
+    // the text behind `doc(builtin)` and the REPL's `:help`
+    pub fn help(&self) -> String {
+        format!("{}\n{}", self.signature(), self.doc())
+    }
+
+    // `call` runs a function value on whichever backend is applying the
+    // builtin, so builtins like `map` can call back into user code
+    pub fn apply<E: From<ObjectError>>(
+        &self,
+        args: &Vec<Rc<Object>>,
+        call: &mut Call<E>,
+    ) -> Result<Rc<Object>, E> {
+        match self {
+            Builtin::Map => {
+                check_argument_count(2, args.len())?;
+                let elements = array_argument("map", &args[0])?;
+                let function = function_argument("map", "second", &args[1])?;
+                let mut mapped = Vec::with_capacity(elements.len());
+                for element in elements {
+                    mapped.push(call(function, &[Rc::clone(element)])?);
+                }
+                Ok(Object::new_array(mapped))
+            }
+            Builtin::Filter => {
+                check_argument_count(2, args.len())?;
+                let elements = array_argument("filter", &args[0])?;
+                let predicate = function_argument("filter", "second", &args[1])?;
+                let mut kept = Vec::new();
+                for element in elements {
+                    if call(predicate, &[Rc::clone(element)])?.is_truthy() {
+                        kept.push(Rc::clone(element));
+                    }
+                }
+                Ok(Object::new_array(kept))
+            }
+            Builtin::Reduce => {
+                check_argument_count(3, args.len())?;
+                let elements = array_argument("reduce", &args[0])?;
+                let function = function_argument("reduce", "third", &args[2])?;
+                let mut accumulator = Rc::clone(&args[1]);
+                for element in elements {
+                    accumulator = call(function, &[accumulator, Rc::clone(element)])?;
+                }
+                Ok(accumulator)
+            }
+            _ => Ok(self.apply_value(args)?),
+        }
+    }
+
+    // hash keys are never closures, so nothing in them mutates
+    #[allow(clippy::mutable_key_type)]
+    fn apply_value(&self, args: &Vec<Rc<Object>>) -> Result<Rc<Object>, ObjectError> {
         match self {
             Builtin::Len => {
                 check_argument_count(1, args.len())?;
@@ -65,7 +249,7 @@ impl Builtin {
                 check_argument_count(1, args.len())?;
                 match *args[0] {
                     Object::Array(ref a) => {
-                        if a.len() > 0 {
+                        if !a.is_empty() {
                             Ok(a[0].clone())
                         } else {
                             Ok(Rc::new(Object::Null))
@@ -82,7 +266,7 @@ impl Builtin {
                 check_argument_count(1, args.len())?;
                 match *args[0] {
                     Object::Array(ref a) => {
-                        if a.len() > 0 {
+                        if !a.is_empty() {
                             Ok(a[a.len() - 1].clone())
                         } else {
                             Ok(Rc::new(Object::Null))
@@ -98,12 +282,8 @@ impl Builtin {
                 check_argument_count(1, args.len())?;
                 match *args[0] {
                     Object::Array(ref a) => {
-                        if a.len() > 0 {
-                            let mut new_array = Vec::new();
-                            for i in 1..a.len() {
-                                new_array.push(a[i].clone());
-                            }
-                            Ok(Rc::new(Object::Array(new_array)))
+                        if !a.is_empty() {
+                            Ok(Object::new_array(a[1..].to_vec()))
                         } else {
                             Ok(Rc::new(Object::Null))
                         }
@@ -118,10 +298,7 @@ impl Builtin {
                 check_argument_count(2, args.len())?;
                 match *args[0] {
                     Object::Array(ref a) => {
-                        let mut new_array = Vec::new();
-                        for i in 0..a.len() {
-                            new_array.push(a[i].clone());
-                        }
+                        let mut new_array = a.clone();
                         new_array.push(args[1].clone());
                         Ok(Rc::new(Object::Array(new_array)))
                     }
@@ -133,22 +310,244 @@ impl Builtin {
             }
             Builtin::Echo => {
                 for arg in args {
-                    print!("{}", arg);
+                    output::write(&arg.to_string())?;
                 }
 
                 Ok(Rc::new(Object::Null))
             }
             Builtin::Echoln => {
                 for arg in args {
-                    print!("{}", arg);
+                    output::write(&arg.to_string())?;
                 }
-                println!();
+                output::write("\n")?;
                 Ok(Rc::new(Object::Null))
             }
+            Builtin::Chars => {
+                check_argument_count(1, args.len())?;
+                match *args[0] {
+                    Object::String(ref s) => {
+                        let chars = s
+                            .chars()
+                            .map(|c| Rc::new(Object::String(c.to_string())))
+                            .collect();
+                        Ok(Object::new_array(chars))
+                    }
+                    _ => Err(ObjectError::new(format!(
+                        "argument to `chars` must be STRING, got {}",
+                        args[0]
+                    ))),
+                }
+            }
+            Builtin::Split => {
+                check_argument_count(2, args.len())?;
+                let (s, separator) = match (&*args[0], &*args[1]) {
+                    (Object::String(s), Object::String(separator)) => (s, separator),
+                    (Object::String(_), _) => {
+                        return Err(ObjectError::new(format!(
+                            "second argument to `split` must be STRING, got {}",
+                            args[1]
+                        )))
+                    }
+                    _ => {
+                        return Err(ObjectError::new(format!(
+                            "argument to `split` must be STRING, got {}",
+                            args[0]
+                        )))
+                    }
+                };
+                let parts: Vec<Rc<Object>> = if separator.is_empty() {
+                    s.chars()
+                        .map(|c| Rc::new(Object::String(c.to_string())))
+                        .collect()
+                } else {
+                    s.split(separator.as_str())
+                        .map(|part| Object::new_string(part.to_string()))
+                        .collect()
+                };
+                Ok(Object::new_array(parts))
+            }
+            Builtin::Join => {
+                check_argument_count(2, args.len())?;
+                let elements = array_argument("join", &args[0])?;
+                let separator = match *args[1] {
+                    Object::String(ref separator) => separator,
+                    _ => {
+                        return Err(ObjectError::new(format!(
+                            "second argument to `join` must be STRING, got {}",
+                            args[1]
+                        )))
+                    }
+                };
+                let mut parts = Vec::with_capacity(elements.len());
+                for element in elements {
+                    match **element {
+                        Object::String(ref part) => parts.push(part.as_str()),
+                        _ => {
+                            return Err(ObjectError::new(format!(
+                                "elements passed to `join` must be STRING, got {}",
+                                element
+                            )))
+                        }
+                    }
+                }
+                Ok(Object::new_string(parts.join(separator)))
+            }
+            Builtin::Keys | Builtin::Values => {
+                check_argument_count(1, args.len())?;
+                let hash = match *args[0] {
+                    Object::Hash(ref hash) => hash,
+                    _ => {
+                        return Err(ObjectError::new(format!(
+                            "argument to `{}` must be HASH, got {}",
+                            self, args[0]
+                        )))
+                    }
+                };
+                let mut pairs: Vec<_> = hash.iter().collect();
+                pairs.sort_by(|(a, _), (b, _)| a.cmp_keys(b));
+                let elements = pairs
+                    .into_iter()
+                    .map(|(key, value)| match self {
+                        Builtin::Keys => Rc::clone(key),
+                        _ => Rc::clone(value),
+                    })
+                    .collect();
+                Ok(Object::new_array(elements))
+            }
+            Builtin::Type => {
+                check_argument_count(1, args.len())?;
+                Ok(Rc::new(Object::String(args[0].type_tag().to_string())))
+            }
+            Builtin::Str => {
+                check_argument_count(1, args.len())?;
+                Ok(Object::new_string(args[0].to_string()))
+            }
+            Builtin::Int => {
+                check_argument_count(1, args.len())?;
+                match *args[0] {
+                    Object::Integer(_) => Ok(Rc::clone(&args[0])),
+                    Object::Boolean(b) => Ok(Rc::new(Object::Integer(b as i64))),
+                    Object::String(ref s) => match s.trim().parse::<i64>() {
+                        Ok(i) => Ok(Rc::new(Object::Integer(i))),
+                        Err(_) => Err(ObjectError::new(format!(
+                            "could not parse {:?} as INTEGER",
+                            s
+                        ))),
+                    },
+                    _ => Err(ObjectError::new(format!(
+                        "argument to `int` not supported, got {}",
+                        args[0]
+                    ))),
+                }
+            }
+            // Like `push`, these leave their argument alone. They return the
+            // removed element alongside the array that remains, which the
+            // caller assigns back to use the array as a stack or queue.
+            Builtin::Pop => {
+                check_argument_count(1, args.len())?;
+                match *args[0] {
+                    Object::Array(ref a) => match a.split_last() {
+                        Some((last, remaining)) => Ok(Object::new_array(vec![
+                            Rc::clone(last),
+                            Object::new_array(remaining.to_vec()),
+                        ])),
+                        None => Ok(Rc::new(Object::Null)),
+                    },
+                    _ => Err(ObjectError::new(format!(
+                        "argument to `pop` must be ARRAY, got {}",
+                        args[0]
+                    ))),
+                }
+            }
+            Builtin::Shift => {
+                check_argument_count(1, args.len())?;
+                match *args[0] {
+                    Object::Array(ref a) => match a.split_first() {
+                        Some((first, remaining)) => Ok(Object::new_array(vec![
+                            Rc::clone(first),
+                            Object::new_array(remaining.to_vec()),
+                        ])),
+                        None => Ok(Rc::new(Object::Null)),
+                    },
+                    _ => Err(ObjectError::new(format!(
+                        "argument to `shift` must be ARRAY, got {}",
+                        args[0]
+                    ))),
+                }
+            }
+            Builtin::Unshift => {
+                check_argument_count(2, args.len())?;
+                match *args[0] {
+                    Object::Array(ref a) => {
+                        let mut new_array = Vec::with_capacity(a.len() + 1);
+                        new_array.push(args[1].clone());
+                        new_array.extend(a.iter().cloned());
+                        Ok(Rc::new(Object::Array(new_array)))
+                    }
+                    _ => Err(ObjectError::new(format!(
+                        "argument to `unshift` must be ARRAY, got {}",
+                        args[0]
+                    ))),
+                }
+            }
+            Builtin::Doc => {
+                check_argument_count(1, args.len())?;
+                match *args[0] {
+                    Object::Builtin(ref builtin) => Ok(Rc::new(Object::String(builtin.help()))),
+                    _ => Err(ObjectError::new(format!(
+                        "argument to `doc` must be BUILTIN, got {}",
+                        args[0]
+                    ))),
+                }
+            }
+            Builtin::Memoize => {
+                check_argument_count(1, args.len())?;
+                if !args[0].is_callable() {
+                    return Err(ObjectError::new(format!(
+                        "argument to `memoize` must be FUNCTION, got {}",
+                        args[0]
+                    )));
+                }
+                if !purity::is_pure(&args[0]) {
+                    return Err(ObjectError::new(format!(
+                        "argument to `memoize` must be a pure function, got {}",
+                        args[0]
+                    )));
+                }
+                Ok(Rc::new(Object::Memoized(Memo::new(Rc::clone(&args[0])))))
+            }
+            Builtin::Map | Builtin::Filter | Builtin::Reduce => {
+                unreachable!("`{}` is applied with a callback", self)
+            }
         }
     }
 }
 
+fn array_argument<'a>(builtin: &str, arg: &'a Object) -> Result<&'a Vec<Rc<Object>>, ObjectError> {
+    match arg {
+        Object::Array(ref a) => Ok(a),
+        _ => Err(ObjectError::new(format!(
+            "argument to `{}` must be ARRAY, got {}",
+            builtin, arg
+        ))),
+    }
+}
+
+fn function_argument<'a>(
+    builtin: &str,
+    position: &str,
+    arg: &'a Rc<Object>,
+) -> Result<&'a Rc<Object>, ObjectError> {
+    if arg.is_callable() {
+        Ok(arg)
+    } else {
+        Err(ObjectError::new(format!(
+            "{} argument to `{}` must be FUNCTION, got {}",
+            position, builtin, arg
+        )))
+    }
+}
+
 fn check_argument_count(expected: usize, actual: usize) -> Result<(), ObjectError> {
     if expected != actual {
         Err(ObjectError::new(format!(
@@ -170,6 +569,22 @@ impl fmt::Display for Builtin {
             Builtin::Push => write!(f, "push"),
             Builtin::Echo => write!(f, "echo"),
             Builtin::Echoln => write!(f, "echoln"),
+            Builtin::Chars => write!(f, "chars"),
+            Builtin::Pop => write!(f, "pop"),
+            Builtin::Shift => write!(f, "shift"),
+            Builtin::Unshift => write!(f, "unshift"),
+            Builtin::Doc => write!(f, "doc"),
+            Builtin::Memoize => write!(f, "memoize"),
+            Builtin::Map => write!(f, "map"),
+            Builtin::Filter => write!(f, "filter"),
+            Builtin::Reduce => write!(f, "reduce"),
+            Builtin::Split => write!(f, "split"),
+            Builtin::Join => write!(f, "join"),
+            Builtin::Keys => write!(f, "keys"),
+            Builtin::Values => write!(f, "values"),
+            Builtin::Type => write!(f, "type"),
+            Builtin::Str => write!(f, "str"),
+            Builtin::Int => write!(f, "int"),
         }
     }
 }
@@ -1,8 +1,13 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{self, Debug, Formatter, Write},
+    rc::Rc,
+};
 
 use super::Object;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Clone, PartialEq)]
 pub struct Environment {
     store: HashMap<String, Rc<Object>>,
     outer: Option<Env>,
@@ -10,6 +15,25 @@ pub struct Environment {
 
 pub type Env = Rc<RefCell<Environment>>;
 
+// Closures hold the environment they were defined in, which usually holds the
+// closure itself, so only the bound names are shown here. See `dump`.
+impl Debug for Environment {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut names: Vec<&String> = self.store.keys().collect();
+        names.sort();
+        f.debug_struct("Environment")
+            .field("store", &names)
+            .field("outer", &self.outer.as_ref().map(|_| "<...>"))
+            .finish()
+    }
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Environment {
     pub fn new() -> Self {
         Self {
@@ -24,6 +48,15 @@ impl Environment {
         env
     }
 
+    /// An enclosed environment sized for `capacity` bindings, so binding a
+    /// call's arguments allocates the map once.
+    pub fn new_call_environment(outer: Env, capacity: usize) -> Self {
+        Self {
+            store: HashMap::with_capacity(capacity),
+            outer: Some(outer),
+        }
+    }
+
     pub fn get(&self, name: &str) -> Option<Rc<Object>> {
         match self.store.get(name) {
             Some(obj) => Some(obj.clone()),
@@ -37,4 +70,124 @@ impl Environment {
     pub fn set(&mut self, name: String, val: Rc<Object>) {
         self.store.insert(name, val);
     }
+
+    /// Replaces the value of an existing binding in whichever environment holds
+    /// it. Returns false, binding nothing, when `name` isn't bound anywhere.
+    pub fn assign(&mut self, name: &str, val: Rc<Object>) -> bool {
+        if let Some(slot) = self.store.get_mut(name) {
+            *slot = val;
+            return true;
+        }
+        match &self.outer {
+            Some(outer) => outer.borrow_mut().assign(name, val),
+            None => false,
+        }
+    }
+
+    pub fn dump(&self, max_depth: usize) -> String {
+        let mut out = String::new();
+        self.dump_into(
+            &mut out,
+            0,
+            max_depth,
+            &mut vec![self as *const Environment],
+        );
+        out
+    }
+
+    fn dump_into(
+        &self,
+        out: &mut String,
+        depth: usize,
+        max_depth: usize,
+        seen: &mut Vec<*const Environment>,
+    ) {
+        let indent = "  ".repeat(depth);
+        let mut names: Vec<&String> = self.store.keys().collect();
+        names.sort();
+        for name in names {
+            let value = &self.store[name];
+            let _ = writeln!(out, "{}{} = {}", indent, name, value);
+            if let Object::Function(_, _, env) | Object::Macro(_, _, env) = &**value {
+                Self::dump_nested(env, out, depth + 1, max_depth, seen);
+            }
+        }
+        if let Some(outer) = &self.outer {
+            let _ = writeln!(out, "{}outer:", indent);
+            Self::dump_nested(outer, out, depth + 1, max_depth, seen);
+        }
+    }
+
+    fn dump_nested(
+        env: &Env,
+        out: &mut String,
+        depth: usize,
+        max_depth: usize,
+        seen: &mut Vec<*const Environment>,
+    ) {
+        if depth > max_depth || seen.contains(&env.as_ptr().cast_const()) {
+            let _ = writeln!(out, "{}<...>", "  ".repeat(depth));
+            return;
+        }
+        seen.push(env.as_ptr());
+        env.borrow().dump_into(out, depth, max_depth, seen);
+        seen.pop();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_presizes_call_environments() {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        globals
+            .borrow_mut()
+            .set("x".to_string(), Rc::new(Object::Integer(1)));
+
+        let mut call = Environment::new_call_environment(Rc::clone(&globals), 2);
+        assert!(call.store.capacity() >= 2);
+        call.set("x".to_string(), Rc::new(Object::Integer(2)));
+        call.set("y".to_string(), Rc::new(Object::Integer(3)));
+
+        assert_eq!(call.get("x"), Some(Rc::new(Object::Integer(2))));
+        assert_eq!(call.get("y"), Some(Rc::new(Object::Integer(3))));
+        assert_eq!(globals.borrow().get("x"), Some(Rc::new(Object::Integer(1))));
+        assert_eq!(globals.borrow().get("y"), None);
+    }
+
+    #[test]
+    fn it_dumps_recursive_closure_environments() {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        let recursive = Object::Function(vec!["n".to_string()], vec![], Rc::clone(&globals));
+        globals
+            .borrow_mut()
+            .set("fib".to_string(), Rc::new(recursive));
+
+        let enclosed = Rc::new(RefCell::new(Environment::new_enclosed_environment(
+            Rc::clone(&globals),
+        )));
+        let inner = Object::Function(vec![], vec![], Rc::clone(&enclosed));
+        enclosed
+            .borrow_mut()
+            .set("inner".to_string(), Rc::new(inner));
+        globals.borrow_mut().set(
+            "outer".to_string(),
+            Rc::new(Object::Function(vec![], vec![], Rc::clone(&enclosed))),
+        );
+
+        assert_eq!(
+            globals.borrow().dump(8),
+            "fib = fn(n) {...}\n  <...>\nouter = fn() {...}\n  inner = fn() {...}\n    <...>\n  outer:\n    <...>\n"
+        );
+        assert_eq!(
+            globals.borrow().dump(0),
+            "fib = fn(n) {...}\n  <...>\nouter = fn() {...}\n  <...>\n"
+        );
+        assert_eq!(
+            format!("{:?}", globals.borrow()),
+            r#"Environment { store: ["fib", "outer"], outer: None }"#
+        );
+    }
 }
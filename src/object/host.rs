@@ -0,0 +1,52 @@
+use std::fmt;
+use std::rc::Rc;
+
+use super::error::ObjectError;
+use super::Object;
+
+pub type HostFn = dyn Fn(&[Rc<Object>]) -> Result<Rc<Object>, ObjectError>;
+
+/// A function supplied by the embedding program rather than the fixed `Builtin` set.
+#[derive(Clone)]
+pub struct HostFunction {
+    name: String,
+    function: Rc<HostFn>,
+}
+
+impl HostFunction {
+    pub fn new<F>(name: &str, function: F) -> Self
+    where
+        F: Fn(&[Rc<Object>]) -> Result<Rc<Object>, ObjectError> + 'static,
+    {
+        HostFunction {
+            name: name.to_string(),
+            function: Rc::new(function),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn apply(&self, args: &[Rc<Object>]) -> Result<Rc<Object>, ObjectError> {
+        (self.function)(args)
+    }
+}
+
+impl PartialEq for HostFunction {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && Rc::ptr_eq(&self.function, &other.function)
+    }
+}
+
+impl fmt::Debug for HostFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HostFunction({})", self.name)
+    }
+}
+
+impl fmt::Display for HostFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
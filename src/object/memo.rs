@@ -0,0 +1,62 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use super::error::ObjectError;
+use super::Object;
+
+type Cache = HashMap<Vec<Rc<Object>>, Rc<Object>>;
+
+/// A pure function returned by `memoize`, caching its results by argument list.
+#[derive(Clone)]
+pub struct Memo {
+    function: Rc<Object>,
+    cache: Rc<RefCell<Cache>>,
+}
+
+impl Memo {
+    pub fn new(function: Rc<Object>) -> Self {
+        Memo {
+            function,
+            cache: Rc::new(RefCell::new(HashMap::new())),
+        }
+    }
+
+    pub fn function(&self) -> &Rc<Object> {
+        &self.function
+    }
+
+    /// The cached result for `args`, failing when one of them can't be a key.
+    pub fn lookup(&self, args: &[Rc<Object>]) -> Result<Option<Rc<Object>>, ObjectError> {
+        if let Some(arg) = args.iter().find(|arg| !arg.is_hashable()) {
+            return Err(ObjectError::new(format!(
+                "memoized function arguments must be hashable, got {}",
+                arg.type_name()
+            )));
+        }
+        Ok(self.cache.borrow().get(args).cloned())
+    }
+
+    pub fn store(&self, args: &[Rc<Object>], result: Rc<Object>) {
+        self.cache.borrow_mut().insert(args.to_vec(), result);
+    }
+}
+
+impl PartialEq for Memo {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.cache, &other.cache)
+    }
+}
+
+impl fmt::Debug for Memo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Memo({:?})", self.function)
+    }
+}
+
+impl fmt::Display for Memo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "memoized {}", self.function)
+    }
+}
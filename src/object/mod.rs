@@ -1,8 +1,13 @@
 pub mod builtin;
 pub mod environment;
 pub mod error;
+pub mod host;
+pub mod memo;
+pub mod output;
+pub mod purity;
 
 use std::{
+    cmp::Ordering,
     collections::HashMap,
     fmt::{Display, Formatter, Result},
     hash::{Hash, Hasher},
@@ -15,20 +20,45 @@ use crate::parser::ast::{Node, Statement};
 use environment::Env;
 
 use self::builtin::Builtin;
+use self::error::ObjectError;
+use self::host::HostFunction;
+use self::memo::Memo;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CompiledFunction {
     pub instructions: code::Instructions,
     pub num_parameters: usize,
     pub num_locals: usize,
+    /// Whether calling it has no effect beyond its result, see `purity::is_pure`.
+    pub pure: bool,
 }
 
 impl CompiledFunction {
+    /// A function that is assumed to have effects, since checking needs the
+    /// constants it was compiled alongside; see `with_constants`.
     pub fn new(instructions: code::Instructions, num_parameters: usize, num_locals: usize) -> Self {
         CompiledFunction {
             instructions,
             num_parameters,
             num_locals,
+            pure: false,
+        }
+    }
+
+    /// Builds a function and works out whether it is pure, looking up the
+    /// functions it creates in `constants`.
+    pub fn with_constants(
+        instructions: code::Instructions,
+        num_parameters: usize,
+        num_locals: usize,
+        constants: &[Rc<Object>],
+    ) -> Self {
+        let pure = purity::is_pure_bytecode(&instructions, constants);
+        CompiledFunction {
+            instructions,
+            num_parameters,
+            num_locals,
+            pure,
         }
     }
 
@@ -48,6 +78,7 @@ impl CompiledFunction {
 #[derive(Debug, PartialEq, Clone)]
 pub enum Object {
     Integer(i64),
+    Float(f64),
     Boolean(bool),
     String(String),
     Array(Vec<Rc<Object>>),
@@ -56,6 +87,8 @@ pub enum Object {
     Function(Vec<String>, Vec<Statement>, Env),
     CompiledFunction(Rc<CompiledFunction>),
     Builtin(Builtin),
+    HostFunction(HostFunction),
+    Memoized(Memo),
     Macro(Vec<String>, Vec<Statement>, Env),
     Quote(Node),
     Null,
@@ -64,12 +97,30 @@ pub enum Object {
 
 impl Eq for Object {}
 
+// Arrays and strings are never changed in place, so every empty one can share
+// a single allocation.
+thread_local! {
+    static EMPTY_ARRAY: Rc<Object> = Rc::new(Object::Array(Vec::new()));
+    static EMPTY_STRING: Rc<Object> = Rc::new(Object::String(String::new()));
+}
+
 impl Object {
     pub fn is_integer(&self) -> bool {
-        match self {
-            Object::Integer(_) => true,
-            _ => false,
+        matches!(self, Object::Integer(_))
+    }
+
+    pub fn new_array(elements: Vec<Rc<Object>>) -> Rc<Object> {
+        if elements.is_empty() {
+            return EMPTY_ARRAY.with(Rc::clone);
+        }
+        Rc::new(Object::Array(elements))
+    }
+
+    pub fn new_string(string: String) -> Rc<Object> {
+        if string.is_empty() {
+            return EMPTY_STRING.with(Rc::clone);
         }
+        Rc::new(Object::String(string))
     }
 }
 
@@ -77,6 +128,7 @@ impl Display for Object {
     fn fmt(&self, f: &mut Formatter) -> Result {
         match self {
             Object::Integer(i) => write!(f, "{}", i),
+            Object::Float(x) => write!(f, "{:?}", x),
             Object::Boolean(b) => write!(f, "{}", b),
             Object::String(s) => write!(f, "{}", s),
             Object::ReturnValue(o) => write!(f, "{}", o),
@@ -86,15 +138,20 @@ impl Display for Object {
                 write!(f, "fn({}) {{...}}", params)
             }
             Object::Builtin(b) => write!(f, "{}", b),
+            Object::HostFunction(h) => write!(f, "{}", h),
+            Object::Memoized(m) => write!(f, "{}", m),
             Object::Array(a) => {
                 let elements: Vec<String> = a.iter().map(|e| format!("{}", e)).collect();
                 write!(f, "[{}]", elements.join(", "))
             }
             Object::Hash(h) => {
-                let mut pairs: Vec<String> = Vec::new();
-                for (k, v) in h.iter() {
-                    pairs.push(format!("{}: {}", k, v));
-                }
+                // in `keys` order, so printing a hash gives the same text every run
+                let mut entries: Vec<_> = h.iter().collect();
+                entries.sort_by(|(a, _), (b, _)| a.cmp_keys(b));
+                let pairs: Vec<String> = entries
+                    .iter()
+                    .map(|(k, v)| format!("{}: {}", k, v))
+                    .collect();
                 write!(f, "{{{}}}", pairs.join(", "))
             }
             Object::Quote(s) => {
@@ -131,6 +188,8 @@ impl From<String> for Object {
 }
 
 impl Object {
+    // Only `false` and `null` are falsy; `0`, `""` and empty collections are not.
+    // Both backends branch and negate through this.
     pub fn is_truthy(&self) -> bool {
         match self {
             Object::Boolean(b) => *b,
@@ -147,23 +206,242 @@ impl Object {
         }
     }
 
+    // ints promote to floats when mixed with them in arithmetic and comparisons
+    pub fn to_float(&self) -> Option<f64> {
+        match self {
+            Object::Integer(i) => Some(*i as f64),
+            Object::Float(x) => Some(*x),
+            _ => None,
+        }
+    }
+
     pub fn is_hashable(&self) -> bool {
+        matches!(
+            self,
+            Object::Integer(_) | Object::Boolean(_) | Object::String(_)
+        )
+    }
+
+    /// What both backends report when a hash is built or indexed with a key
+    /// that `is_hashable` rejects.
+    pub fn unusable_key_message(&self) -> String {
+        format!("unusable as hash key: {}", self.type_tag())
+    }
+
+    /// The order `keys` and `values` list a hash in: booleans, then integers,
+    /// then strings, each sorted by value.
+    pub fn cmp_keys(&self, other: &Object) -> Ordering {
+        fn rank(key: &Object) -> u8 {
+            match key {
+                Object::Boolean(_) => 0,
+                Object::Integer(_) => 1,
+                Object::String(_) => 2,
+                _ => 3,
+            }
+        }
+        match (self, other) {
+            (Object::Boolean(a), Object::Boolean(b)) => a.cmp(b),
+            (Object::Integer(a), Object::Integer(b)) => a.cmp(b),
+            (Object::String(a), Object::String(b)) => a.cmp(b),
+            _ => rank(self).cmp(&rank(other)),
+        }
+    }
+
+    /// `container[index] = value` on a copy, leaving `self` alone. Arrays only
+    /// take indexes they already have; hashes take any hashable key.
+    // hash keys are never closures, so nothing in them mutates
+    #[allow(clippy::mutable_key_type)]
+    pub fn with_index(
+        &self,
+        index: &Object,
+        value: Rc<Object>,
+    ) -> std::result::Result<Rc<Object>, ObjectError> {
+        match (self, index) {
+            (Object::Array(elements), Object::Integer(i)) => {
+                if *i < 0 || *i as usize >= elements.len() {
+                    return Err(ObjectError::new(format!(
+                        "index {} out of bounds for array of length {}",
+                        i,
+                        elements.len()
+                    )));
+                }
+                let mut elements = elements.clone();
+                elements[*i as usize] = value;
+                Ok(Rc::new(Object::Array(elements)))
+            }
+            (Object::Array(_), _) => Err(ObjectError::new(format!(
+                "array index must be INTEGER, got {}",
+                index.type_tag()
+            ))),
+            (Object::Hash(_), _) if !index.is_hashable() => {
+                Err(ObjectError::new(index.unusable_key_message()))
+            }
+            (Object::Hash(pairs), _) => {
+                let mut pairs = pairs.clone();
+                pairs.insert(Rc::new(index.clone()), value);
+                Ok(Rc::new(Object::Hash(pairs)))
+            }
+            _ => Err(ObjectError::new(format!(
+                "index assignment not supported: {}",
+                self.type_tag()
+            ))),
+        }
+    }
+
+    /// `-` on collections: drops every element of an array found in `other`
+    /// (duplicates included), or every key of a hash found in `other`, which is
+    /// either an array of keys or another hash.
+    pub fn difference(&self, other: &Object) -> Option<Rc<Object>> {
+        match (self, other) {
+            (Object::Array(left), Object::Array(right)) => Some(Object::new_array(
+                left.iter()
+                    .filter(|e| !right.contains(e))
+                    .cloned()
+                    .collect(),
+            )),
+            (Object::Hash(hash), Object::Array(keys)) => Some(Rc::new(Object::Hash(
+                hash.iter()
+                    .filter(|(k, _)| !keys.contains(k))
+                    .map(|(k, v)| (Rc::clone(k), Rc::clone(v)))
+                    .collect(),
+            ))),
+            (Object::Hash(hash), Object::Hash(other)) => Some(Rc::new(Object::Hash(
+                hash.iter()
+                    .filter(|(k, _)| !other.contains_key(*k))
+                    .map(|(k, v)| (Rc::clone(k), Rc::clone(v)))
+                    .collect(),
+            ))),
+            _ => None,
+        }
+    }
+
+    pub fn is_callable(&self) -> bool {
+        matches!(
+            self,
+            Object::Function(_, _, _)
+                | Object::Closure(_, _)
+                | Object::Builtin(_)
+                | Object::HostFunction(_)
+                | Object::Memoized(_)
+        )
+    }
+
+    /// What both backends report when a call's target isn't callable.
+    pub fn not_callable_message(&self) -> String {
+        format!("not callable: {} ({})", self.type_name(), self)
+    }
+
+    /// What both backends report when an ordering operator gets operands of
+    /// different types. `==` and `!=` across types are simply false and true.
+    pub fn incomparable_message(&self, other: &Object) -> String {
+        format!(
+            "cannot compare {} and {}",
+            self.type_tag(),
+            other.type_tag()
+        )
+    }
+
+    // the uppercase names type errors use, as in "must be ARRAY, got 1"
+    pub fn type_tag(&self) -> &'static str {
         match self {
-            Object::Integer(_) => true,
-            Object::Boolean(_) => true,
-            Object::String(_) => true,
-            _ => false,
+            Object::Integer(_) => "INTEGER",
+            Object::Float(_) => "FLOAT",
+            Object::Boolean(_) => "BOOLEAN",
+            Object::String(_) => "STRING",
+            Object::Array(_) => "ARRAY",
+            Object::Hash(_) => "HASH",
+            Object::ReturnValue(o) => o.type_tag(),
+            Object::Function(_, _, _)
+            | Object::CompiledFunction(_)
+            | Object::Closure(_, _)
+            | Object::Memoized(_) => "FUNCTION",
+            Object::Builtin(_) | Object::HostFunction(_) => "BUILTIN",
+            Object::Macro(_, _, _) => "MACRO",
+            Object::Quote(_) => "QUOTE",
+            Object::Null => "NULL",
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Object::Integer(_) => "int",
+            Object::Float(_) => "float",
+            Object::Boolean(_) => "bool",
+            Object::String(_) => "string",
+            Object::Array(_) => "array",
+            Object::Hash(_) => "hash",
+            Object::ReturnValue(o) => o.type_name(),
+            Object::Function(_, _, _) => "function",
+            Object::CompiledFunction(_) => "compiled function",
+            Object::Builtin(_) => "builtin",
+            Object::HostFunction(_) => "host function",
+            Object::Memoized(_) => "memoized function",
+            Object::Macro(_, _, _) => "macro",
+            Object::Quote(_) => "quote",
+            Object::Null => "null",
+            Object::Closure(_, _) => "closure",
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        match self {
+            Object::Integer(i) => i.to_string(),
+            Object::Float(x) if x.is_finite() => format!("{:?}", x),
+            Object::Float(_) => "null".to_string(),
+            Object::Boolean(b) => b.to_string(),
+            Object::Null => "null".to_string(),
+            Object::ReturnValue(o) => o.to_json(),
+            Object::Array(a) => {
+                let elements: Vec<String> = a.iter().map(|e| e.to_json()).collect();
+                format!("[{}]", elements.join(","))
+            }
+            Object::Hash(h) => {
+                // json keys must be strings, and sorting keeps the output stable
+                let mut pairs: Vec<(String, String)> = h
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_json()))
+                    .collect();
+                pairs.sort();
+                let pairs: Vec<String> = pairs
+                    .iter()
+                    .map(|(k, v)| format!("{}:{}", json_string(k), v))
+                    .collect();
+                format!("{{{}}}", pairs.join(","))
+            }
+            _ => json_string(&self.to_string()),
         }
     }
 }
 
+// Hash keys never match across types: the variant is hashed alongside the
+// value, and equality is derived, so `{1: "a"}[true]` and `{1: "a"}["1"]` miss.
 impl Hash for Object {
     fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
         match self {
             Object::Integer(i) => i.hash(state),
             Object::Boolean(b) => b.hash(state),
             Object::String(s) => s.hash(state),
-            _ => "".hash(state),
+            // hash literals and index expressions turn these away first
+            _ => unreachable!("{}", self.unusable_key_message()),
+        }
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
         }
     }
+    out.push('"');
+    out
 }
@@ -0,0 +1,96 @@
+use std::cell::RefCell;
+use std::io::{self, Write};
+
+use super::error::ObjectError;
+
+// Where `echo`/`echoln` write to, and how much they may write in total.
+struct Output {
+    sink: Box<dyn Write>,
+    limit: Option<usize>,
+    written: usize,
+}
+
+thread_local! {
+    static OUTPUT: RefCell<Output> = RefCell::new(Output {
+        sink: Box::new(io::stdout()),
+        limit: None,
+        written: 0,
+    });
+}
+
+/// Sends program output to `sink` instead of stdout.
+pub fn set_sink(sink: Box<dyn Write>) {
+    OUTPUT.with(|output| output.borrow_mut().sink = sink);
+}
+
+/// Caps the total bytes a program may print; `None` removes the cap. Resets
+/// the count of bytes written so far.
+pub fn set_limit(limit: Option<usize>) {
+    OUTPUT.with(|output| {
+        let mut output = output.borrow_mut();
+        output.limit = limit;
+        output.written = 0;
+    });
+}
+
+pub fn write(s: &str) -> Result<(), ObjectError> {
+    OUTPUT.with(|output| {
+        let mut output = output.borrow_mut();
+        let remaining = output.limit.map(|limit| limit - output.written);
+        let bytes = s.as_bytes();
+        let allowed = remaining.map_or(bytes.len(), |r| r.min(bytes.len()));
+
+        output.written += allowed;
+        output
+            .sink
+            .write_all(&bytes[..allowed])
+            .map_err(|e| ObjectError::new(format!("failed to write output: {}", e)))?;
+
+        if allowed < bytes.len() {
+            return Err(ObjectError::new("output limit exceeded".to_string()));
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn it_stops_writing_past_the_limit() {
+        let buffer = Buffer::default();
+        set_sink(Box::new(buffer.clone()));
+        set_limit(Some(8));
+
+        assert_eq!(write("hello"), Ok(()));
+        assert_eq!(
+            write(" world"),
+            Err(ObjectError::new("output limit exceeded".to_string()))
+        );
+        assert_eq!(
+            write("!"),
+            Err(ObjectError::new("output limit exceeded".to_string()))
+        );
+        assert_eq!(*buffer.0.borrow(), b"hello wo");
+
+        set_limit(None);
+        assert_eq!(write("!"), Ok(()));
+        assert_eq!(*buffer.0.borrow(), b"hello wo!");
+    }
+}
@@ -0,0 +1,148 @@
+use std::rc::Rc;
+
+use crate::code::{self, Instructions, Opcode};
+use crate::parser::ast::{Expression, Literal, Statement};
+
+use super::builtin::Builtin;
+use super::Object;
+
+/// Whether calling `function` can have no effect beyond its result, so its
+/// results can be cached. This looks at the function's body and the functions
+/// it defines: calls it makes to other user functions are assumed to be pure.
+pub fn is_pure(function: &Object) -> bool {
+    match function {
+        Object::Function(_, body, _) => body.iter().all(is_pure_statement),
+        Object::Closure(compiled, _) => compiled.pure,
+        Object::Builtin(builtin) => !builtin.has_effects(),
+        Object::Memoized(_) => true,
+        _ => false,
+    }
+}
+
+fn is_pure_statement(statement: &Statement) -> bool {
+    match statement {
+        Statement::Let(_, value) | Statement::Return(value) | Statement::Expression(value, _) => {
+            is_pure_expression(value)
+        }
+    }
+}
+
+fn is_pure_block(block: &[Statement]) -> bool {
+    block.iter().all(is_pure_statement)
+}
+
+fn is_pure_expression(expression: &Expression) -> bool {
+    match expression {
+        // naming an effectful builtin is enough, since it could be called later
+        Expression::Identifier(name) => {
+            !matches!(Builtin::lookup(name), Some(Object::Builtin(builtin)) if builtin.has_effects())
+        }
+        Expression::Assign(..) | Expression::IndexAssign(..) => false,
+        Expression::Literal(Literal::Array(elements)) => elements.iter().all(is_pure_expression),
+        Expression::Literal(Literal::Hash(pairs)) => pairs
+            .iter()
+            .all(|(key, value)| is_pure_expression(key) && is_pure_expression(value)),
+        Expression::Literal(_) => true,
+        Expression::Prefix(_, right) => is_pure_expression(right),
+        Expression::Infix(left, _, right) | Expression::Index(left, right) => {
+            is_pure_expression(left) && is_pure_expression(right)
+        }
+        Expression::If(condition, consequence, alternative) => {
+            is_pure_expression(condition)
+                && is_pure_block(consequence)
+                && alternative.as_deref().is_none_or(is_pure_block)
+        }
+        Expression::While(condition, body) => is_pure_expression(condition) && is_pure_block(body),
+        Expression::Function(_, _, body) | Expression::Macro(_, body) => is_pure_block(body),
+        Expression::FunctionCall(function, arguments) => {
+            is_pure_expression(function) && arguments.iter().all(is_pure_expression)
+        }
+    }
+}
+
+// Locals die with the frame, so only global writes, effectful builtins and
+// impure nested functions count. The compiler adds a function to `constants`
+// before any function that creates it, so those are already worked out.
+pub(crate) fn is_pure_bytecode(instructions: &Instructions, constants: &[Rc<Object>]) -> bool {
+    let mut i = 0;
+    while i < instructions.len() {
+        let Some(def) = code::lookup(instructions[i]) else {
+            return false;
+        };
+        let Ok((operands, n)) = code::read_operands(&def, &instructions.as_slice()[i + 1..]) else {
+            return false;
+        };
+        match Opcode::from(instructions[i]) {
+            Opcode::SetGlobal => return false,
+            Opcode::GetBuiltin if Builtin::from(operands[0] as u8).has_effects() => return false,
+            Opcode::Closure => match constants.get(operands[0]).map(|c| &**c) {
+                Some(Object::CompiledFunction(function)) if function.pure => {}
+                _ => return false,
+            },
+            _ => {}
+        }
+        i += n + 1;
+    }
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::lexer::Lexer;
+    use crate::object::environment::Environment;
+    use crate::parser::{ast::Node, Parser};
+    use crate::vm::VM;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn it_finds_effects_in_function_bodies() {
+        let tests = vec![
+            ("fn(x) { x + 1 }", true),
+            (
+                "fn(x) { let y = x * 2; if (y > 2) { return y; }; first([y]) }",
+                true,
+            ),
+            ("fn() { fn() { 1 } }", true),
+            ("fn(x) { echo(x) }", false),
+            ("fn(x) { let p = echoln; x }", false),
+            ("fn(x) { if (x) { echo(x) } else { 1 } }", false),
+            ("fn() { fn() { echo(1) } }", false),
+            ("fn() { fn() { fn(x) { x } } }", true),
+            ("let n = 0; fn() { fn() { fn() { n = 1 } } }", false),
+        ];
+
+        for (input, expected) in tests {
+            let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+            let env = Rc::new(RefCell::new(Environment::new()));
+            let function = crate::evaluator::evaluate(Node::Program(program.clone()), env).unwrap();
+            assert_eq!(is_pure(&function), expected, "direct: {}", input);
+
+            let mut compiler = Compiler::new();
+            compiler.compile(Node::Program(program)).unwrap();
+            let mut machine = VM::new(compiler.bytecode());
+            machine.run().unwrap();
+            let closure = machine.last_popped_stack_elem();
+            assert_eq!(is_pure(&closure), expected, "vm: {}", input);
+        }
+    }
+
+    #[test]
+    fn it_treats_global_writes_as_effects() {
+        let program = Parser::new(Lexer::new("let n = 0; fn() { n = n + 1 }"))
+            .parse_program()
+            .unwrap();
+        let mut compiler = Compiler::new();
+        compiler.compile(Node::Program(program)).unwrap();
+        let mut machine = VM::new(compiler.bytecode());
+        machine.run().unwrap();
+        assert!(!is_pure(&machine.last_popped_stack_elem()));
+
+        assert!(is_pure(&Object::Builtin(Builtin::Len)));
+        assert!(!is_pure(&Object::Builtin(Builtin::Echo)));
+        assert!(!is_pure(&Object::Integer(1)));
+    }
+}
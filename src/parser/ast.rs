@@ -1,23 +1,31 @@
+use std::convert::Infallible;
 use std::fmt::{Display, Formatter, Result};
 use std::rc::Rc;
 
 use crate::token::Token;
 
-#[derive(Debug, Clone, PartialEq, PartialOrd, Ord, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Literal {
     Integer(i64),
+    Float(f64),
     Boolean(bool),
+    Null,
     String(String),
     Array(Rc<Vec<Expression>>),
     Hash(Vec<(Expression, Expression)>),
 }
 
+// float literals are never NaN, so equality stays reflexive
+impl Eq for Literal {}
+
 impl Display for Literal {
     fn fmt(&self, f: &mut Formatter) -> Result {
         match self {
             Literal::Integer(i) => write!(f, "{}", *i),
+            Literal::Float(x) => write!(f, "{:?}", *x),
             Literal::String(s) => write!(f, "{}", s),
             Literal::Boolean(s) => write!(f, "{}", s),
+            Literal::Null => write!(f, "null"),
             Literal::Array(a) => {
                 write!(f, "[")?;
                 for (i, e) in a.iter().enumerate() {
@@ -42,19 +50,38 @@ impl Display for Literal {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, PartialOrd, Ord, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Expression {
     Identifier(String),
     Literal(Literal),
     Prefix(Token, Box<Expression>),
     Infix(Box<Expression>, Token, Box<Expression>),
     If(Box<Expression>, Vec<Statement>, Option<Vec<Statement>>),
+    While(Box<Expression>, Vec<Statement>),
+    Assign(String, Box<Expression>),
+    // `name[index] = value`, the target always being an `Index` on a name
+    IndexAssign(Box<Expression>, Box<Expression>),
     Function(Option<String>, Vec<String>, Vec<Statement>), // name, parameters, body
     Macro(Vec<String>, Vec<Statement>),
     FunctionCall(Box<Expression>, Vec<Expression>),
     Index(Box<Expression>, Box<Expression>),
 }
 
+impl Eq for Expression {}
+
+impl Expression {
+    /// The name and index of an `IndexAssign` target such as `a[i]`.
+    pub fn index_target(&self) -> Option<(&str, &Expression)> {
+        match self {
+            Expression::Index(container, index) => match &**container {
+                Expression::Identifier(name) => Some((name, index)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
 impl Display for Expression {
     fn fmt(&self, f: &mut Formatter) -> Result {
         match self {
@@ -77,6 +104,15 @@ impl Display for Expression {
                 }
                 Ok(())
             }
+            Expression::While(condition, body) => {
+                write!(f, "while {} {{", condition)?;
+                for statement in body {
+                    write!(f, "{}", statement)?;
+                }
+                write!(f, "}}")
+            }
+            Expression::Assign(name, value) => write!(f, "{} = {}", name, value),
+            Expression::IndexAssign(target, value) => write!(f, "{} = {}", target, value),
             Expression::Function(_, parameters, body) => {
                 write!(f, "fn(")?;
                 for (i, parameter) in parameters.iter().enumerate() {
@@ -120,19 +156,23 @@ impl Display for Expression {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Statement {
     Let(String, Expression),
     Return(Expression),
-    Expression(Expression),
+    // whether the statement ended with a semicolon, which the repl takes as
+    // a request not to print its value
+    Expression(Expression, bool),
 }
 
+impl Eq for Statement {}
+
 impl Display for Statement {
     fn fmt(&self, f: &mut Formatter) -> Result {
         match self {
             Statement::Let(name, value) => write!(f, "let {} = {};", name, value),
             Statement::Return(value) => write!(f, "return {};", value),
-            Statement::Expression(value) => write!(f, "{}", value),
+            Statement::Expression(value, _) => write!(f, "{}", value),
         }
     }
 }
@@ -164,92 +204,102 @@ pub fn modify<M>(node: Node, modifier: M) -> Node
 where
     M: Fn(Node) -> Node + Clone,
 {
+    let result: std::result::Result<Node, Infallible> = try_modify(node, |node| Ok(modifier(node)));
+    match result {
+        Ok(node) => node,
+        Err(never) => match never {},
+    }
+}
+
+/// Like `modify`, but stops at the first error `modifier` returns.
+pub fn try_modify<M, E>(node: Node, modifier: M) -> std::result::Result<Node, E>
+where
+    M: Fn(Node) -> std::result::Result<Node, E> + Clone,
+{
+    let modify_expression = |expression: Expression| -> std::result::Result<Expression, E> {
+        Ok(unwrap_node_to_expression(try_modify(
+            Node::Expression(expression),
+            modifier.clone(),
+        )?))
+    };
+    let modify_block = |statements: Vec<Statement>| -> std::result::Result<Vec<Statement>, E> {
+        Ok(unwrap_node_to_statements(try_modify(
+            Node::Program(statements),
+            modifier.clone(),
+        )?))
+    };
+
     let new_node = match node {
         Node::Program(statements) => {
-            let modified_statements: Vec<Statement> = statements
+            let modified_statements = statements
                 .into_iter()
-                .map(|s| {
-                    if let Node::Statement(modified_s) =
-                        modify(Node::Statement(s), modifier.clone())
-                    {
-                        modified_s
-                    } else {
-                        panic!("Expected a Node::Statement variant!"); // Handle this better based on your requirements
-                    }
-                })
-                .collect();
+                .map(
+                    |s| match try_modify(Node::Statement(s), modifier.clone())? {
+                        Node::Statement(modified_s) => Ok(modified_s),
+                        _ => panic!("Expected a Node::Statement variant!"),
+                    },
+                )
+                .collect::<std::result::Result<Vec<Statement>, E>>()?;
             Node::Program(modified_statements)
         }
 
         Node::Expression(expression) => match expression {
-            Expression::Infix(left, token, right) => {
-                let modified_left = modify(Node::Expression(*left), modifier.clone());
-                let modified_right = modify(Node::Expression(*right), modifier.clone());
-                Node::Expression(Expression::Infix(
-                    Box::new(unwrap_node_to_expression(modified_left)),
-                    token,
-                    Box::new(unwrap_node_to_expression(modified_right)),
-                ))
-            }
+            Expression::Infix(left, token, right) => Node::Expression(Expression::Infix(
+                Box::new(modify_expression(*left)?),
+                token,
+                Box::new(modify_expression(*right)?),
+            )),
 
-            Expression::Prefix(left, expression) => {
-                let modified_expression = modify(Node::Expression(*expression), modifier.clone());
-                Node::Expression(Expression::Prefix(
-                    left,
-                    Box::new(unwrap_node_to_expression(modified_expression)),
-                ))
-            }
+            Expression::Prefix(left, expression) => Node::Expression(Expression::Prefix(
+                left,
+                Box::new(modify_expression(*expression)?),
+            )),
 
-            Expression::Index(left, index) => {
-                let modified_left = modify(Node::Expression(*left), modifier.clone());
-                let modified_index = modify(Node::Expression(*index), modifier.clone());
-                Node::Expression(Expression::Index(
-                    Box::new(unwrap_node_to_expression(modified_left)),
-                    Box::new(unwrap_node_to_expression(modified_index)),
-                ))
-            }
+            Expression::Index(left, index) => Node::Expression(Expression::Index(
+                Box::new(modify_expression(*left)?),
+                Box::new(modify_expression(*index)?),
+            )),
 
             Expression::If(condition, consequence, alternative) => {
-                let modified_condition = modify(Node::Expression(*condition), modifier.clone());
-
-                let modified_consequence: Vec<Statement> =
-                    unwrap_node_to_statements(modify(Node::Program(consequence), modifier.clone()));
-
-                let modified_alternative: Option<Vec<Statement>> = match alternative {
-                    Some(alternative) => Some(unwrap_node_to_statements(modify(
-                        Node::Program(alternative),
-                        modifier.clone(),
-                    ))),
-                    None => None,
-                };
+                let modified_condition = modify_expression(*condition)?;
+                let modified_consequence = modify_block(consequence)?;
+                let modified_alternative = alternative.map(modify_block).transpose()?;
                 Node::Expression(Expression::If(
-                    Box::new(unwrap_node_to_expression(modified_condition)),
+                    Box::new(modified_condition),
                     modified_consequence,
                     modified_alternative,
                 ))
             }
 
+            Expression::While(condition, body) => Node::Expression(Expression::While(
+                Box::new(modify_expression(*condition)?),
+                modify_block(body)?,
+            )),
+
+            Expression::Assign(name, value) => Node::Expression(Expression::Assign(
+                name,
+                Box::new(modify_expression(*value)?),
+            )),
+
+            Expression::IndexAssign(target, value) => Node::Expression(Expression::IndexAssign(
+                Box::new(modify_expression(*target)?),
+                Box::new(modify_expression(*value)?),
+            )),
+
             Expression::Function(name, arguments, body) => {
-                let modified_arguments: Vec<String> = arguments
-                    .iter()
-                    .map(|argument| {
-                        let modified_argument = modify(
-                            Node::Expression(Expression::Identifier(argument.clone())),
-                            modifier.clone(),
-                        );
-                        let modified_expression = unwrap_node_to_expression(modified_argument);
-                        let modified_identifier = match modified_expression {
-                            Expression::Identifier(identifier) => identifier,
+                let modified_arguments = arguments
+                    .into_iter()
+                    .map(
+                        |argument| match modify_expression(Expression::Identifier(argument))? {
+                            Expression::Identifier(identifier) => Ok(identifier),
                             _ => panic!("Expected Expression::Identifier!"),
-                        };
-                        modified_identifier
-                    })
-                    .collect();
+                        },
+                    )
+                    .collect::<std::result::Result<Vec<String>, E>>()?;
 
-                let modified_body: Vec<Statement> =
-                    unwrap_node_to_statements(modify(Node::Program(body), modifier.clone()));
+                let modified_body = modify_block(body)?;
                 Node::Expression(Expression::Function(
-                    None,
+                    name,
                     modified_arguments,
                     modified_body,
                 ))
@@ -258,37 +308,26 @@ where
             Expression::Literal(literal) => {
                 let modified_literal = match literal {
                     Literal::Array(expressions) => {
-                        let modified_expressions: Rc<Vec<Expression>> = Rc::new(
-                            expressions
-                                .iter()
-                                .map(|expression| {
-                                    let modified_expression = modify(
-                                        Node::Expression(expression.clone()),
-                                        modifier.clone(),
-                                    );
-                                    unwrap_node_to_expression(modified_expression)
-                                })
-                                .collect(),
-                        );
-                        Literal::Array(modified_expressions)
+                        // only copy the elements when the literal is shared
+                        let expressions =
+                            Rc::try_unwrap(expressions).unwrap_or_else(|rc| (*rc).clone());
+                        let modified_expressions = expressions
+                            .into_iter()
+                            .map(modify_expression)
+                            .collect::<std::result::Result<Vec<Expression>, E>>(
+                        )?;
+                        Literal::Array(Rc::new(modified_expressions))
                     }
                     Literal::Hash(pairs) => {
-                        let modified_pairs: Vec<(Expression, Expression)> = pairs
-                            .iter()
+                        let modified_pairs = pairs
+                            .into_iter()
                             .map(|(key, value)| {
-                                let modified_key =
-                                    modify(Node::Expression(key.clone()), modifier.clone());
-                                let modified_value =
-                                    modify(Node::Expression(value.clone()), modifier.clone());
-                                (
-                                    unwrap_node_to_expression(modified_key),
-                                    unwrap_node_to_expression(modified_value),
-                                )
+                                Ok((modify_expression(key)?, modify_expression(value)?))
                             })
-                            .collect();
+                            .collect::<std::result::Result<Vec<(Expression, Expression)>, E>>()?;
                         Literal::Hash(modified_pairs)
                     }
-                    _ => literal.clone(),
+                    literal => literal,
                 };
                 Node::Expression(Expression::Literal(modified_literal))
             }
@@ -296,24 +335,14 @@ where
             _ => Node::Expression(expression),
         },
         Node::Statement(statement) => match statement {
-            Statement::Expression(expression) => {
-                let modified_expression = modify(Node::Expression(expression), modifier.clone());
-                Node::Statement(Statement::Expression(unwrap_node_to_expression(
-                    modified_expression,
-                )))
-            }
+            Statement::Expression(expression, terminated) => Node::Statement(
+                Statement::Expression(modify_expression(expression)?, terminated),
+            ),
             Statement::Return(expression) => {
-                let modified_expression = modify(Node::Expression(expression), modifier.clone());
-                Node::Statement(Statement::Return(unwrap_node_to_expression(
-                    modified_expression,
-                )))
+                Node::Statement(Statement::Return(modify_expression(expression)?))
             }
             Statement::Let(name, expression) => {
-                let modified_expression = modify(Node::Expression(expression), modifier.clone());
-                Node::Statement(Statement::Let(
-                    name,
-                    unwrap_node_to_expression(modified_expression),
-                ))
+                Node::Statement(Statement::Let(name, modify_expression(expression)?))
             }
         },
     };
@@ -338,20 +367,20 @@ fn unwrap_node_to_statements(node: Node) -> Vec<Statement> {
 mod test {
 
     use super::*;
-    fn get_closures() -> (
-        Box<dyn Fn() -> Node>,
-        Box<dyn Fn() -> Node>,
-        Box<dyn Fn(Node) -> Node>,
-    ) {
+
+    type NodeFactory = Box<dyn Fn() -> Node>;
+    type NodeModifier = Box<dyn Fn(Node) -> Node>;
+
+    fn get_closures() -> (NodeFactory, NodeFactory, NodeModifier) {
         let one = || -> Node { Node::Expression(Expression::Literal(Literal::Integer(1))) };
         let two = || -> Node { Node::Expression(Expression::Literal(Literal::Integer(2))) };
 
         let turn_one_into_two = |expr: Node| -> Node {
             match expr {
                 Node::Expression(Expression::Literal(Literal::Integer(1))) => {
-                    return Node::Expression(Expression::Literal(Literal::Integer(2)))
+                    Node::Expression(Expression::Literal(Literal::Integer(2)))
                 }
-                _ => return expr,
+                _ => expr,
             }
         };
         (Box::new(one), Box::new(two), Box::new(turn_one_into_two))
@@ -455,12 +484,18 @@ mod test {
         let tests = vec![(
             Node::Expression(Expression::If(
                 Box::new(unwrap_node_to_expression(one())),
-                vec![Statement::Expression(unwrap_node_to_expression(one()))],
+                vec![Statement::Expression(
+                    unwrap_node_to_expression(one()),
+                    false,
+                )],
                 None,
             )),
             Node::Expression(Expression::If(
                 Box::new(unwrap_node_to_expression(two())),
-                vec![Statement::Expression(unwrap_node_to_expression(two()))],
+                vec![Statement::Expression(
+                    unwrap_node_to_expression(two()),
+                    false,
+                )],
                 None,
             )),
         )];
@@ -514,18 +549,44 @@ mod test {
     #[test]
     fn it_modifies_function_literals() {
         let (one, two, turn_one_into_two) = get_closures();
-        let tests = vec![(
-            Node::Expression(Expression::Function(
-                None,
-                vec!["a".to_string()],
-                vec![Statement::Expression(unwrap_node_to_expression(one()))],
-            )),
-            Node::Expression(Expression::Function(
-                None,
-                vec!["a".to_string()],
-                vec![Statement::Expression(unwrap_node_to_expression(two()))],
-            )),
-        )];
+        let tests = vec![
+            (
+                Node::Expression(Expression::Function(
+                    None,
+                    vec!["a".to_string()],
+                    vec![Statement::Expression(
+                        unwrap_node_to_expression(one()),
+                        false,
+                    )],
+                )),
+                Node::Expression(Expression::Function(
+                    None,
+                    vec!["a".to_string()],
+                    vec![Statement::Expression(
+                        unwrap_node_to_expression(two()),
+                        false,
+                    )],
+                )),
+            ),
+            (
+                Node::Expression(Expression::Function(
+                    Some("f".to_string()),
+                    vec![],
+                    vec![Statement::Expression(
+                        unwrap_node_to_expression(one()),
+                        false,
+                    )],
+                )),
+                Node::Expression(Expression::Function(
+                    Some("f".to_string()),
+                    vec![],
+                    vec![Statement::Expression(
+                        unwrap_node_to_expression(two()),
+                        false,
+                    )],
+                )),
+            ),
+        ];
 
         for (input, expected) in tests {
             let modified = modify(input, &turn_one_into_two);
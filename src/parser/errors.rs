@@ -1,15 +1,30 @@
+use crate::token::Span;
+
 use thiserror::Error;
 
 pub type ParserErrors = Vec<ParserError>;
 
 #[derive(Debug, Clone, Error)]
-#[error("{msg}")]
+#[error("{}", locate(.msg, .span))]
 pub struct ParserError {
     pub msg: String,
+    pub span: Option<Span>,
 }
 
 impl ParserError {
     pub fn new(msg: String) -> Self {
-        ParserError { msg }
+        ParserError { msg, span: None }
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span.get_or_insert(span);
+        self
+    }
+}
+
+fn locate(msg: &str, span: &Option<Span>) -> String {
+    match span {
+        Some(span) => format!("error at line {}, col {}: {}", span.line, span.col, msg),
+        None => msg.to_string(),
     }
 }
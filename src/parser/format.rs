@@ -0,0 +1,319 @@
+// Prints a parsed program back as source in one canonical layout: a statement
+// per line, blocks indented by two spaces, and parentheses only where the
+// parser needs them. Comments never reach the AST, so they are not kept.
+
+use super::ast::{Expression, Literal, Statement};
+use super::precedence::{token_precedence, Precedence};
+
+const INDENT: &str = "  ";
+
+/// Formats `program`, ending with a newline. Parsing the result gives back
+/// the same program, and formatting that again changes nothing.
+pub fn format(program: &[Statement]) -> String {
+    let mut out = String::new();
+    for statement in program {
+        write_statement(&mut out, statement, 0);
+        out.push('\n');
+    }
+    out
+}
+
+fn write_statement(out: &mut String, statement: &Statement, depth: usize) {
+    out.push_str(&INDENT.repeat(depth));
+    match statement {
+        Statement::Let(name, value) => {
+            out.push_str(&format!("let {} = ", name));
+            write_expression(out, value, depth);
+            out.push(';');
+        }
+        Statement::Return(value) => {
+            out.push_str("return ");
+            write_expression(out, value, depth);
+            out.push(';');
+        }
+        Statement::Expression(value, terminated) => {
+            write_expression(out, value, depth);
+            if *terminated {
+                out.push(';');
+            }
+        }
+    }
+}
+
+fn write_block(out: &mut String, body: &[Statement], depth: usize) {
+    if body.is_empty() {
+        out.push_str("{}");
+        return;
+    }
+    out.push_str("{\n");
+    for statement in body {
+        write_statement(out, statement, depth + 1);
+        out.push('\n');
+    }
+    out.push_str(&INDENT.repeat(depth));
+    out.push('}');
+}
+
+// how tightly an expression holds together when it is another's operand;
+// anything that starts with a keyword or bracket can't be split apart
+fn precedence(expression: &Expression) -> Precedence {
+    match expression {
+        Expression::Assign(..) | Expression::IndexAssign(..) => Precedence::Assign,
+        Expression::Infix(_, operator, _) => token_precedence(operator),
+        Expression::Prefix(..) => Precedence::Prefix,
+        Expression::FunctionCall(..) | Expression::Index(..) => Precedence::Call,
+        Expression::Identifier(_)
+        | Expression::Literal(_)
+        | Expression::If(..)
+        | Expression::While(..)
+        | Expression::Function(..)
+        | Expression::Macro(..) => Precedence::Index,
+    }
+}
+
+fn write_operand(out: &mut String, operand: &Expression, parenthesize: bool, depth: usize) {
+    if parenthesize {
+        out.push('(');
+        write_expression(out, operand, depth);
+        out.push(')');
+    } else {
+        write_expression(out, operand, depth);
+    }
+}
+
+fn write_list(out: &mut String, items: &[Expression], depth: usize) {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        write_expression(out, item, depth);
+    }
+}
+
+fn write_expression(out: &mut String, expression: &Expression, depth: usize) {
+    match expression {
+        Expression::Identifier(name) => out.push_str(name),
+        Expression::Literal(literal) => write_literal(out, literal, depth),
+        Expression::Prefix(operator, operand) => {
+            out.push_str(&operator.to_string());
+            // `-(-x)` rather than `--x`
+            write_operand(
+                out,
+                operand,
+                precedence(operand) <= Precedence::Prefix,
+                depth,
+            );
+        }
+        Expression::Infix(left, operator, right) => {
+            // every infix operator is left associative
+            let own = token_precedence(operator);
+            write_operand(out, left, precedence(left) < own, depth);
+            out.push_str(&format!(" {} ", operator));
+            write_operand(out, right, precedence(right) <= own, depth);
+        }
+        Expression::Assign(name, value) => {
+            out.push_str(&format!("{} = ", name));
+            write_expression(out, value, depth);
+        }
+        Expression::IndexAssign(target, value) => {
+            write_expression(out, target, depth);
+            out.push_str(" = ");
+            write_expression(out, value, depth);
+        }
+        Expression::If(condition, consequence, alternative) => {
+            out.push_str("if (");
+            write_expression(out, condition, depth);
+            out.push_str(") ");
+            write_block(out, consequence, depth);
+            if let Some(alternative) = alternative {
+                out.push_str(" else ");
+                write_block(out, alternative, depth);
+            }
+        }
+        Expression::While(condition, body) => {
+            out.push_str("while (");
+            write_expression(out, condition, depth);
+            out.push_str(") ");
+            write_block(out, body, depth);
+        }
+        Expression::Function(_, parameters, body) => {
+            out.push_str(&format!("fn({}) ", parameters.join(", ")));
+            write_block(out, body, depth);
+        }
+        Expression::Macro(parameters, body) => {
+            out.push_str(&format!("macro({}) ", parameters.join(", ")));
+            write_block(out, body, depth);
+        }
+        Expression::FunctionCall(function, arguments) => {
+            write_operand(
+                out,
+                function,
+                precedence(function) < Precedence::Call,
+                depth,
+            );
+            out.push('(');
+            write_list(out, arguments, depth);
+            out.push(')');
+        }
+        Expression::Index(container, index) => {
+            write_operand(
+                out,
+                container,
+                precedence(container) < Precedence::Call,
+                depth,
+            );
+            out.push('[');
+            write_expression(out, index, depth);
+            out.push(']');
+        }
+    }
+}
+
+fn write_literal(out: &mut String, literal: &Literal, depth: usize) {
+    match literal {
+        Literal::Integer(i) => out.push_str(&i.to_string()),
+        // `Display` never uses an exponent, which the lexer couldn't read back
+        Literal::Float(x) => {
+            let digits = x.to_string();
+            out.push_str(&digits);
+            if !digits.contains('.') {
+                out.push_str(".0");
+            }
+        }
+        Literal::Boolean(b) => out.push_str(&b.to_string()),
+        Literal::Null => out.push_str("null"),
+        Literal::String(s) => write_string(out, s),
+        Literal::Array(elements) => {
+            out.push('[');
+            write_list(out, elements, depth);
+            out.push(']');
+        }
+        Literal::Hash(pairs) => {
+            out.push('{');
+            for (i, (key, value)) in pairs.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                // a bare one-element array key would read back as a computed
+                // key, so an array key is bracketed once more
+                let array_key = matches!(key, Expression::Literal(Literal::Array(_)));
+                if array_key {
+                    out.push('[');
+                }
+                write_expression(out, key, depth);
+                if array_key {
+                    out.push(']');
+                }
+                out.push_str(": ");
+                write_expression(out, value, depth);
+            }
+            out.push('}');
+        }
+    }
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '\0' => out.push_str("\\0"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn parse(input: &str) -> Vec<Statement> {
+        Parser::new(Lexer::new(input)).parse_program().unwrap()
+    }
+
+    // formats `input`, checking the result parses to the same program and is
+    // already in its final form
+    fn format_source(input: &str) -> String {
+        let program = parse(input);
+        let formatted = format(&program);
+        assert_eq!(parse(&formatted), program, "{}", formatted);
+        assert_eq!(format(&parse(&formatted)), formatted);
+        formatted
+    }
+
+    #[test]
+    fn it_formats_nested_blocks() {
+        let input = "let max = fn(a, b) { if (a > b) { return a; } else { let c = b;
+            if (c == 0) { while (c < 3) { c = c + 1 } } c } };
+fn noop() {} max(1,2);";
+        let expected = "\
+let max = fn(a, b) {
+  if (a > b) {
+    return a;
+  } else {
+    let c = b;
+    if (c == 0) {
+      while (c < 3) {
+        c = c + 1
+      }
+    }
+    c
+  }
+};
+let noop = fn() {};
+max(1, 2);
+";
+        assert_eq!(format_source(input), expected);
+    }
+
+    #[test]
+    fn it_keeps_only_the_parentheses_the_parser_needs() {
+        let tests = vec![
+            ("(1 + 2) * 3", "(1 + 2) * 3"),
+            ("1 + (2 * 3)", "1 + 2 * 3"),
+            ("(1 - 2) - 3", "1 - 2 - 3"),
+            ("1 - (2 - 3)", "1 - (2 - 3)"),
+            ("-(-x)", "-(-x)"),
+            ("!(a == b)", "!(a == b)"),
+            ("-(a[0])", "-a[0]"),
+            ("(f(1))[2](3)", "f(1)[2](3)"),
+            ("(a + b)(1)", "(a + b)(1)"),
+            ("a = b = c ?? (d || e)", "a = b = c ?? d || e"),
+            ("(a ?? b) || c", "(a ?? b) || c"),
+            ("(x = 1) + 2", "(x = 1) + 2"),
+            ("a[i] = a[i]*2", "a[i] = a[i] * 2"),
+            ("fn(x){x}(1)", "fn(x) {\n  x\n}(1)"),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(format_source(input), format!("{}\n", expected), "{}", input);
+        }
+    }
+
+    #[test]
+    fn it_formats_literals_so_they_read_back() {
+        let tests = vec![
+            (r#""tab\tquote\" slash\\""#, r#""tab\tquote\" slash\\""#),
+            ("10.0 + 2.5", "10.0 + 2.5"),
+            ("100000000000000000000.0", "100000000000000000000.0"),
+            ("[1, [2, 3], {}]", "[1, [2, 3], {}]"),
+            (
+                r#"{"a": 1, [[1]]: 2, [x]: 3, true: null}"#,
+                r#"{"a": 1, [[1]]: 2, x: 3, true: null}"#,
+            ),
+            (
+                "macro(a) { quote(unquote(a)) }",
+                "macro(a) {\n  quote(unquote(a))\n}",
+            ),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(format_source(input), format!("{}\n", expected), "{}", input);
+        }
+    }
+}
@@ -1,39 +1,60 @@
 pub mod ast;
 pub mod errors;
+pub mod format;
 pub mod precedence;
+mod registry;
+pub mod trace;
 
 use self::ast::*;
 use self::errors::*;
 use self::precedence::*;
 
 use crate::lexer::Lexer;
-use crate::token::Token;
+use crate::token::{Span, Token};
 
 use anyhow::Result;
 use std::rc::Rc;
 
+const MAX_NESTING_DEPTH: usize = 256;
+
 pub struct Parser {
     lexer: Lexer,
     current_token: Token,
     peek_token: Token,
+    current_span: Span,
+    peek_span: Span,
     errors: ParserErrors,
+    depth: usize,
 }
 
 impl Parser {
     pub fn new(mut lexer: Lexer) -> Self {
         let current_token = lexer.next_token();
+        let current_span = lexer.span();
         let peek_token = lexer.next_token();
+        let peek_span = lexer.span();
         Parser {
             lexer,
             current_token,
             peek_token,
+            current_span,
+            peek_span,
             errors: Vec::new(),
+            depth: 0,
         }
     }
 
     fn next_token(&mut self) {
         self.current_token = self.peek_token.clone();
         self.peek_token = self.lexer.next_token();
+        self.current_span = self.peek_span;
+        self.peek_span = self.lexer.span();
+    }
+
+    // errors are pinned to the token the parser was looking at when it gave up,
+    // unless they already name the token after it
+    fn record_error(&mut self, error: ParserError) {
+        self.errors.push(error.with_span(self.current_span));
     }
 
     pub fn parse_program(&mut self) -> Result<Vec<Statement>, ParserErrors> {
@@ -42,7 +63,7 @@ impl Parser {
         while !self.current_token_is(&Token::Eof) {
             match self.parse_statement() {
                 Ok(statement) => program.push(statement),
-                Err(e) => self.errors.push(e),
+                Err(e) => self.record_error(e),
             }
             self.next_token();
         }
@@ -58,6 +79,9 @@ impl Parser {
         match self.current_token {
             Token::Let => self.parse_let_statement(),
             Token::Return => self.parse_return_statement(),
+            Token::Function if matches!(self.peek_token, Token::Ident(_)) => {
+                self.parse_function_declaration()
+            }
             _ => self.parse_expression_statement(),
         }
     }
@@ -69,7 +93,8 @@ impl Parser {
                 return Err(ParserError::new(format!(
                     "parse error: expected identifier, got {:?}",
                     t
-                )));
+                ))
+                .with_span(self.peek_span));
             }
         };
 
@@ -79,13 +104,32 @@ impl Parser {
 
         let mut exp = self.parse_expression(Precedence::Lowest)?;
 
-        match exp {
-            Expression::Function(ref mut name, _, _) => {
-                *name = Some(ident.clone());
-            }
-            _ => {}
+        if let Expression::Function(ref mut name, _, _) = exp {
+            *name = Some(ident.clone());
+        }
+
+        if self.peek_token_is(&Token::Semicolon) {
+            self.next_token()
         }
 
+        Ok(Statement::Let(ident, exp))
+    }
+
+    // `fn name(params) { ... }` is sugar for `let name = fn(params) { ... };`
+    fn parse_function_declaration(&mut self) -> Result<Statement, ParserError> {
+        self.next_token();
+        let ident = match &self.current_token {
+            Token::Ident(id) => id.clone(),
+            _ => unreachable!(),
+        };
+
+        let exp = match self.parse_function_expression()? {
+            Expression::Function(_, parameters, body) => {
+                Expression::Function(Some(ident.clone()), parameters, body)
+            }
+            _ => unreachable!(),
+        };
+
         if self.peek_token_is(&Token::Semicolon) {
             self.next_token()
         }
@@ -106,67 +150,109 @@ impl Parser {
     fn parse_expression_statement(&mut self) -> Result<Statement, ParserError> {
         let expression_statement = self.parse_expression(Precedence::Lowest)?;
 
-        if self.peek_token_is(&Token::Semicolon) {
+        let terminated = self.peek_token_is(&Token::Semicolon);
+        if terminated {
             self.next_token();
         }
-        Ok(Statement::Expression(expression_statement))
+        Ok(Statement::Expression(expression_statement, terminated))
     }
 
     fn parse_expression(&mut self, precedence: Precedence) -> Result<Expression, ParserError> {
-        let mut exp = match self.current_token {
-            Token::Ident(ref ident) => Expression::Identifier(ident.clone()),
-            Token::Int(i) => Expression::Literal(Literal::Integer(i)),
-            Token::True => Expression::Literal(Literal::Boolean(true)),
-            Token::False => Expression::Literal(Literal::Boolean(false)),
-            Token::Bang | Token::Dash => self.parse_prefix_expression()?, // is there a better way
-            Token::Lparen => {
-                self.next_token();
-                let exp = self.parse_expression(Precedence::Lowest)?;
-                self.expect_peek_token(&Token::Rparen)?;
-                exp
-            }
-            Token::If => self.parse_if_expression()?,
-            Token::Function => self.parse_function_expression()?,
-            Token::Macro => self.parse_macro_expression()?,
-            Token::LBracket => self.parse_array_literal()?,
-            Token::Lbrace => self.parse_hash_literal()?,
-            Token::String(ref s) => Expression::Literal(Literal::String(s.clone())),
-            _ => {
+        if self.depth >= MAX_NESTING_DEPTH {
+            return Err(ParserError::new(format!(
+                "parse error: expression nested deeper than {} levels",
+                MAX_NESTING_DEPTH
+            )));
+        }
+        self.depth += 1;
+        trace::enter(self.depth, precedence, &self.current_token);
+        let result = self.parse_nested_expression(precedence);
+        trace::exit(self.depth, &result);
+        self.depth -= 1;
+        result
+    }
+
+    fn parse_nested_expression(
+        &mut self,
+        precedence: Precedence,
+    ) -> Result<Expression, ParserError> {
+        let prefix = match registry::prefix_fn(&self.current_token) {
+            Some(prefix) => prefix,
+            None => {
                 return Err(ParserError::new(format!(
                     "parse error: no prefix parse function for {} found",
                     self.current_token
                 )))
             }
         };
+        let mut exp = prefix(self)?;
 
         while !self.peek_token_is(&Token::Semicolon) && precedence < self.peek_precedence() {
-            match self.peek_token {
-                Token::Plus
-                | Token::Dash
-                | Token::Slash
-                | Token::Asterisk
-                | Token::Eq
-                | Token::NotEq
-                | Token::Lt
-                | Token::Gt => {
-                    self.next_token();
-                    exp = self.parse_infix_expression(exp)?;
-                }
-                Token::Lparen => {
-                    self.next_token();
-                    exp = self.parse_function_call_expression(exp)?;
-                }
-                Token::LBracket => {
-                    self.next_token();
-                    exp = self.parse_index_expression(exp)?;
-                }
-                _ => break,
-            }
+            let Some((_, infix)) = registry::infix_fn(&self.peek_token) else {
+                break;
+            };
+            self.next_token();
+            exp = infix(self, exp)?;
         }
 
         Ok(exp)
     }
 
+    fn parse_identifier(&mut self) -> Result<Expression, ParserError> {
+        match self.current_token {
+            Token::Ident(ref ident) => Ok(Expression::Identifier(ident.clone())),
+            _ => unreachable!("registered for identifiers only"),
+        }
+    }
+
+    fn parse_integer_literal(&mut self) -> Result<Expression, ParserError> {
+        match self.current_token {
+            Token::Int(i) => Ok(Expression::Literal(Literal::Integer(i))),
+            _ => unreachable!("registered for integers only"),
+        }
+    }
+
+    fn parse_float_literal(&mut self) -> Result<Expression, ParserError> {
+        match self.current_token {
+            Token::Float(x) => Ok(Expression::Literal(Literal::Float(x))),
+            _ => unreachable!("registered for floats only"),
+        }
+    }
+
+    fn parse_boolean_literal(&mut self) -> Result<Expression, ParserError> {
+        Ok(Expression::Literal(Literal::Boolean(
+            self.current_token_is(&Token::True),
+        )))
+    }
+
+    fn parse_null_literal(&mut self) -> Result<Expression, ParserError> {
+        Ok(Expression::Literal(Literal::Null))
+    }
+
+    fn parse_string_literal(&mut self) -> Result<Expression, ParserError> {
+        match self.current_token {
+            Token::String(ref s) => Ok(Expression::Literal(Literal::String(s.clone()))),
+            _ => unreachable!("registered for strings only"),
+        }
+    }
+
+    fn parse_grouped_expression(&mut self) -> Result<Expression, ParserError> {
+        self.next_token();
+        let exp = self.parse_expression(Precedence::Lowest)?;
+        self.expect_peek_token(&Token::Rparen)?;
+        Ok(exp)
+    }
+
+    fn parse_illegal_token(&mut self) -> Result<Expression, ParserError> {
+        Err(ParserError::new(format!(
+            "parse error: {}",
+            match self.current_token {
+                Token::Illegal(ref msg) => msg,
+                _ => unreachable!("registered for illegal tokens only"),
+            }
+        )))
+    }
+
     fn parse_prefix_expression(&mut self) -> Result<Expression, ParserError> {
         let prefix = self.current_token.clone();
         self.next_token();
@@ -185,6 +271,25 @@ impl Parser {
             Box::new(right_exp),
         ))
     }
+
+    // `=` is right associative, so `a = b = 1` assigns `b` first
+    fn parse_assign_expression(&mut self, left_exp: Expression) -> Result<Expression, ParserError> {
+        // an index target has to be on a name, which is where the changed
+        // copy of the array or hash gets stored
+        if !matches!(left_exp, Expression::Identifier(_)) && left_exp.index_target().is_none() {
+            return Err(ParserError::new(format!(
+                "parse error: cannot assign to {}",
+                left_exp
+            )));
+        }
+        self.next_token();
+        let value = Box::new(self.parse_expression(Precedence::Lowest)?);
+        match left_exp {
+            Expression::Identifier(name) => Ok(Expression::Assign(name, value)),
+            target => Ok(Expression::IndexAssign(Box::new(target), value)),
+        }
+    }
+
     fn parse_if_expression(&mut self) -> Result<Expression, ParserError> {
         self.expect_peek_token(&Token::Lparen)?;
         self.next_token();
@@ -202,6 +307,23 @@ impl Parser {
         Ok(Expression::If(Box::new(condition), if_block, else_block))
     }
 
+    fn parse_while_expression(&mut self) -> Result<Expression, ParserError> {
+        self.expect_peek_token(&Token::Lparen)?;
+        self.next_token();
+        let condition = self.parse_expression(Precedence::Lowest)?;
+        self.expect_peek_token(&Token::Rparen)?;
+        self.expect_peek_token(&Token::Lbrace)?;
+        let body = self.parse_block_statement()?;
+        Ok(Expression::While(Box::new(condition), body))
+    }
+
+    fn parse_loop_control(&mut self) -> Result<Expression, ParserError> {
+        Err(ParserError::new(format!(
+            "parse error: {} is not supported yet",
+            self.current_token
+        )))
+    }
+
     fn parse_macro_expression(&mut self) -> Result<Expression, ParserError> {
         self.expect_peek_token(&Token::Lparen)?;
         let parameters = self.parse_function_parameters()?;
@@ -245,6 +367,12 @@ impl Parser {
             self.next_token();
             self.next_token();
             match &self.current_token {
+                Token::Ident(ident) if identifiers.contains(ident) => {
+                    return Err(ParserError::new(format!(
+                        "parse error: duplicate parameter name {}",
+                        ident
+                    )))
+                }
                 Token::Ident(ident) => identifiers.push(ident.clone()),
                 _ => {
                     return Err(ParserError::new(format!(
@@ -262,8 +390,9 @@ impl Parser {
         let mut statements = Vec::new();
         self.next_token();
         while !self.current_token_is(&Token::Rbrace) && !self.current_token_is(&Token::Eof) {
-            if let Ok(statement) = self.parse_statement() {
-                statements.push(statement);
+            match self.parse_statement() {
+                Ok(statement) => statements.push(statement),
+                Err(e) => self.record_error(e),
             }
             self.next_token();
         }
@@ -278,6 +407,31 @@ impl Parser {
         Ok(Expression::FunctionCall(Box::new(exp), arguments))
     }
 
+    // `receiver.name(args)` is sugar for `name(receiver, args)`
+    fn parse_method_call_expression(
+        &mut self,
+        receiver: Expression,
+    ) -> Result<Expression, ParserError> {
+        let name = match &self.peek_token {
+            Token::Ident(ref name) => name.clone(),
+            t => {
+                return Err(ParserError::new(format!(
+                    "parse error: expected method name, got {:?}",
+                    t
+                )));
+            }
+        };
+        self.next_token();
+        self.expect_peek_token(&Token::Lparen)?;
+
+        let mut arguments = vec![receiver];
+        arguments.extend(self.parse_expression_list(&Token::Rparen)?);
+        Ok(Expression::FunctionCall(
+            Box::new(Expression::Identifier(name)),
+            arguments,
+        ))
+    }
+
     fn parse_expression_list(
         &mut self,
         ending_token: &Token,
@@ -316,14 +470,15 @@ impl Parser {
     }
 
     fn expect_peek_token(&mut self, token: &Token) -> Result<(), ParserError> {
-        if self.peek_token_is(&token) {
+        if self.peek_token_is(token) {
             self.next_token();
             Ok(())
         } else {
             Err(ParserError::new(format!(
                 "parse error: expected {:?}, got {:?}",
                 token, self.peek_token
-            )))
+            ))
+            .with_span(self.peek_span))
         }
     }
 
@@ -331,7 +486,14 @@ impl Parser {
         let mut map = Vec::new();
         while !self.peek_token_is(&Token::Rbrace) {
             self.next_token();
-            let key = self.parse_expression(Precedence::Lowest)?;
+            let key = match self.parse_expression(Precedence::Lowest)? {
+                // `{[expr]: value}` computes its key; an array could never be a
+                // key anyway, so a bare one-element array literal means this
+                Expression::Literal(Literal::Array(elements)) if elements.len() == 1 => {
+                    elements[0].clone()
+                }
+                key => key,
+            };
             self.expect_peek_token(&Token::Colon)?;
             self.next_token();
             let value = self.parse_expression(Precedence::Lowest)?;
@@ -364,7 +526,7 @@ mod test {
         let foobar = 838383;
         "#;
 
-        let lexer = Lexer::new(input.into());
+        let lexer = Lexer::new(input);
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program().unwrap();
         assert_eq!(program.len(), 3);
@@ -384,7 +546,7 @@ mod test {
         return 10;
         return 993322;
         "#;
-        let lexer = Lexer::new(input.into());
+        let lexer = Lexer::new(input);
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program().unwrap();
         assert_eq!(program.len(), 3);
@@ -398,7 +560,7 @@ mod test {
         let input = r#"
         foobar;
         "#;
-        let lexer = Lexer::new(input.into());
+        let lexer = Lexer::new(input);
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program().unwrap();
         assert_eq!(program.len(), 1);
@@ -408,13 +570,29 @@ mod test {
     #[test]
     fn it_parses_integer_literal_expressions() {
         let input = "5;";
-        let lexer = Lexer::new(input.into());
+        let lexer = Lexer::new(input);
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program().unwrap();
         assert_eq!(program.len(), 1);
         check_expression_statement(&program[0], &Expression::Literal(Literal::Integer(5)));
     }
 
+    #[test]
+    fn it_parses_float_literal_expressions() {
+        let program = Parser::new(Lexer::new("2.5; -0.5;"))
+            .parse_program()
+            .unwrap();
+        assert_eq!(program.len(), 2);
+        check_expression_statement(&program[0], &Expression::Literal(Literal::Float(2.5)));
+        check_expression_statement(
+            &program[1],
+            &Expression::Prefix(
+                Token::Dash,
+                Box::new(Expression::Literal(Literal::Float(0.5))),
+            ),
+        );
+    }
+
     #[test]
     fn it_parses_prefix_expressions() {
         let input = r#"
@@ -424,7 +602,7 @@ mod test {
             -foobar;
             !true;
             "#;
-        let lexer = Lexer::new(input.into());
+        let lexer = Lexer::new(input);
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program().unwrap();
         assert_eq!(program.len(), 5);
@@ -480,7 +658,7 @@ mod test {
             true != false;
             false == false;
             "#;
-        let lexer = Lexer::new(input.into());
+        let lexer = Lexer::new(input);
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program().unwrap();
         assert_eq!(program.len(), 11);
@@ -592,6 +770,10 @@ mod test {
            3 + 4 * 5 == 3 * 1 + 4 * 5;
            a * [1, 2, 3, 4][b * c] * d;
            add(a * b[2], b[1], 2 * [1, 2][1]);
+           a || b && c == d;
+           a && b || !c;
+           a < b && c >= d;
+           a + b % c * d;
         "#;
         let with_parens = r#"
             (3 + 4);
@@ -609,27 +791,75 @@ mod test {
             ((3 + (4 * 5)) == ((3 * 1) + (4 * 5)));
             ((a * ([1, 2, 3, 4][(b * c)])) * d);
             add((a * (b[2])), (b[1]), (2 * ([1, 2][1])));
+            (a || (b && (c == d)));
+            ((a && b) || (!c));
+            ((a < b) && (c >= d));
+            (a + ((b % c) * d));
             "#;
         // "#;
 
-        let without_parens_lexer = Lexer::new(without_parens.into());
+        let without_parens_lexer = Lexer::new(without_parens);
         let mut without_parens_parser = Parser::new(without_parens_lexer);
         let without_parens_program = without_parens_parser.parse_program().unwrap();
 
-        let with_parens_lexer = Lexer::new(with_parens.into());
+        let with_parens_lexer = Lexer::new(with_parens);
         let mut with_parens_parser = Parser::new(with_parens_lexer);
         let with_parens_program = with_parens_parser.parse_program().unwrap();
 
         assert_eq!(without_parens_program, with_parens_program);
     }
 
+    #[test]
+    fn it_keeps_registered_operator_precedence() {
+        let tests = vec![
+            (Token::Eq, Precedence::Equals),
+            (Token::NotEq, Precedence::Equals),
+            (Token::Lt, Precedence::LessGreater),
+            (Token::Gt, Precedence::LessGreater),
+            (Token::LtEq, Precedence::LessGreater),
+            (Token::GtEq, Precedence::LessGreater),
+            (Token::And, Precedence::And),
+            (Token::Or, Precedence::Or),
+            (Token::Coalesce, Precedence::Coalesce),
+            (Token::Plus, Precedence::Sum),
+            (Token::Dash, Precedence::Sum),
+            (Token::Slash, Precedence::Product),
+            (Token::Asterisk, Precedence::Product),
+            (Token::Percent, Precedence::Product),
+            (Token::Lparen, Precedence::Call),
+            (Token::LBracket, Precedence::Index),
+            (Token::Dot, Precedence::Call),
+            (Token::Bang, Precedence::Lowest),
+            (Token::Semicolon, Precedence::Lowest),
+        ];
+        for (token, expected) in tests {
+            assert_eq!(token_precedence(&token), expected, "{:?}", token);
+        }
+
+        let left_associative = "a - b - c; a / b / c; a == b != c; a < b > c; a(b)(c); a[b][c];";
+        let grouped =
+            "((a - b) - c); ((a / b) / c); ((a == b) != c); ((a < b) > c); (a(b))(c); ((a[b])[c]);";
+        let left_associative = Parser::new(Lexer::new(left_associative))
+            .parse_program()
+            .unwrap();
+        let grouped = Parser::new(Lexer::new(grouped)).parse_program().unwrap();
+        assert_eq!(left_associative, grouped);
+
+        // `??` binds looser than everything but assignment
+        let loose = "a ?? b || c; x = a ?? b == c;";
+        let grouped = "a ?? (b || c); x = (a ?? (b == c));";
+        let loose = Parser::new(Lexer::new(loose)).parse_program().unwrap();
+        let grouped = Parser::new(Lexer::new(grouped)).parse_program().unwrap();
+        assert_eq!(loose, grouped);
+    }
+
     #[test]
     fn it_parses_boolean_literal_expressions() {
         let input = r#"
                 true;
                 false;
                 "#;
-        let lexer = Lexer::new(input.into());
+        let lexer = Lexer::new(input);
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program().unwrap();
         assert_eq!(program.len(), 2);
@@ -653,10 +883,10 @@ mod test {
             (-(5 + 5));
             (!(true == true));
             "#;
-        let without_parens_lexer = Lexer::new(without_parens.into());
+        let without_parens_lexer = Lexer::new(without_parens);
         let mut without_parens_parser = Parser::new(without_parens_lexer);
         let without_parens_program = without_parens_parser.parse_program().unwrap();
-        let with_parens_lexer = Lexer::new(with_parens.into());
+        let with_parens_lexer = Lexer::new(with_parens);
         let mut with_parens_parser = Parser::new(with_parens_lexer);
         let with_parens_program = with_parens_parser.parse_program().unwrap();
         for (without_parens_statement, with_parens_statement) in without_parens_program
@@ -672,7 +902,7 @@ mod test {
         let input = r#"
                 if (x < y) { x }
                 "#;
-        let lexer = Lexer::new(input.into());
+        let lexer = Lexer::new(input);
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program().unwrap();
         assert_eq!(program.len(), 1);
@@ -684,7 +914,10 @@ mod test {
                     Token::Lt,
                     Box::new(Expression::Identifier("y".into())),
                 )),
-                vec![Statement::Expression(Expression::Identifier("x".into()))],
+                vec![Statement::Expression(
+                    Expression::Identifier("x".into()),
+                    false,
+                )],
                 None,
             ),
         )
@@ -695,7 +928,7 @@ mod test {
         let input = r#"
                 if (x < y) { x } else { y }
                 "#;
-        let lexer = Lexer::new(input.into());
+        let lexer = Lexer::new(input);
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program().unwrap();
         assert_eq!(program.len(), 1);
@@ -707,10 +940,14 @@ mod test {
                     Token::Lt,
                     Box::new(Expression::Identifier("y".into())),
                 )),
-                vec![Statement::Expression(Expression::Identifier("x".into()))],
-                Some(vec![Statement::Expression(Expression::Identifier(
-                    "y".into(),
-                ))]),
+                vec![Statement::Expression(
+                    Expression::Identifier("x".into()),
+                    false,
+                )],
+                Some(vec![Statement::Expression(
+                    Expression::Identifier("y".into()),
+                    false,
+                )]),
             ),
         );
     }
@@ -720,7 +957,7 @@ mod test {
         let input = r#"
                 fn(x, y) { x + y; }
                 "#;
-        let lexer = Lexer::new(input.into());
+        let lexer = Lexer::new(input);
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program().unwrap();
         assert_eq!(program.len(), 1);
@@ -729,11 +966,14 @@ mod test {
             &Expression::Function(
                 None,
                 vec!["x".into(), "y".into()],
-                vec![Statement::Expression(Expression::Infix(
-                    Box::new(Expression::Identifier("x".into())),
-                    Token::Plus,
-                    Box::new(Expression::Identifier("y".into())),
-                ))],
+                vec![Statement::Expression(
+                    Expression::Infix(
+                        Box::new(Expression::Identifier("x".into())),
+                        Token::Plus,
+                        Box::new(Expression::Identifier("y".into())),
+                    ),
+                    true,
+                )],
             ),
         );
     }
@@ -745,7 +985,7 @@ mod test {
                 fn(x) {};
                 fn(x, y, z) {};
                 "#;
-        let lexer = Lexer::new(input.into());
+        let lexer = Lexer::new(input);
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program().unwrap();
         assert_eq!(program.len(), 3);
@@ -760,12 +1000,166 @@ mod test {
         );
     }
 
+    #[test]
+    fn it_rejects_duplicate_parameter_names() {
+        let tests = vec![
+            ("fn(x, x) { x }", "parse error: duplicate parameter name x"),
+            (
+                "fn(a, b, c, b) { a }",
+                "parse error: duplicate parameter name b",
+            ),
+            (
+                "macro(q, q) { q }",
+                "parse error: duplicate parameter name q",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let errors = Parser::new(Lexer::new(input)).parse_program().unwrap_err();
+            assert_eq!(errors[0].msg, expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn it_reports_unterminated_string_literals() {
+        let errors = Parser::new(Lexer::new("puts(\"hi);"))
+            .parse_program()
+            .unwrap_err();
+        assert_eq!(
+            errors[0].to_string(),
+            "error at line 1, col 6: parse error: unterminated string literal"
+        );
+    }
+
+    #[test]
+    fn it_reports_error_positions() {
+        let input = "let a = 1;\nlet b = fn(x) {\n  x +;\n};\nlet é = ;";
+        let errors = Parser::new(Lexer::new(input)).parse_program().unwrap_err();
+        let located: Vec<(usize, usize)> = errors
+            .iter()
+            .map(|e| e.span.map(|s| (s.line, s.col)).unwrap())
+            .collect();
+        assert_eq!(located, vec![(3, 6), (5, 9)]);
+        assert_eq!(
+            errors[0].to_string(),
+            "error at line 3, col 6: parse error: no prefix parse function for ; found"
+        );
+    }
+
+    #[test]
+    fn it_points_errors_at_the_offending_token() {
+        let errors = Parser::new(Lexer::new("let = 5;"))
+            .parse_program()
+            .unwrap_err();
+        let span = errors[0].span.unwrap();
+        assert_eq!((span.start(), span.end()), ((1, 5), (1, 6)));
+        assert_eq!(
+            errors[0].to_string(),
+            "error at line 1, col 5: parse error: expected identifier, got Assign"
+        );
+
+        let tests = vec![
+            ("let x 5;", ((1, 7), (1, 8))),
+            ("let f = fn(x { x };", ((1, 14), (1, 15))),
+            ("if (true) 1", ((1, 11), (1, 12))),
+            ("let s = {1 2};", ((1, 12), (1, 13))),
+            ("let\n  é == 1;", ((2, 5), (2, 7))),
+        ];
+        for (input, expected) in tests {
+            let errors = Parser::new(Lexer::new(input)).parse_program().unwrap_err();
+            let span = errors[0].span.unwrap();
+            assert_eq!((span.start(), span.end()), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn it_parses_assignments() {
+        let program = Parser::new(Lexer::new("x = 5; a = b = c + 1;"))
+            .parse_program()
+            .unwrap();
+        assert_eq!(program.len(), 2);
+        check_expression_statement(
+            &program[0],
+            &Expression::Assign(
+                "x".into(),
+                Box::new(Expression::Literal(Literal::Integer(5))),
+            ),
+        );
+        check_expression_statement(
+            &program[1],
+            &Expression::Assign(
+                "a".into(),
+                Box::new(Expression::Assign(
+                    "b".into(),
+                    Box::new(Expression::Infix(
+                        Box::new(Expression::Identifier("c".into())),
+                        Token::Plus,
+                        Box::new(Expression::Literal(Literal::Integer(1))),
+                    )),
+                )),
+            ),
+        );
+
+        let tests = vec![
+            ("1 = 2", "parse error: cannot assign to 1"),
+            ("a + b = 3", "parse error: cannot assign to a + b"),
+            ("f() = 3", "parse error: cannot assign to f()"),
+            ("a[0][1] = 3", "parse error: cannot assign to ((a[0])[1])"),
+            ("[1][0] = 3", "parse error: cannot assign to ([1][0])"),
+        ];
+        for (input, expected) in tests {
+            let errors = Parser::new(Lexer::new(input)).parse_program().unwrap_err();
+            assert_eq!(errors[0].msg, expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn it_parses_while_expressions() {
+        let program = Parser::new(Lexer::new("while (x < y) { x }"))
+            .parse_program()
+            .unwrap();
+        assert_eq!(program.len(), 1);
+        check_expression_statement(
+            &program[0],
+            &Expression::While(
+                Box::new(Expression::Infix(
+                    Box::new(Expression::Identifier("x".into())),
+                    Token::Lt,
+                    Box::new(Expression::Identifier("y".into())),
+                )),
+                vec![Statement::Expression(
+                    Expression::Identifier("x".into()),
+                    false,
+                )],
+            ),
+        );
+    }
+
+    #[test]
+    fn it_rejects_loop_control_statements() {
+        let tests = vec![
+            (
+                "while (true) { break; }",
+                "parse error: break is not supported yet",
+            ),
+            (
+                "while (true) { continue }",
+                "parse error: continue is not supported yet",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let errors = Parser::new(Lexer::new(input)).parse_program().unwrap_err();
+            assert_eq!(errors[0].msg, expected, "{}", input);
+        }
+    }
+
     #[test]
     fn it_parses_function_call_expressions() {
         let input = r#"
                 add(1, 2 * 3, 4 + 5);
                 "#;
-        let lexer = Lexer::new(input.into());
+        let lexer = Lexer::new(input);
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program().unwrap();
         assert_eq!(program.len(), 1);
@@ -799,14 +1193,14 @@ mod test {
                 "#;
         let expected = r#" 
             ((a + add(b * c)) + d);
-            add(a, b, 1, (2 * 3), (4 + 5), add(6, (7 * 8)));
+            add(a, b, 1, (2 * 3), (4 + 5), add(6, (7 * 8)))
             add((((a + b) + ((c * d) / f)) + g))
             "#;
 
-        let lexer = Lexer::new(input.into());
+        let lexer = Lexer::new(input);
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program().unwrap();
-        let expected_lexer = Lexer::new(expected.into());
+        let expected_lexer = Lexer::new(expected);
         let mut expected_parser = Parser::new(expected_lexer);
         let expected_program = expected_parser.parse_program().unwrap();
         assert_eq!(program.len(), expected_program.len());
@@ -815,12 +1209,43 @@ mod test {
         }
     }
 
+    #[test]
+    fn it_parses_method_calls_as_builtin_calls() {
+        let input = r#"
+                arr.len();
+                arr.push(x + 1);
+                "a,b".split(",").len();
+                -a.first() * b;
+                [1, 2][0].len();
+                "#;
+        let expected = r#"
+                len(arr);
+                push(arr, x + 1);
+                len(split("a,b", ","));
+                (-first(a)) * b;
+                len([1, 2][0]);
+                "#;
+
+        let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+        let expected_program = Parser::new(Lexer::new(expected)).parse_program().unwrap();
+        assert_eq!(program, expected_program);
+
+        let errors = Parser::new(Lexer::new("arr.1()"))
+            .parse_program()
+            .unwrap_err();
+        assert_eq!(
+            errors[0].msg,
+            "parse error: expected method name, got Int(1)"
+        );
+        assert!(Parser::new(Lexer::new("arr.len")).parse_program().is_err());
+    }
+
     #[test]
     fn it_parses_string_literal_expressions() {
         let input = r#"
                 "hello world";
                 "#;
-        let lexer = Lexer::new(input.into());
+        let lexer = Lexer::new(input);
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program().unwrap();
         assert_eq!(program.len(), 1);
@@ -835,7 +1260,7 @@ mod test {
         let input = r#"
                 myArray[1 + 1];
                 "#;
-        let lexer = Lexer::new(input.into());
+        let lexer = Lexer::new(input);
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program().unwrap();
         assert_eq!(program.len(), 1);
@@ -859,7 +1284,7 @@ mod test {
                 [1, 2, 3];
                 [];
                 "#;
-        let lexer = Lexer::new(input.into());
+        let lexer = Lexer::new(input);
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program().unwrap();
         assert_eq!(program.len(), 3);
@@ -901,7 +1326,7 @@ mod test {
                 {"one": 0 + 1, "two": 10 - 8, "three": 15 / 5};
                 {};"#;
 
-        let lexer = Lexer::new(input.into());
+        let lexer = Lexer::new(input);
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program().unwrap();
         assert_eq!(program.len(), 4);
@@ -990,7 +1415,7 @@ mod test {
         let input = r#"
                 macro(x, y) { x + y; };
                 "#;
-        let lexer = Lexer::new(input.into());
+        let lexer = Lexer::new(input);
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program().unwrap();
 
@@ -999,18 +1424,21 @@ mod test {
             &program[0],
             &Expression::Macro(
                 vec!["x".into(), "y".into()],
-                vec![Statement::Expression(Expression::Infix(
-                    Box::new(Expression::Identifier("x".into())),
-                    Token::Plus,
-                    Box::new(Expression::Identifier("y".into())),
-                ))],
+                vec![Statement::Expression(
+                    Expression::Infix(
+                        Box::new(Expression::Identifier("x".into())),
+                        Token::Plus,
+                        Box::new(Expression::Identifier("y".into())),
+                    ),
+                    true,
+                )],
             ),
         );
     }
 
     fn check_expression_statement(statement: &Statement, expected_value: &Expression) {
         match statement {
-            Statement::Expression(expression) => check_expression(expression, expected_value),
+            Statement::Expression(expression, _) => check_expression(expression, expected_value),
             _ => panic!("Expected expression statement"),
         }
     }
@@ -1025,6 +1453,9 @@ mod test {
                     (Literal::Integer(i), Literal::Integer(expected_i)) => {
                         assert_eq!(i, expected_i);
                     }
+                    (Literal::Float(x), Literal::Float(expected_x)) => {
+                        assert_eq!(x, expected_x);
+                    }
                     (Literal::Boolean(b), Literal::Boolean(expected_b)) => {
                         assert_eq!(b, expected_b);
                     }
@@ -1054,21 +1485,21 @@ mod test {
                 Expression::Prefix(expected_token, expected_inner_expr),
             ) => {
                 assert_eq!(token, expected_token);
-                check_expression(&**inner_expr, &**expected_inner_expr);
+                check_expression(inner_expr, expected_inner_expr);
             }
             (
                 Expression::Infix(left_expr, token, right_expr),
                 Expression::Infix(expected_left_expr, expected_token, expected_right_expr),
             ) => {
                 assert_eq!(token, expected_token);
-                check_expression(&**left_expr, &**expected_left_expr);
-                check_expression(&**right_expr, &**expected_right_expr);
+                check_expression(left_expr, expected_left_expr);
+                check_expression(right_expr, expected_right_expr);
             }
             (
                 Expression::If(condition, consequence, alternative),
                 Expression::If(expected_condition, expected_consequence, expected_alternative),
             ) => {
-                check_expression(&**condition, &**expected_condition);
+                check_expression(condition, expected_condition);
                 for (statement, expected_statement) in
                     consequence.iter().zip(expected_consequence.iter())
                 {
@@ -1094,7 +1525,7 @@ mod test {
                 Expression::FunctionCall(function, arguments),
                 Expression::FunctionCall(expected_function, expected_arguments),
             ) => {
-                check_expression(&**function, &**expected_function);
+                check_expression(function, expected_function);
                 for (argument, expected_argument) in arguments.iter().zip(expected_arguments.iter())
                 {
                     check_expression(argument, expected_argument);
@@ -1104,8 +1535,22 @@ mod test {
                 Expression::Index(left_expr, index_expr),
                 Expression::Index(expected_left_expr, expected_index_expr),
             ) => {
-                check_expression(&**left_expr, &**expected_left_expr);
-                check_expression(&**index_expr, &**expected_index_expr);
+                check_expression(left_expr, expected_left_expr);
+                check_expression(index_expr, expected_index_expr);
+            }
+            (
+                Expression::While(condition, body),
+                Expression::While(expected_condition, expected_body),
+            ) => {
+                check_expression(condition, expected_condition);
+                assert_eq!(body, expected_body);
+            }
+            (
+                Expression::Assign(name, value),
+                Expression::Assign(expected_name, expected_value),
+            ) => {
+                assert_eq!(name, expected_name);
+                check_expression(value, expected_value);
             }
             (
                 Expression::Macro(params, body),
@@ -1126,7 +1571,7 @@ mod test {
         let input = r#"
         let myFunction = fn() { 5 + 5; };
         "#;
-        let lexer = Lexer::new(input.into());
+        let lexer = Lexer::new(input);
         let mut parser = Parser::new(lexer);
         let program = parser.parse_program().unwrap();
         assert_eq!(program.len(), 1);
@@ -1142,6 +1587,118 @@ mod test {
         }
     }
 
+    #[test]
+    fn it_parses_named_function_declarations() {
+        let input = r#"
+        fn add(x, y) { x + y; }
+        fn noop() {};
+        fn(x) { x };
+        "#;
+        let lexer = Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().unwrap();
+        assert_eq!(program.len(), 3);
+        assert_eq!(
+            program[0],
+            Statement::Let(
+                "add".into(),
+                Expression::Function(
+                    Some("add".into()),
+                    vec!["x".into(), "y".into()],
+                    vec![Statement::Expression(
+                        Expression::Infix(
+                            Box::new(Expression::Identifier("x".into())),
+                            Token::Plus,
+                            Box::new(Expression::Identifier("y".into())),
+                        ),
+                        true
+                    )],
+                ),
+            )
+        );
+        assert_eq!(
+            program[1],
+            Statement::Let(
+                "noop".into(),
+                Expression::Function(Some("noop".into()), vec![], vec![]),
+            )
+        );
+        check_expression_statement(
+            &program[2],
+            &Expression::Function(
+                None,
+                vec!["x".into()],
+                vec![Statement::Expression(
+                    Expression::Identifier("x".into()),
+                    false,
+                )],
+            ),
+        );
+    }
+
+    #[test]
+    fn it_rejects_deeply_nested_expressions() {
+        // nesting through blocks is limited too, and the error reaches the
+        // program from inside the innermost block
+        let inputs = [
+            "(".repeat(10_000),
+            "-".repeat(10_000),
+            "[".repeat(10_000),
+            "fn() {".repeat(10_000),
+            "if (true) {".repeat(10_000),
+        ];
+        for input in inputs.iter() {
+            let lexer = Lexer::new(input);
+            let mut parser = Parser::new(lexer);
+            assert!(parser.parse_program().is_err());
+        }
+
+        let input = format!("{}1{}", "(".repeat(100), ")".repeat(100));
+        let lexer = Lexer::new(&input);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().unwrap();
+        assert_eq!(
+            program[0],
+            Statement::Expression(Expression::Literal(Literal::Integer(1)), false)
+        );
+    }
+
+    #[test]
+    fn it_parses_computed_hash_keys() {
+        let input = r#"{[1 + 1]: "two", [x]: 1, [1, 2][0]: 3}"#;
+
+        let lexer = Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program().unwrap();
+        check_expression_statement(
+            &program[0],
+            &Expression::Literal(Literal::Hash(vec![
+                (
+                    Expression::Infix(
+                        Box::new(Expression::Literal(Literal::Integer(1))),
+                        Token::Plus,
+                        Box::new(Expression::Literal(Literal::Integer(1))),
+                    ),
+                    Expression::Literal(Literal::String("two".into())),
+                ),
+                (
+                    Expression::Identifier("x".to_string()),
+                    Expression::Literal(Literal::Integer(1)),
+                ),
+                (
+                    Expression::Index(
+                        Box::new(Expression::Literal(Literal::Array(Rc::new(vec![
+                            Expression::Literal(Literal::Integer(1)),
+                            Expression::Literal(Literal::Integer(2)),
+                        ])))),
+                        Box::new(Expression::Literal(Literal::Integer(0))),
+                    ),
+                    Expression::Literal(Literal::Integer(3)),
+                ),
+            ])),
+        );
+    }
+
     fn check_let_statement(s: &Statement, name: &str, expected_exp: &Expression) {
         match s {
             Statement::Let(ref ident, ref exp) => {
@@ -1,8 +1,13 @@
+use super::registry;
 use crate::token::Token;
 
 #[derive(PartialOrd, PartialEq, Debug, Copy, Clone)]
 pub enum Precedence {
     Lowest,
+    Assign,      // =
+    Coalesce,    // ??
+    Or,          // ||
+    And,         // &&
     Equals,      // ==
     LessGreater, // > or <
     Sum,         // +
@@ -13,13 +18,5 @@ pub enum Precedence {
 }
 
 pub fn token_precedence(token: &Token) -> Precedence {
-    match token {
-        Token::Eq | Token::NotEq => Precedence::Equals,
-        Token::Plus | Token::Dash => Precedence::Sum,
-        Token::Lt | Token::Gt => Precedence::LessGreater,
-        Token::Slash | Token::Asterisk => Precedence::Product,
-        Token::Lparen => Precedence::Call,
-        Token::LBracket => Precedence::Index,
-        _ => Precedence::Lowest,
-    }
+    registry::infix_fn(token).map_or(Precedence::Lowest, |(precedence, _)| precedence)
 }
@@ -0,0 +1,141 @@
+use super::ast::Expression;
+use super::errors::ParserError;
+use super::precedence::Precedence;
+use super::Parser;
+
+use crate::token::Token;
+
+use std::collections::HashMap;
+use std::mem::{discriminant, Discriminant};
+use std::sync::OnceLock;
+
+pub type PrefixFn = fn(&mut Parser) -> Result<Expression, ParserError>;
+pub type InfixFn = fn(&mut Parser, Expression) -> Result<Expression, ParserError>;
+
+// Tokens carrying data (identifiers, literals) are registered by variant, so
+// any placeholder value works as the key.
+static PREFIX_FNS: OnceLock<HashMap<Discriminant<Token>, PrefixFn>> = OnceLock::new();
+static INFIX_FNS: OnceLock<HashMap<Discriminant<Token>, (Precedence, InfixFn)>> = OnceLock::new();
+
+fn prefix_fns() -> &'static HashMap<Discriminant<Token>, PrefixFn> {
+    PREFIX_FNS.get_or_init(|| {
+        let entries: [(Token, PrefixFn); 19] = [
+            (Token::Ident(String::new()), Parser::parse_identifier),
+            (Token::Int(0), Parser::parse_integer_literal),
+            (Token::Float(0.0), Parser::parse_float_literal),
+            (Token::True, Parser::parse_boolean_literal),
+            (Token::False, Parser::parse_boolean_literal),
+            (Token::Null, Parser::parse_null_literal),
+            (Token::String(String::new()), Parser::parse_string_literal),
+            (Token::Bang, Parser::parse_prefix_expression),
+            (Token::Dash, Parser::parse_prefix_expression),
+            (Token::Lparen, Parser::parse_grouped_expression),
+            (Token::If, Parser::parse_if_expression),
+            (Token::While, Parser::parse_while_expression),
+            (Token::Break, Parser::parse_loop_control),
+            (Token::Continue, Parser::parse_loop_control),
+            (Token::Function, Parser::parse_function_expression),
+            (Token::Macro, Parser::parse_macro_expression),
+            (Token::LBracket, Parser::parse_array_literal),
+            (Token::Lbrace, Parser::parse_hash_literal),
+            (Token::Illegal(String::new()), Parser::parse_illegal_token),
+        ];
+        entries
+            .into_iter()
+            .map(|(token, f)| (discriminant(&token), f))
+            .collect()
+    })
+}
+
+fn infix_fns() -> &'static HashMap<Discriminant<Token>, (Precedence, InfixFn)> {
+    INFIX_FNS.get_or_init(|| {
+        let entries: [(Token, Precedence, InfixFn); 18] = [
+            (
+                Token::Assign,
+                Precedence::Assign,
+                Parser::parse_assign_expression,
+            ),
+            (
+                Token::Coalesce,
+                Precedence::Coalesce,
+                Parser::parse_infix_expression,
+            ),
+            (Token::Or, Precedence::Or, Parser::parse_infix_expression),
+            (Token::And, Precedence::And, Parser::parse_infix_expression),
+            (
+                Token::Eq,
+                Precedence::Equals,
+                Parser::parse_infix_expression,
+            ),
+            (
+                Token::NotEq,
+                Precedence::Equals,
+                Parser::parse_infix_expression,
+            ),
+            (
+                Token::Lt,
+                Precedence::LessGreater,
+                Parser::parse_infix_expression,
+            ),
+            (
+                Token::Gt,
+                Precedence::LessGreater,
+                Parser::parse_infix_expression,
+            ),
+            (
+                Token::LtEq,
+                Precedence::LessGreater,
+                Parser::parse_infix_expression,
+            ),
+            (
+                Token::GtEq,
+                Precedence::LessGreater,
+                Parser::parse_infix_expression,
+            ),
+            (Token::Plus, Precedence::Sum, Parser::parse_infix_expression),
+            (Token::Dash, Precedence::Sum, Parser::parse_infix_expression),
+            (
+                Token::Slash,
+                Precedence::Product,
+                Parser::parse_infix_expression,
+            ),
+            (
+                Token::Asterisk,
+                Precedence::Product,
+                Parser::parse_infix_expression,
+            ),
+            (
+                Token::Percent,
+                Precedence::Product,
+                Parser::parse_infix_expression,
+            ),
+            (
+                Token::Lparen,
+                Precedence::Call,
+                Parser::parse_function_call_expression,
+            ),
+            (
+                Token::LBracket,
+                Precedence::Index,
+                Parser::parse_index_expression,
+            ),
+            (
+                Token::Dot,
+                Precedence::Call,
+                Parser::parse_method_call_expression,
+            ),
+        ];
+        entries
+            .into_iter()
+            .map(|(token, precedence, f)| (discriminant(&token), (precedence, f)))
+            .collect()
+    })
+}
+
+pub fn prefix_fn(token: &Token) -> Option<PrefixFn> {
+    prefix_fns().get(&discriminant(token)).copied()
+}
+
+pub fn infix_fn(token: &Token) -> Option<(Precedence, InfixFn)> {
+    infix_fns().get(&discriminant(token)).copied()
+}
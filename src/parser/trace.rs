@@ -0,0 +1,86 @@
+use std::cell::RefCell;
+use std::fmt::Display;
+use std::io::Write;
+
+use super::ast::Expression;
+use super::errors::ParserError;
+use super::precedence::Precedence;
+use crate::token::Token;
+
+// Where `--trace-parse` logs each `parse_expression` call; nothing is
+// formatted while no sink is set.
+thread_local! {
+    static SINK: RefCell<Option<Box<dyn Write>>> = const { RefCell::new(None) };
+}
+
+/// Logs every `parse_expression` entry and exit to `sink`, indented by
+/// nesting depth; `None` turns tracing off again.
+pub fn set_sink(sink: Option<Box<dyn Write>>) {
+    SINK.with(|s| *s.borrow_mut() = sink);
+}
+
+pub(super) fn enter(depth: usize, precedence: Precedence, token: &Token) {
+    log(depth, format_args!("enter {:?} at {}", precedence, token));
+}
+
+pub(super) fn exit(depth: usize, result: &Result<Expression, ParserError>) {
+    match result {
+        Ok(expression) => log(depth, format_args!("exit {}", expression)),
+        Err(err) => log(depth, format_args!("exit with {}", err)),
+    }
+}
+
+fn log(depth: usize, line: impl Display) {
+    SINK.with(|sink| {
+        if let Some(sink) = sink.borrow_mut().as_mut() {
+            // tracing is a debugging aid, so a failed write is not worth surfacing
+            let _ = writeln!(sink, "{}{}", "  ".repeat(depth - 1), line);
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use std::io;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn it_traces_expression_nesting() {
+        let buffer = Buffer::default();
+        set_sink(Some(Box::new(buffer.clone())));
+        Parser::new(Lexer::new("1 + 2 * 3"))
+            .parse_program()
+            .unwrap();
+        set_sink(None);
+
+        let expected = "\
+enter Lowest at 1
+  enter Sum at 2
+    enter Product at 3
+    exit 3
+  exit 2 * 3
+exit 1 + 2 * 3
+";
+        assert_eq!(String::from_utf8(buffer.0.take()).unwrap(), expected);
+
+        Parser::new(Lexer::new("1 + 2")).parse_program().unwrap();
+        assert!(buffer.0.borrow().is_empty());
+    }
+}
@@ -1,12 +1,35 @@
 use std::fmt::{Display, Formatter, Result};
 
-#[derive(Debug, PartialEq, Clone, Ord, PartialOrd, Eq)]
+/// Where a token sits in the source: 1-based line and column, with the column
+/// and length counted in characters rather than bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub line: usize,
+    pub col: usize,
+    pub len: usize,
+}
+
+impl Span {
+    /// `(line, col)` of the token's first character.
+    pub fn start(&self) -> (usize, usize) {
+        (self.line, self.col)
+    }
+
+    /// `(line, col)` just past the token's last character, counted on the
+    /// token's first line.
+    pub fn end(&self) -> (usize, usize) {
+        (self.line, self.col + self.len)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum Token {
-    Illegal,
+    Illegal(String),
     Eof,
 
     Ident(String),
     Int(i64),
+    Float(f64),
     String(String),
 
     // operators
@@ -16,13 +39,21 @@ pub enum Token {
     Bang,
     Asterisk,
     Slash,
+    Percent,
 
     // comparators
     Lt,
     Gt,
+    LtEq,
+    GtEq,
     Eq,
     NotEq,
 
+    // logical
+    And,
+    Or,
+    Coalesce,
+
     Comma,
     Semicolon,
     Lparen,
@@ -32,36 +63,52 @@ pub enum Token {
     LBracket,
     RBracket,
     Colon,
+    Dot,
 
     // keywords
     Function,
     Let,
     True,
     False,
+    Null,
     If,
     Else,
     Return,
     Macro,
+    While,
+    Break,
+    Continue,
 }
 
+// the lexer never produces a NaN float, so equality stays reflexive
+impl Eq for Token {}
+
 impl Display for Token {
     fn fmt(&self, f: &mut Formatter) -> Result {
-        return match self {
-            Token::Illegal => write!(f, "Illegal"),
+        match self {
+            Token::Illegal(s) => write!(f, "Illegal({})", s),
             Token::Colon => write!(f, ":"),
+            Token::Dot => write!(f, "."),
             Token::Eof => write!(f, "Eof"),
             Token::Ident(s) => write!(f, "{}", s),
             Token::Int(s) => write!(f, "{}", s),
+            Token::Float(x) => write!(f, "{:?}", x),
             Token::Assign => write!(f, "="),
             Token::Plus => write!(f, "+"),
             Token::Dash => write!(f, "-"),
             Token::Bang => write!(f, "!"),
             Token::Asterisk => write!(f, "*"),
             Token::Slash => write!(f, "/"),
+            Token::Percent => write!(f, "%"),
             Token::Lt => write!(f, "<"),
             Token::Gt => write!(f, ">"),
+            Token::LtEq => write!(f, "<="),
+            Token::GtEq => write!(f, ">="),
             Token::Eq => write!(f, "=="),
             Token::NotEq => write!(f, "!="),
+            Token::And => write!(f, "&&"),
+            Token::Or => write!(f, "||"),
+            Token::Coalesce => write!(f, "??"),
             Token::Comma => write!(f, ","),
             Token::Semicolon => write!(f, ";"),
             Token::Lparen => write!(f, "("),
@@ -75,10 +122,14 @@ impl Display for Token {
             Token::Let => write!(f, "let"),
             Token::True => write!(f, "true"),
             Token::False => write!(f, "false"),
+            Token::Null => write!(f, "null"),
             Token::If => write!(f, "if"),
             Token::Else => write!(f, "else"),
             Token::Return => write!(f, "return"),
+            Token::While => write!(f, "while"),
+            Token::Break => write!(f, "break"),
+            Token::Continue => write!(f, "continue"),
             Token::String(s) => write!(f, "{}", s),
-        };
+        }
     }
 }
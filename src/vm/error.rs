@@ -1,13 +1,41 @@
 use thiserror::Error;
 
+use crate::object::error::ObjectError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmErrorKind {
+    StackOverflow,
+    TypeMismatch,
+    DivisionByZero,
+    IntegerOverflow,
+    UndefinedGlobal,
+    Arity,
+    UnknownOpcode,
+    NotCallable,
+    Builtin,
+    Index,
+    Internal,
+}
+
 #[derive(Debug, Clone, Error)]
 #[error("{msg}")]
 pub struct VmError {
+    pub kind: VmErrorKind,
     pub msg: String,
 }
 
 impl VmError {
     pub fn new(msg: String) -> Self {
-        VmError { msg }
+        VmError::with_kind(VmErrorKind::Internal, msg)
+    }
+
+    pub fn with_kind(kind: VmErrorKind, msg: String) -> Self {
+        VmError { kind, msg }
+    }
+}
+
+impl From<ObjectError> for VmError {
+    fn from(err: ObjectError) -> Self {
+        VmError::with_kind(VmErrorKind::Builtin, err.msg)
     }
 }
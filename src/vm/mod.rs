@@ -4,11 +4,11 @@ pub mod frame;
 use crate::{
     code::{self, Instructions, Opcode},
     compiler,
-    object::{CompiledFunction, Object},
+    object::{builtin::Builtin, CompiledFunction, Object},
 };
-use error::VmError;
+use error::{VmError, VmErrorKind};
 
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{cell::RefCell, collections::HashMap, fmt::Display, mem::discriminant, rc::Rc};
 
 use self::frame::Frame;
 
@@ -23,9 +23,17 @@ pub struct VM {
     pub globals: Rc<RefCell<Vec<Rc<Object>>>>,
     pub frames: Vec<Frame>,
     pub frame_index: usize,
+    // shared results for every boolean-producing instruction
+    true_object: Rc<Object>,
+    false_object: Rc<Object>,
+    // executions per opcode byte
+    #[cfg(feature = "profile")]
+    opcode_counts: [u64; 256],
 }
 
 impl VM {
+    // the stack and globals start out sharing a single `Null`
+    #[allow(clippy::rc_clone_in_vec_init)]
     pub fn new(bytecode: compiler::Bytecode) -> Self {
         let main_fn = Rc::new(CompiledFunction::new(bytecode.instructions, GLOBAL_SIZE, 0));
         let main_closure = Object::Closure(main_fn, vec![]);
@@ -49,16 +57,22 @@ impl VM {
 
         frames[0] = main_frame;
 
-        return VM {
+        VM {
             constants: bytecode.constants,
             stack: vec![Rc::new(Object::Null); STACK_SIZE],
             sp: 0,
             globals: Rc::new(RefCell::new(vec![Rc::new(Object::Null); GLOBAL_SIZE])),
             frames,
             frame_index: 1,
-        };
+            true_object: Rc::new(Object::Boolean(true)),
+            false_object: Rc::new(Object::Boolean(false)),
+            #[cfg(feature = "profile")]
+            opcode_counts: [0; 256],
+        }
     }
 
+    // the stack and globals start out sharing a single `Null`
+    #[allow(clippy::rc_clone_in_vec_init)]
     pub fn new_with_global_store(
         bytecode: compiler::Bytecode,
         globals: Rc<RefCell<Vec<Rc<Object>>>>,
@@ -87,23 +101,75 @@ impl VM {
 
         frames[0] = main_frame;
 
-        return VM {
+        VM {
             constants: bytecode.constants,
             stack: vec![Rc::new(Object::Null); STACK_SIZE],
             sp: 0,
             globals,
             frames,
             frame_index: 1,
-        };
+            true_object: Rc::new(Object::Boolean(true)),
+            false_object: Rc::new(Object::Boolean(false)),
+            #[cfg(feature = "profile")]
+            opcode_counts: [0; 256],
+        }
+    }
+
+    /// Loads `bytecode` as the new main program, reusing the stack and frame
+    /// allocations. Globals are kept, so a REPL can run each line on one VM.
+    pub fn reset(&mut self, bytecode: compiler::Bytecode) {
+        let main_fn = Rc::new(Object::Closure(
+            Rc::new(CompiledFunction::new(bytecode.instructions, GLOBAL_SIZE, 0)),
+            vec![],
+        ));
+        self.frames[0] = Frame::new(main_fn, 0).unwrap();
+        self.frame_index = 1;
+        self.sp = 0;
+        self.constants = bytecode.constants;
+    }
+
+    /// How many times each opcode has been executed, for the `profile` feature.
+    #[cfg(feature = "profile")]
+    pub fn opcode_counts(&self) -> HashMap<Opcode, u64> {
+        Opcode::all()
+            .iter()
+            .filter(|op| self.opcode_counts[**op as usize] > 0)
+            .map(|op| (*op, self.opcode_counts[*op as usize]))
+            .collect()
     }
 
     pub fn current_frame(&mut self) -> &mut Frame {
         &mut self.frames[self.frame_index - 1]
     }
 
-    pub fn push_frame(&mut self, frame: Frame) {
+    // stack slot of a local in the current frame, checked against the locals the
+    // frame's function reserved
+    fn local_slot(&mut self, local_index: usize) -> Result<usize, VmError> {
+        let frame = self.current_frame();
+        let num_locals = match &*frame.function {
+            Object::Closure(function, _) => function.num_locals(),
+            _ => 0,
+        };
+        let slot = frame.base_pointer + local_index;
+        if local_index >= num_locals || slot >= STACK_SIZE {
+            return Err(VmError::new(format!(
+                "local index {} out of range for a frame with {} locals",
+                local_index, num_locals
+            )));
+        }
+        Ok(slot)
+    }
+
+    pub fn push_frame(&mut self, frame: Frame) -> Result<(), VmError> {
+        if self.frame_index >= MAX_FRAMES {
+            return Err(VmError::with_kind(
+                VmErrorKind::StackOverflow,
+                "call stack overflow".to_string(),
+            ));
+        }
         self.frames[self.frame_index] = frame;
         self.frame_index += 1;
+        Ok(())
     }
 
     pub fn pop_frame(&mut self) -> &mut Frame {
@@ -119,7 +185,33 @@ impl VM {
     }
 
     pub fn run(&mut self) -> Result<(), VmError> {
-        while self.current_frame().ip < (self.current_frame().instructions()?.len() - 1) as isize {
+        self.run_frames(0)
+    }
+
+    /// Calls `function` with `args` and runs it to completion, for code outside
+    /// the instruction loop that needs a result back, such as a memoized call.
+    pub fn call(
+        &mut self,
+        function: Rc<Object>,
+        args: &[Rc<Object>],
+    ) -> Result<Rc<Object>, VmError> {
+        let depth = self.frame_index;
+        self.push(function)?;
+        for arg in args {
+            self.push(Rc::clone(arg))?;
+        }
+        self.call_function(args.len())?;
+        self.run_frames(depth)?;
+        self.pop()
+    }
+
+    // Runs until only `depth` frames are left, or the current frame runs out of
+    // instructions. The main frame never returns, so a depth of 0 runs it to the end.
+    fn run_frames(&mut self, depth: usize) -> Result<(), VmError> {
+        // `ip` is the last byte executed and starts at -1; empty programs are fine
+        while self.frame_index > depth
+            && self.current_frame().ip + 1 < self.current_frame().instructions()?.len() as isize
+        {
             self.current_frame().ip += 1;
 
             let instructions = self.current_frame().instructions()?;
@@ -129,39 +221,54 @@ impl VM {
                 .try_into()
                 .map_err(|_| VmError::new("Invalid IP".to_string()))?;
 
-            let opcode = instructions[ip];
+            let opcode = Opcode::from_byte(instructions[ip]).ok_or_else(|| {
+                VmError::with_kind(
+                    VmErrorKind::UnknownOpcode,
+                    format!("unknown opcode {} at {}", instructions[ip], ip),
+                )
+            })?;
 
-            match opcode.into() {
+            #[cfg(feature = "profile")]
+            {
+                self.opcode_counts[opcode as usize] += 1;
+            }
+
+            match opcode {
                 Opcode::Constant => {
                     let constant_index = code::read_u16(&instructions, ip + 1) as usize;
                     self.current_frame().ip += 2;
-                    let constants = self.constants.borrow().clone();
+                    self.push_constant(constant_index)?;
+                }
 
-                    if constant_index > constants.len() {
-                        return Err(VmError::new("Invalid constant index".to_string()));
-                    }
-                    let constant = Rc::clone(&constants[constant_index]);
-                    self.push(constant);
+                Opcode::ConstantWide => {
+                    let constant_index = code::read_u32(&instructions, ip + 1) as usize;
+                    self.current_frame().ip += 4;
+                    self.push_constant(constant_index)?;
                 }
 
-                Opcode::Add | Opcode::Sub | Opcode::Mul | Opcode::Div => {
-                    self.execute_binary_instruction(opcode.into())?;
+                Opcode::Add | Opcode::Sub | Opcode::Mul | Opcode::Div | Opcode::Mod => {
+                    self.execute_binary_instruction(opcode)?;
                 }
 
                 Opcode::Pop => {
-                    self.pop();
+                    self.pop()?;
                 }
 
                 Opcode::True => {
-                    self.push(Rc::new(Object::Boolean(true)));
+                    self.push(self.boolean(true))?;
                 }
 
                 Opcode::False => {
-                    self.push(Rc::new(Object::Boolean(false)));
+                    self.push(self.boolean(false))?;
                 }
 
-                Opcode::Equal | Opcode::NotEqual | Opcode::GreaterThan => {
-                    self.execute_comparison(opcode.into())?;
+                Opcode::Equal
+                | Opcode::NotEqual
+                | Opcode::GreaterThan
+                | Opcode::LessThan
+                | Opcode::LessEqual
+                | Opcode::GreaterEqual => {
+                    self.execute_comparison(opcode)?;
                 }
 
                 Opcode::Bang => {
@@ -174,26 +281,46 @@ impl VM {
 
                 Opcode::Jump => {
                     let position = code::read_u16(&instructions, ip + 1) as usize;
-                    self.current_frame().ip = (position - 1) as isize;
+                    self.current_frame().ip = position as isize - 1;
                 }
 
                 Opcode::JumpNotTruthy => {
                     let maybe_jump_position = code::read_u16(&instructions, ip + 1) as usize;
                     self.current_frame().ip += 2;
-                    let condition = self.pop();
-                    if !self.is_truthy(condition) {
-                        self.current_frame().ip = (maybe_jump_position - 1) as isize;
+                    let condition = self.pop()?;
+                    if !condition.is_truthy() {
+                        self.current_frame().ip = maybe_jump_position as isize - 1;
                     }
                 }
 
                 Opcode::Null => {
-                    self.push(Rc::new(Object::Null));
+                    self.push(Rc::new(Object::Null))?;
+                }
+
+                // `??` keeps a non-null left side and jumps past the right one
+                Opcode::JumpNotNull => {
+                    let maybe_jump_position = code::read_u16(&instructions, ip + 1) as usize;
+                    self.current_frame().ip += 2;
+                    let value = self.pop()?;
+                    if !matches!(*value, Object::Null) {
+                        self.push(value)?;
+                        self.current_frame().ip = maybe_jump_position as isize - 1;
+                    }
                 }
 
                 Opcode::SetGlobal => {
                     let symbol_index = code::read_u16(&instructions, ip + 1) as usize;
                     self.current_frame().ip += 2;
-                    self.globals.borrow_mut()[symbol_index] = self.pop();
+                    let value = self.pop()?;
+                    match self.globals.borrow_mut().get_mut(symbol_index) {
+                        Some(global) => *global = value,
+                        None => {
+                            return Err(VmError::new(format!(
+                                "global index {} out of range",
+                                symbol_index
+                            )));
+                        }
+                    }
                 }
 
                 Opcode::GetGlobal => {
@@ -206,103 +333,92 @@ impl VM {
                     // Check if the global variable exists at the given index
                     if let Some(global) = global {
                         // Push the cloned global variable onto the stack
-                        self.push(global);
+                        self.push(global)?;
                     } else {
                         // Handle the case when the global variable doesn't exist
-                        return Err(VmError::new("Global variable not found".to_string()));
+                        return Err(VmError::with_kind(
+                            VmErrorKind::UndefinedGlobal,
+                            "Global variable not found".to_string(),
+                        ));
                     }
                 }
 
                 Opcode::Array => {
                     let num_elements = code::read_u16(&instructions, ip + 1) as usize;
                     self.current_frame().ip += 2;
-                    let array = self.build_array(self.sp - num_elements, self.sp);
-                    self.sp = self.sp - num_elements;
-                    self.push(Rc::new(array));
+                    let start = self.stack_below(num_elements)?;
+                    let array = self.build_array(start, self.sp);
+                    self.sp = start;
+                    self.push(array)?;
                 }
 
                 Opcode::Hash => {
                     let num_elements = code::read_u16(&instructions, ip + 1) as usize;
                     self.current_frame().ip += 2;
-                    let hash = self.build_hash(self.sp - num_elements, self.sp);
-                    self.sp = self.sp - num_elements;
-                    self.push(Rc::new(hash));
+                    let start = self.stack_below(num_elements)?;
+                    let hash = self.build_hash(start, self.sp)?;
+                    self.sp = start;
+                    self.push(Rc::new(hash))?;
                 }
 
                 Opcode::Index => {
-                    let index = self.pop();
-                    let indexable = self.pop();
+                    let index = self.pop()?;
+                    let indexable = self.pop()?;
 
                     self.execute_index_expression(indexable, index)?;
                 }
 
+                Opcode::SetIndex => {
+                    let value = self.pop()?;
+                    let index = self.pop()?;
+                    let container = self.pop()?;
+
+                    let updated = container
+                        .with_index(&index, Rc::clone(&value))
+                        .map_err(|e| VmError::with_kind(VmErrorKind::Index, e.msg))?;
+                    self.push(value)?;
+                    self.push(updated)?;
+                }
+
                 Opcode::Call => {
                     let num_args = code::read_u8(&instructions, ip + 1) as usize;
                     self.current_frame().ip += 1;
-
-                    let fun = self.stack[self.sp - 1 - num_args].clone();
-                    match &*fun {
-                        Object::Closure(compiled_function, num_free) => {
-                            if num_args != compiled_function.num_parameters() {
-                                return Err(VmError::new(format!(
-                                    "Invalid number of arguments: want {}, got {}",
-                                    num_args,
-                                    compiled_function.num_parameters()
-                                )));
-                            }
-                            let frame = Frame::new(fun.clone(), self.sp - num_args)?;
-                            let base_pointer = frame.base_pointer;
-                            self.push_frame(frame);
-                            self.sp = base_pointer + compiled_function.num_locals();
-                        }
-                        Object::Builtin(builtin) => {
-                            let args = &self.stack[self.sp - num_args..self.sp].to_vec();
-                            let result = builtin
-                                .apply(args)
-                                .map_err(|e| VmError::new(e.to_string()))?;
-                            self.sp -= num_args + 1;
-                            self.push(result);
-                        }
-                        _ => {
-                            return Err(VmError::new("Calling non-function".to_string()));
-                        }
-                    }
+                    self.call_function(num_args)?;
                 }
 
                 Opcode::ReturnValue => {
-                    let return_value = self.pop();
-                    let frame = self.pop_frame();
-                    self.sp = frame.base_pointer - 1;
-
-                    self.push(return_value);
+                    let return_value = self.pop()?;
+                    self.return_from_frame(return_value)?;
                 }
 
                 Opcode::Return => {
-                    let frame = self.pop_frame();
-                    self.sp = frame.base_pointer - 1;
-                    self.push(Rc::new(Object::Null));
+                    self.return_from_frame(Rc::new(Object::Null))?;
                 }
 
                 Opcode::SetLocal => {
                     let local_index = code::read_u8(&instructions, ip + 1) as usize;
                     self.current_frame().ip += 1;
-                    let frame = self.current_frame();
-                    let base_pointer = frame.base_pointer;
-                    self.stack[base_pointer + local_index] = self.pop();
+                    let slot = self.local_slot(local_index)?;
+                    self.stack[slot] = self.pop()?;
                 }
 
                 Opcode::GetLocal => {
                     let local_index = code::read_u8(&instructions, ip + 1) as usize;
                     self.current_frame().ip += 1;
-                    let frame = self.current_frame();
-                    let base_pointer = frame.base_pointer;
-                    self.push(self.stack[base_pointer + local_index].clone());
+                    let slot = self.local_slot(local_index)?;
+                    self.push(self.stack[slot].clone())?;
                 }
 
                 Opcode::GetBuiltin => {
                     let builtin_index = code::read_u8(&instructions, ip + 1);
                     self.current_frame().ip += 1;
-                    self.push(Rc::new(Object::Builtin(builtin_index.into())));
+                    if builtin_index as usize >= Builtin::variants().len() {
+                        return Err(VmError::new(format!(
+                            "builtin index {} out of range",
+                            builtin_index
+                        )));
+                    }
+                    self.push(Rc::new(Object::Builtin(builtin_index.into())))?;
                 }
 
                 Opcode::Closure => {
@@ -318,9 +434,16 @@ impl VM {
 
                     let current_closure = self.current_frame().function.clone();
                     match &*current_closure {
-                        Object::Closure(_, free_vars) => {
-                            self.push(free_vars[free_index].clone());
-                        }
+                        Object::Closure(_, free_vars) => match free_vars.get(free_index) {
+                            Some(free) => self.push(Rc::clone(free))?,
+                            None => {
+                                return Err(VmError::new(format!(
+                                    "free index {} out of range for a closure with {} free variables",
+                                    free_index,
+                                    free_vars.len()
+                                )));
+                            }
+                        },
                         _ => {
                             return Err(VmError::new(
                                 "tried to find free variables on non-closure".to_string(),
@@ -330,37 +453,123 @@ impl VM {
                 }
                 Opcode::CurrentClosure => {
                     let current_closure = self.current_frame().function.clone();
-                    self.push(current_closure);
+                    self.push(current_closure)?;
                 }
             }
         }
         Ok(())
     }
 
-    fn is_truthy(&self, obj: Rc<Object>) -> bool {
-        let truthy = match *obj {
-            Object::Boolean(b) => b,
-            Object::Null => false,
-            _ => true,
-        };
-        truthy
+    // Replaces the called closure and its arguments with `value`. The main
+    // frame has no caller, so a top-level return ends the program instead,
+    // leaving `value` on top of the stack like a final expression.
+    fn return_from_frame(&mut self, value: Rc<Object>) -> Result<(), VmError> {
+        if self.frame_index == 1 {
+            self.sp = 0;
+            self.push(value)?;
+            let end = self.current_frame().instructions()?.len() as isize;
+            self.current_frame().ip = end - 1;
+            return Ok(());
+        }
+        let frame = self.pop_frame();
+        self.sp = frame.base_pointer - 1;
+        self.push(value)
+    }
+
+    // Calls the function sitting below its `num_args` arguments on the stack.
+    // Closures get a new frame; everything else leaves its result in place of
+    // the function and arguments straight away.
+    fn call_function(&mut self, num_args: usize) -> Result<(), VmError> {
+        let fun = self.stack[self.stack_below(num_args + 1)?].clone();
+        match &*fun {
+            Object::Closure(compiled_function, _num_free) => {
+                if num_args != compiled_function.num_parameters() {
+                    return Err(VmError::with_kind(
+                        VmErrorKind::Arity,
+                        format!(
+                            "wrong number of arguments: got={}, want={}",
+                            num_args,
+                            compiled_function.num_parameters()
+                        ),
+                    ));
+                }
+                let frame = Frame::new(fun.clone(), self.sp - num_args)?;
+                let base_pointer = frame.base_pointer;
+                if base_pointer + compiled_function.num_locals() > STACK_SIZE {
+                    return Err(VmError::with_kind(
+                        VmErrorKind::StackOverflow,
+                        "stack overflow".to_string(),
+                    ));
+                }
+                self.push_frame(frame)?;
+                self.sp = base_pointer + compiled_function.num_locals();
+            }
+            Object::Builtin(builtin) => {
+                let args = self.stack[self.sp - num_args..self.sp].to_vec();
+                let result = builtin.apply(&args, &mut |f, a| self.call(Rc::clone(f), a))?;
+                self.sp -= num_args + 1;
+                self.push(result)?;
+            }
+            Object::Memoized(memo) => {
+                let args = self.stack[self.sp - num_args..self.sp].to_vec();
+                let cached = memo
+                    .lookup(&args)
+                    .map_err(|e| VmError::with_kind(VmErrorKind::Builtin, e.to_string()))?;
+                let result = match cached {
+                    Some(result) => result,
+                    None => {
+                        let result = self.call(Rc::clone(memo.function()), &args)?;
+                        memo.store(&args, Rc::clone(&result));
+                        result
+                    }
+                };
+                self.sp -= num_args + 1;
+                self.push(result)?;
+            }
+            Object::HostFunction(host) => {
+                let result = host
+                    .apply(&self.stack[self.sp - num_args..self.sp])
+                    .map_err(|e| VmError::with_kind(VmErrorKind::Builtin, e.to_string()))?;
+                self.sp -= num_args + 1;
+                self.push(result)?;
+            }
+            _ => {
+                return Err(VmError::with_kind(
+                    VmErrorKind::NotCallable,
+                    fun.not_callable_message(),
+                ));
+            }
+        }
+        Ok(())
     }
 
-    pub fn push(&mut self, obj: Rc<Object>) {
+    pub fn push(&mut self, obj: Rc<Object>) -> Result<(), VmError> {
         if self.sp >= STACK_SIZE {
-            panic!("stack overflow");
+            return Err(VmError::with_kind(
+                VmErrorKind::StackOverflow,
+                "stack overflow".to_string(),
+            ));
         }
         self.stack[self.sp] = obj;
         self.sp += 1;
+        Ok(())
     }
 
-    pub fn pop(&mut self) -> Rc<Object> {
-        if self.sp == 0 {
-            panic!("stack underflow");
+    // The slot where the top `count` values start, for instructions that
+    // consume several at once.
+    fn stack_below(&self, count: usize) -> Result<usize, VmError> {
+        self.sp
+            .checked_sub(count)
+            .ok_or_else(|| VmError::new("stack underflow".to_string()))
+    }
+
+    pub fn pop(&mut self) -> Result<Rc<Object>, VmError> {
+        if self.sp == 0 || self.sp > STACK_SIZE {
+            return Err(VmError::new("stack underflow".to_string()));
         }
         self.sp -= 1;
-        let obj = self.stack[self.sp].clone();
-        obj
+
+        Ok(self.stack[self.sp].clone())
     }
 
     pub fn last_popped_stack_elem(&self) -> Rc<Object> {
@@ -377,97 +586,182 @@ impl VM {
                 Object::Integer(real_index) => {
                     let max = arr.len() as i64;
                     if *real_index < 0 || *real_index >= max {
-                        self.push(Rc::new(Object::Null));
+                        self.push(Rc::new(Object::Null))?;
                     } else {
-                        self.push(arr[*real_index as usize].clone());
+                        self.push(arr[*real_index as usize].clone())?;
                     }
                     Ok(())
                 }
-                _ => return Err(VmError::new("Unsupported index type for array".to_string())),
+                _ => Err(VmError::with_kind(
+                    VmErrorKind::TypeMismatch,
+                    "Unsupported index type for array".to_string(),
+                )),
+            },
+            Object::Hash(_) if !index.is_hashable() => Err(VmError::with_kind(
+                VmErrorKind::TypeMismatch,
+                index.unusable_key_message(),
+            )),
+            Object::Hash(hash) => match hash.get(&index) {
+                Some(obj) => self.push(obj.clone()),
+                None => self.push(Rc::new(Object::Null)),
             },
-            Object::Hash(hash) => {
-                match hash.get(&index) {
-                    Some(obj) => self.push(obj.clone()),
-                    None => self.push(Rc::new(Object::Null)),
-                }
-                Ok(())
-            }
 
-            _ => {
-                return Err(VmError::new(
-                    "Unsupported operation index for type".to_string(),
-                ))
-            }
+            _ => Err(VmError::with_kind(
+                VmErrorKind::TypeMismatch,
+                "Unsupported operation index for type".to_string(),
+            )),
         }
     }
 
+    fn push_constant(&mut self, constant_index: usize) -> Result<(), VmError> {
+        let constant = match self.constants.borrow().get(constant_index) {
+            Some(constant) => Rc::clone(constant),
+            None => return Err(VmError::new("Invalid constant index".to_string())),
+        };
+        self.push(constant)?;
+        Ok(())
+    }
+
     pub fn execute_binary_instruction(&mut self, opcode: Opcode) -> Result<(), VmError> {
-        let right = self.pop();
-        let left = self.pop();
+        let right = self.pop()?;
+        let left = self.pop()?;
 
         match (&*left, &*right) {
             (Object::Integer(left), Object::Integer(right)) => {
+                let result = match opcode {
+                    Opcode::Add => left.checked_add(*right),
+                    Opcode::Sub => left.checked_sub(*right),
+                    Opcode::Mul => left.checked_mul(*right),
+                    Opcode::Div => {
+                        if *right == 0 {
+                            return Err(VmError::with_kind(
+                                VmErrorKind::DivisionByZero,
+                                "Division by zero".to_string(),
+                            ));
+                        }
+                        left.checked_div(*right)
+                    }
+                    Opcode::Mod => {
+                        if *right == 0 {
+                            return Err(VmError::with_kind(
+                                VmErrorKind::DivisionByZero,
+                                "Division by zero".to_string(),
+                            ));
+                        }
+                        left.checked_rem(*right)
+                    }
+                    _ => {
+                        return Err(VmError::with_kind(
+                            VmErrorKind::UnknownOpcode,
+                            "Invalid opcode".to_string(),
+                        ))
+                    }
+                };
+                let result = result.ok_or_else(integer_overflow)?;
+                self.push(Rc::new(Object::Integer(result)))?;
+            }
+            (Object::Float(_), Object::Integer(_) | Object::Float(_))
+            | (Object::Integer(_), Object::Float(_)) => {
+                let (left, right) = (
+                    left.to_float().unwrap_or_default(),
+                    right.to_float().unwrap_or_default(),
+                );
                 let result = match opcode {
                     Opcode::Add => left + right,
                     Opcode::Sub => left - right,
                     Opcode::Mul => left * right,
                     Opcode::Div => left / right,
-                    _ => return Err(VmError::new("Invalid opcode".to_string())),
+                    Opcode::Mod => left % right,
+                    _ => {
+                        return Err(VmError::with_kind(
+                            VmErrorKind::UnknownOpcode,
+                            "Invalid opcode".to_string(),
+                        ))
+                    }
                 };
-                self.push(Rc::new(Object::Integer(result)));
+                self.push(Rc::new(Object::Float(result)))?;
             }
             (Object::String(left), Object::String(right)) => {
                 let result = match opcode {
                     Opcode::Add => format!("{}{}", left, right),
-                    _ => {
-                        return Err(VmError::new("Unsupported operation for string".to_string()));
-                    }
+                    _ => return Err(unknown_operator(opcode, left, right)),
                 };
-                self.push(Rc::new(Object::String(result)));
+                self.push(Object::new_string(result))?;
+            }
+            (Object::Array(_) | Object::Hash(_), _) if opcode == Opcode::Sub => {
+                match left.difference(&right) {
+                    Some(result) => self.push(result)?,
+                    None => return Err(type_mismatch(opcode, &left, &right)),
+                }
             }
-            (Object::Array(left), Object::Array(right)) => {
+            (Object::Array(left_elements), Object::Array(right_elements)) => {
                 let result = match opcode {
                     Opcode::Add => {
-                        let mut new_array = left.clone();
-                        new_array.extend(right.clone());
+                        let mut new_array = left_elements.clone();
+                        new_array.extend(right_elements.clone());
                         new_array
                     }
-                    _ => {
-                        return Err(VmError::new("Unsupported operation for array".to_string()));
-                    }
+                    _ => return Err(type_mismatch(opcode, &left, &right)),
                 };
-                self.push(Rc::new(Object::Array(result)));
+                self.push(Object::new_array(result))?;
             }
-            _ => {
-                return Err(VmError::new(
-                    "Unsupported types for binary operation".to_string(),
-                ));
+            (Object::Boolean(left), Object::Boolean(right)) => {
+                return Err(unknown_operator(opcode, left, right));
             }
+            _ => return Err(type_mismatch(opcode, &left, &right)),
         }
         Ok(())
     }
 
     pub fn execute_comparison(&mut self, opcode: Opcode) -> Result<(), VmError> {
-        let right = self.pop();
-        let left = self.pop();
+        let right = self.pop()?;
+        let left = self.pop()?;
         match (&*left, &*right) {
             (Object::Integer(left), Object::Integer(right)) => {
                 return self.execute_integer_comparison(opcode, *left, *right);
             }
+            (Object::Float(_), Object::Integer(_) | Object::Float(_))
+            | (Object::Integer(_), Object::Float(_)) => {
+                let left = left.to_float().unwrap_or_default();
+                let right = right.to_float().unwrap_or_default();
+                let result = match opcode {
+                    Opcode::Equal => left == right,
+                    Opcode::NotEqual => left != right,
+                    Opcode::GreaterThan => left > right,
+                    Opcode::LessThan => left < right,
+                    Opcode::LessEqual => left <= right,
+                    Opcode::GreaterEqual => left >= right,
+                    _ => {
+                        return Err(VmError::with_kind(
+                            VmErrorKind::UnknownOpcode,
+                            "Invalid opcode".to_string(),
+                        ));
+                    }
+                };
+                self.push(self.boolean(result))?;
+            }
             _ => match opcode {
                 Opcode::Equal => {
-                    let result = Rc::new(Object::Boolean(left == right));
-                    self.push(result);
+                    self.push(self.boolean(left == right))?;
                 }
                 Opcode::NotEqual => {
-                    let result = Rc::new(Object::Boolean(left != right));
-                    self.push(result);
+                    self.push(self.boolean(left != right))?;
                 }
-                _ => {
-                    return Err(VmError::new(
-                        "Unsupported comparison operation for type".to_string(),
+                _ if discriminant(&*left) != discriminant(&*right) => {
+                    return Err(VmError::with_kind(
+                        VmErrorKind::TypeMismatch,
+                        left.incomparable_message(&right),
                     ));
                 }
+                _ => match (&*left, &*right) {
+                    (Object::Boolean(left), Object::Boolean(right)) => {
+                        return Err(unknown_operator(opcode, left, right));
+                    }
+                    (Object::String(left), Object::String(right)) => {
+                        return Err(unknown_operator(opcode, left, right));
+                    }
+                    _ => return Err(type_mismatch(opcode, &left, &right)),
+                },
             },
         }
 
@@ -484,80 +778,94 @@ impl VM {
             Opcode::Equal => left == right,
             Opcode::NotEqual => left != right,
             Opcode::GreaterThan => left > right,
+            Opcode::LessThan => left < right,
+            Opcode::LessEqual => left <= right,
+            Opcode::GreaterEqual => left >= right,
             _ => {
-                return Err(VmError::new("Invalid opcode".to_string()));
+                return Err(VmError::with_kind(
+                    VmErrorKind::UnknownOpcode,
+                    "Invalid opcode".to_string(),
+                ));
             }
         };
-        self.push(Rc::new(Object::Boolean(result)));
+        self.push(self.boolean(result))?;
         Ok(())
     }
 
     pub fn execute_bang_operator(&mut self) -> Result<(), VmError> {
-        let operand = self.pop();
-        match &*operand {
-            Object::Boolean(value) => {
-                let result = Rc::new(Object::Boolean(!value));
-                self.push(result);
-            }
+        let operand = self.pop()?;
+        self.push(self.boolean(!operand.is_truthy()))?;
+        Ok(())
+    }
 
-            Object::Null => {
-                let result = Rc::new(Object::Boolean(true));
-                self.push(result);
-            }
-            _ => {
-                let result = Rc::new(Object::Boolean(false));
-                self.push(result);
-            }
+    fn boolean(&self, value: bool) -> Rc<Object> {
+        if value {
+            Rc::clone(&self.true_object)
+        } else {
+            Rc::clone(&self.false_object)
         }
-        Ok(())
     }
 
     pub fn execute_minus_operator(&mut self) -> Result<(), VmError> {
-        let operand = self.pop();
+        let operand = self.pop()?;
         match &*operand {
             Object::Integer(value) => {
-                let result = Rc::new(Object::Integer(-value));
-                self.push(result);
+                let result = value.checked_neg().ok_or_else(integer_overflow)?;
+                self.push(Rc::new(Object::Integer(result)))?;
+            }
+            Object::Float(value) => {
+                self.push(Rc::new(Object::Float(-value)))?;
             }
             _ => {
-                return Err(VmError::new("Unsupported type for negation".to_string()));
+                return Err(VmError::with_kind(
+                    VmErrorKind::TypeMismatch,
+                    format!("unknown operator: -{}", operand),
+                ));
             }
         }
         Ok(())
     }
 
-    fn build_array(&mut self, start_index: usize, end_index: usize) -> Object {
-        let mut elements = vec![Rc::new(Object::Null); end_index - start_index];
-        for i in start_index..end_index {
-            elements[i - start_index] = self.stack[i].clone();
-        }
-        Object::Array(elements)
+    fn build_array(&mut self, start_index: usize, end_index: usize) -> Rc<Object> {
+        let elements = self.stack[start_index..end_index].to_vec();
+        Object::new_array(elements)
     }
 
-    fn build_hash(&mut self, start_index: usize, end_index: usize) -> Object {
+    // hash keys are never closures, so nothing in them mutates
+    #[allow(clippy::mutable_key_type)]
+    fn build_hash(&mut self, start_index: usize, end_index: usize) -> Result<Object, VmError> {
         let mut pairs = HashMap::new();
         let mut i = start_index;
         while start_index <= i && i < end_index {
             let key = self.stack[i].clone();
+            if !key.is_hashable() {
+                return Err(VmError::with_kind(
+                    VmErrorKind::TypeMismatch,
+                    key.unusable_key_message(),
+                ));
+            }
             let value = self.stack[i + 1].clone();
             pairs.insert(key, value);
             i += 2;
         }
-        Object::Hash(pairs)
+        Ok(Object::Hash(pairs))
     }
 
     fn push_closure(&mut self, const_index: usize, num_free: usize) -> Result<(), VmError> {
-        let constant = self.constants.borrow()[const_index].clone();
+        let constant = match self.constants.borrow().get(const_index) {
+            Some(constant) => Rc::clone(constant),
+            None => return Err(VmError::new("Invalid constant index".to_string())),
+        };
         match &*constant {
             Object::CompiledFunction(compiled_function) => {
                 let mut free = Vec::with_capacity(num_free);
                 for _ in 0..num_free {
-                    free.push(self.pop());
+                    free.push(self.pop()?);
                 }
                 free.reverse();
 
                 let closure = Rc::new(Object::Closure(compiled_function.clone(), free));
-                self.push(closure);
+                self.push(closure)?;
             }
             _ => {
                 return Err(VmError::new("Object not compiled function".to_string()));
@@ -567,7 +875,51 @@ impl VM {
     }
 }
 
+fn integer_overflow() -> VmError {
+    VmError::with_kind(VmErrorKind::IntegerOverflow, "integer overflow".to_string())
+}
+
+// Operator errors are worded the way the evaluator words them, naming the
+// operator as it was written in the source.
+fn type_mismatch(opcode: Opcode, left: &Object, right: &Object) -> VmError {
+    VmError::with_kind(
+        VmErrorKind::TypeMismatch,
+        format!(
+            "type mismatch between operands: {} {} {}",
+            left,
+            operator(opcode),
+            right
+        ),
+    )
+}
+
+fn unknown_operator(opcode: Opcode, left: impl Display, right: impl Display) -> VmError {
+    VmError::with_kind(
+        VmErrorKind::TypeMismatch,
+        format!("unknown operator: {} {} {}", left, operator(opcode), right),
+    )
+}
+
+fn operator(opcode: Opcode) -> &'static str {
+    match opcode {
+        Opcode::Add => "+",
+        Opcode::Sub => "-",
+        Opcode::Mul => "*",
+        Opcode::Div => "/",
+        Opcode::Mod => "%",
+        Opcode::Equal => "==",
+        Opcode::NotEqual => "!=",
+        Opcode::GreaterThan => ">",
+        Opcode::LessThan => "<",
+        Opcode::GreaterEqual => ">=",
+        Opcode::LessEqual => "<=",
+        _ => opcode.name(),
+    }
+}
+
 #[cfg(test)]
+// tests build hashes keyed by `Rc<Object>` and fresh `Null` globals
+#[allow(clippy::mutable_key_type, clippy::rc_clone_in_vec_init)]
 mod test {
     use std::ops::Deref;
 
@@ -598,9 +950,12 @@ mod test {
             let mut vm = VM::new(comp.bytecode());
             let ret = vm.run();
 
-            if let Err(ref ret_err) = test.expected {
-                assert_eq!(ret_err.msg, test.expected.clone().unwrap_err().msg);
-                return;
+            if let Err(ref expected_err) = test.expected {
+                match ret {
+                    Ok(_) => panic!("expected error for {}", test.input),
+                    Err(err) => assert_eq!(err.msg, expected_err.msg, "{}", test.input),
+                }
+                continue;
             }
 
             println!("{:?}", ret);
@@ -619,6 +974,13 @@ mod test {
         }
     }
 
+    fn validate_float_object(obj: Object, expected: f64) {
+        match obj {
+            Object::Float(value) => assert_eq!(value, expected),
+            _ => panic!("object not float"),
+        }
+    }
+
     fn validate_boolean_object(obj: Object, expected: bool) {
         match obj {
             Object::Boolean(value) => assert_eq!(value, expected),
@@ -673,6 +1035,7 @@ mod test {
     fn test_expected_object(expected: Object, actual: Object) {
         match expected {
             Object::Integer(expected) => validate_integer_object(actual, expected),
+            Object::Float(expected) => validate_float_object(actual, expected),
             Object::Boolean(expected) => validate_boolean_object(actual, expected),
             Object::String(expected) => validate_string_object(actual, &expected),
             Object::Array(expected) => validate_array_object(actual, expected),
@@ -724,6 +1087,134 @@ mod test {
         run_vm_tests(tests);
     }
 
+    #[test]
+    fn it_computes_remainders() {
+        let tests = vec![
+            ("7 % 3", Object::Integer(1)),
+            ("-7 % 3", Object::Integer(-1)),
+            ("7 % -3", Object::Integer(1)),
+            ("-7 % -3", Object::Integer(-1)),
+            ("6 % 3", Object::Integer(0)),
+            ("2 + 7 % 4 * 2", Object::Integer(8)),
+            ("7.5 % 2", Object::Float(1.5)),
+            ("-7.5 % 2", Object::Float(-1.5)),
+        ];
+        run_vm_tests(
+            tests
+                .into_iter()
+                .map(|(input, expected)| VmTest {
+                    input: input.to_string(),
+                    expected: Ok(expected),
+                })
+                .collect(),
+        );
+    }
+
+    #[test]
+    fn it_rejects_division_by_zero() {
+        let tests = vec![
+            VmTest {
+                input: "5 / 0".to_string(),
+                expected: Err(VmError::new("Division by zero".to_string())),
+            },
+            VmTest {
+                input: "let f = fn(x) { 10 / x }; f(2) + f(0)".to_string(),
+                expected: Err(VmError::new("Division by zero".to_string())),
+            },
+            VmTest {
+                input: "5 % 0".to_string(),
+                expected: Err(VmError::new("Division by zero".to_string())),
+            },
+        ];
+
+        run_vm_tests(tests);
+    }
+
+    #[test]
+    fn it_short_circuits_logical_operators() {
+        let mut tests: Vec<VmTest> = vec![
+            ("true && true", true),
+            ("true && false", false),
+            ("false || true", true),
+            ("false || false", false),
+            ("1 && \"a\"", true),
+            ("if (false) { 1 } || false", false),
+            ("false && (1 / 0 == 0)", false),
+            ("true || (1 / 0 == 0)", true),
+            ("let f = fn() { 1 / 0 }; 1 > 2 && f()", false),
+            (
+                "let f = fn(x) { x > 0 && 10 / x > 2 }; [f(0), f(3)] == [false, true]",
+                true,
+            ),
+        ]
+        .into_iter()
+        .map(|(input, expected)| VmTest {
+            input: input.to_string(),
+            expected: Ok(Object::Boolean(expected)),
+        })
+        .collect();
+        for input in ["true && (1 / 0 == 0)", "false || (1 / 0 == 0)"] {
+            tests.push(VmTest {
+                input: input.to_string(),
+                expected: Err(VmError::new("Division by zero".to_string())),
+            });
+        }
+
+        run_vm_tests(tests);
+    }
+
+    #[test]
+    fn it_reports_stack_overflow_as_an_error() {
+        let elements = vec!["1"; STACK_SIZE + 1].join(", ");
+        let tests = vec![
+            (format!("[{}]", elements), "stack overflow"),
+            (
+                "let f = fn(n) { 1 + f(n + 1) }; f(0);".to_string(),
+                "stack overflow",
+            ),
+            (
+                "let f = fn() { f() }; f();".to_string(),
+                "call stack overflow",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let mut comp = Compiler::new();
+            comp.compile(parse(&input)).unwrap();
+            let mut vm = VM::new(comp.bytecode());
+            let err = vm.run().unwrap_err();
+            assert_eq!(err.msg, expected);
+            assert_eq!(err.kind, VmErrorKind::StackOverflow);
+        }
+
+        let mut vm = VM::new(Compiler::new().bytecode());
+        assert_eq!(vm.pop().unwrap_err().msg, "stack underflow");
+    }
+
+    #[test]
+    fn it_rejects_integer_overflow() {
+        let mut tests: Vec<VmTest> = vec![
+            "9223372036854775807 * 2",
+            "4611686018427387904 * 4611686018427387904",
+            "9223372036854775807 + 1",
+            "-9223372036854775807 - 2",
+            "let min = -9223372036854775807 - 1; min / -1",
+            "let min = -9223372036854775807 - 1; -min",
+        ]
+        .into_iter()
+        .map(|input| VmTest {
+            input: input.to_string(),
+            expected: Err(VmError::new("integer overflow".to_string())),
+        })
+        .collect();
+        tests.push(VmTest {
+            input: "9223372036854775807 - 1 + 1".to_string(),
+            expected: Ok(Object::Integer(i64::MAX)),
+        });
+
+        run_vm_tests(tests);
+    }
+
     #[test]
     fn it_pushes_bools() {
         let tests = vec![
@@ -738,9 +1229,99 @@ mod test {
         ];
         run_vm_tests(tests);
     }
+    #[test]
+    fn it_executes_float_expressions() {
+        let tests = vec![
+            VmTest {
+                input: "2.75".to_string(),
+                expected: Ok(Object::Float(2.75)),
+            },
+            VmTest {
+                input: "-2.5".to_string(),
+                expected: Ok(Object::Float(-2.5)),
+            },
+            VmTest {
+                input: "1.5 + 1".to_string(),
+                expected: Ok(Object::Float(2.5)),
+            },
+            VmTest {
+                input: "1 + 1.5".to_string(),
+                expected: Ok(Object::Float(2.5)),
+            },
+            VmTest {
+                input: "2.0 * 3".to_string(),
+                expected: Ok(Object::Float(6.0)),
+            },
+            VmTest {
+                input: "5 - 0.5".to_string(),
+                expected: Ok(Object::Float(4.5)),
+            },
+            VmTest {
+                input: "3.0 / 2".to_string(),
+                expected: Ok(Object::Float(1.5)),
+            },
+            VmTest {
+                input: "3 / 2".to_string(),
+                expected: Ok(Object::Integer(1)),
+            },
+            VmTest {
+                input: "1.0 / 0".to_string(),
+                expected: Ok(Object::Float(f64::INFINITY)),
+            },
+            VmTest {
+                input: "1.5 < 2".to_string(),
+                expected: Ok(Object::Boolean(true)),
+            },
+            VmTest {
+                input: "2 > 2.5".to_string(),
+                expected: Ok(Object::Boolean(false)),
+            },
+            VmTest {
+                input: "2.5 >= 2.5".to_string(),
+                expected: Ok(Object::Boolean(true)),
+            },
+            VmTest {
+                input: "3 <= 2.5".to_string(),
+                expected: Ok(Object::Boolean(false)),
+            },
+            VmTest {
+                input: "1 == 1.0".to_string(),
+                expected: Ok(Object::Boolean(true)),
+            },
+            VmTest {
+                input: "0.1 + 0.2 != 0.3".to_string(),
+                expected: Ok(Object::Boolean(true)),
+            },
+            VmTest {
+                input: "1.5 + true".to_string(),
+                expected: Err(VmError::new(
+                    "type mismatch between operands: 1.5 + true".to_string(),
+                )),
+            },
+        ];
+
+        run_vm_tests(tests);
+    }
+
     #[test]
     fn it_compares() {
         let tests = vec![
+            VmTest {
+                input: "1 <= 1".to_string(),
+                expected: Ok(Object::Boolean(true)),
+            },
+            VmTest {
+                input: "2 <= 1".to_string(),
+                expected: Ok(Object::Boolean(false)),
+            },
+            VmTest {
+                input: "1 >= 1".to_string(),
+                expected: Ok(Object::Boolean(true)),
+            },
+            VmTest {
+                input: "1 >= 2".to_string(),
+                expected: Ok(Object::Boolean(false)),
+            },
             VmTest {
                 input: "1 < 2".to_string(),
                 expected: Ok(Object::Boolean(true)),
@@ -811,13 +1392,340 @@ mod test {
     }
 
     #[test]
-    fn it_executes_boolean_prefix_expressions() {
+    fn it_reuses_a_reset_vm() {
+        let mut comp = Compiler::new();
+        let first = comp.compile_line("let a = fn(x) { x * 2 }; a(1);").unwrap();
+        let mut vm = VM::new(compiler::Bytecode {
+            instructions: first,
+            constants: comp.constants.clone(),
+        });
+        vm.run().unwrap();
+        assert_eq!(*vm.last_popped_stack_elem(), Object::Integer(2));
+        let stack_ptr = vm.stack.as_ptr();
+
+        let second = comp.compile_line("a(a(5)) + 1").unwrap();
+        vm.reset(compiler::Bytecode {
+            instructions: second,
+            constants: comp.constants.clone(),
+        });
+        assert_eq!(vm.sp, 0);
+        assert_eq!(vm.frame_index, 1);
+        vm.run().unwrap();
+        assert_eq!(*vm.last_popped_stack_elem(), Object::Integer(21));
+        assert_eq!(vm.stack.as_ptr(), stack_ptr);
+    }
+
+    #[test]
+    fn it_rejects_out_of_range_local_indices() {
+        let function = |instructions: Vec<Vec<u8>>, num_locals| {
+            Rc::new(Object::CompiledFunction(Rc::new(CompiledFunction::new(
+                Instructions::from(instructions.concat()),
+                0,
+                num_locals,
+            ))))
+        };
         let tests = vec![
-            VmTest {
-                input: "!true".to_string(),
-                expected: Ok(Object::Boolean(false)),
-            },
-            VmTest {
+            (
+                vec![code::make(Opcode::GetLocal, vec![0])],
+                vec![],
+                "local index 0 out of range for a frame with 0 locals",
+            ),
+            (
+                vec![
+                    code::make(Opcode::Closure, vec![0, 0]),
+                    code::make(Opcode::Call, vec![0]),
+                ],
+                vec![function(
+                    vec![
+                        code::make(Opcode::GetLocal, vec![200]),
+                        code::make(Opcode::ReturnValue, vec![]),
+                    ],
+                    1,
+                )],
+                "local index 200 out of range for a frame with 1 locals",
+            ),
+            (
+                vec![
+                    code::make(Opcode::Closure, vec![0, 0]),
+                    code::make(Opcode::Call, vec![0]),
+                ],
+                vec![function(
+                    vec![
+                        code::make(Opcode::True, vec![]),
+                        code::make(Opcode::SetLocal, vec![1]),
+                        code::make(Opcode::Return, vec![]),
+                    ],
+                    1,
+                )],
+                "local index 1 out of range for a frame with 1 locals",
+            ),
+        ];
+
+        for (instructions, constants, expected) in tests {
+            let mut vm = VM::new(compiler::Bytecode {
+                instructions: Instructions::from(instructions.concat()),
+                constants: Rc::new(RefCell::new(constants)),
+            });
+            let err = vm.run().unwrap_err();
+            assert_eq!(err.msg, expected);
+            assert_eq!(err.kind, VmErrorKind::Internal);
+        }
+    }
+
+    #[test]
+    fn it_ends_the_program_on_a_top_level_return() {
+        let tests = vec![
+            ("return 5;", Object::Integer(5)),
+            ("return 5; 10", Object::Integer(5)),
+            ("if (true) { return 5; }", Object::Integer(5)),
+            (
+                "let x = 1; [x, if (x > 0) { return x + 1; }, 3]",
+                Object::Integer(2),
+            ),
+            (
+                "let f = fn() { return 1; }; return f() + 1;",
+                Object::Integer(2),
+            ),
+        ];
+        for (input, expected) in tests {
+            let mut comp = Compiler::new();
+            comp.compile(parse(input)).unwrap();
+            let mut vm = VM::new(comp.bytecode());
+            vm.run().unwrap();
+            assert_eq!(vm.stack_top(), Some(Rc::new(expected)), "{}", input);
+        }
+
+        // the compiler never emits a bare return at the top level
+        let mut vm = VM::new(compiler::Bytecode {
+            instructions: Instructions::from(
+                [
+                    code::make(Opcode::Return, vec![]),
+                    code::make(Opcode::True, vec![]),
+                ]
+                .concat(),
+            ),
+            constants: Rc::new(RefCell::new(vec![])),
+        });
+        vm.run().unwrap();
+        assert_eq!(vm.stack_top(), Some(Rc::new(Object::Null)));
+    }
+
+    #[test]
+    fn it_rejects_out_of_range_operands() {
+        let function = Rc::new(Object::CompiledFunction(Rc::new(CompiledFunction::new(
+            Instructions::from(
+                [
+                    code::make(Opcode::GetFree, vec![1]),
+                    code::make(Opcode::ReturnValue, vec![]),
+                ]
+                .concat(),
+            ),
+            0,
+            0,
+        ))));
+        let tests = vec![
+            // one past the end, which a `>` check would let through
+            (
+                vec![code::make(Opcode::Constant, vec![1])],
+                vec![Rc::new(Object::Integer(1))],
+                "Invalid constant index",
+            ),
+            (
+                vec![code::make(Opcode::ConstantWide, vec![70000])],
+                vec![],
+                "Invalid constant index",
+            ),
+            (
+                vec![code::make(Opcode::Closure, vec![0, 0])],
+                vec![],
+                "Invalid constant index",
+            ),
+            (
+                vec![
+                    code::make(Opcode::True, vec![]),
+                    code::make(Opcode::Closure, vec![0, 1]),
+                    code::make(Opcode::Call, vec![0]),
+                ],
+                vec![function],
+                "free index 1 out of range for a closure with 1 free variables",
+            ),
+            (
+                vec![code::make(Opcode::GetBuiltin, vec![200])],
+                vec![],
+                "builtin index 200 out of range",
+            ),
+            // collections and calls reaching below the bottom of the stack
+            (
+                vec![code::make(Opcode::Array, vec![5])],
+                vec![],
+                "stack underflow",
+            ),
+            (
+                vec![
+                    code::make(Opcode::True, vec![]),
+                    code::make(Opcode::Hash, vec![2]),
+                ],
+                vec![],
+                "stack underflow",
+            ),
+            (
+                vec![code::make(Opcode::Call, vec![0])],
+                vec![],
+                "stack underflow",
+            ),
+        ];
+
+        for (instructions, constants, expected) in tests {
+            let mut vm = VM::new(compiler::Bytecode {
+                instructions: Instructions::from(instructions.concat()),
+                constants: Rc::new(RefCell::new(constants)),
+            });
+            let err = vm.run().unwrap_err();
+            assert_eq!(err.msg, expected);
+            assert_eq!(err.kind, VmErrorKind::Internal);
+        }
+
+        let mut vm = VM::new(compiler::Bytecode {
+            instructions: Instructions::from(vec![200]),
+            constants: Rc::new(RefCell::new(vec![])),
+        });
+        let err = vm.run().unwrap_err();
+        assert_eq!(err.msg, "unknown opcode 200 at 0");
+        assert_eq!(err.kind, VmErrorKind::UnknownOpcode);
+
+        // a shared global store can be smaller than the u16 operand allows
+        let globals = Rc::new(RefCell::new(vec![Rc::new(Object::Null); 1]));
+        for op in [Opcode::SetGlobal, Opcode::GetGlobal] {
+            let mut vm = VM::new_with_global_store(
+                compiler::Bytecode {
+                    instructions: Instructions::from(
+                        [code::make(Opcode::True, vec![]), code::make(op, vec![1])].concat(),
+                    ),
+                    constants: Rc::new(RefCell::new(vec![])),
+                },
+                Rc::clone(&globals),
+            );
+            assert!(vm.run().is_err(), "{}", op.name());
+        }
+    }
+
+    #[test]
+    fn it_loads_constants_past_the_u16_limit() {
+        let mut comp = Compiler::new();
+        comp.constants
+            .borrow_mut()
+            .resize(u16::MAX as usize + 1, Rc::new(Object::Null));
+
+        let instructions = comp.compile_line("40 + 2").unwrap();
+        let wide = Instructions::from(
+            [
+                code::make(Opcode::ConstantWide, vec![65536]),
+                code::make(Opcode::ConstantWide, vec![65537]),
+            ]
+            .concat(),
+        );
+        assert_eq!(instructions.slice(0, 10), wide.slice(0, 10));
+
+        let mut vm = VM::new(compiler::Bytecode {
+            instructions,
+            constants: comp.constants.clone(),
+        });
+        vm.run().unwrap();
+        assert_eq!(*vm.last_popped_stack_elem(), Object::Integer(42));
+    }
+
+    #[test]
+    fn it_shares_empty_arrays_and_strings() {
+        let empty_array = Object::new_array(vec![]);
+        let empty_string = Object::new_string(String::new());
+
+        let tests = vec![
+            ("[]", &empty_array),
+            ("rest([1])", &empty_array),
+            (r#"chars("")"#, &empty_array),
+            ("[] + []", &empty_array),
+            (r#""""#, &empty_string),
+            (r#""" + """#, &empty_string),
+        ];
+        for (input, expected) in tests {
+            let mut comp = Compiler::new();
+            comp.compile(parse(input)).unwrap();
+            let mut vm = VM::new(comp.bytecode());
+            vm.run().unwrap();
+            assert!(
+                Rc::ptr_eq(&vm.last_popped_stack_elem(), expected),
+                "{}",
+                input
+            );
+        }
+
+        run_vm_tests(vec![VmTest {
+            input: "let a = []; let b = push(a, 1); let c = []; len(a) + len(c)".to_string(),
+            expected: Ok(Object::Integer(0)),
+        }]);
+    }
+
+    #[test]
+    fn it_runs_bytecode_after_a_serialization_round_trip() {
+        let input = "let add = fn(a) { fn(b) { a + b } }; let addTwo = add(2); addTwo(3) * 10";
+        let mut comp = Compiler::new();
+        comp.compile(parse(input)).unwrap();
+        let bytecode = comp.bytecode();
+        assert!(bytecode
+            .constants
+            .borrow()
+            .iter()
+            .any(|c| matches!(**c, Object::CompiledFunction(_))));
+
+        let bytes = bytecode.serialize().unwrap();
+        let loaded = compiler::Bytecode::deserialize(&bytes).unwrap();
+        assert_eq!(loaded.instructions, bytecode.instructions);
+        assert_eq!(*loaded.constants.borrow(), *bytecode.constants.borrow());
+
+        let mut vm = VM::new(loaded);
+        vm.run().unwrap();
+        assert_eq!(*vm.last_popped_stack_elem(), Object::Integer(50));
+
+        let closure = Object::Closure(Rc::new(CompiledFunction::new(vec![].into(), 0, 0)), vec![]);
+        let bytecode = compiler::Bytecode {
+            instructions: Instructions::from(vec![]),
+            constants: Rc::new(RefCell::new(vec![Rc::new(closure)])),
+        };
+        assert_eq!(
+            bytecode.serialize().unwrap_err().msg,
+            "cannot serialize FUNCTION constants"
+        );
+    }
+
+    #[test]
+    fn it_shares_boolean_results() {
+        let program = parse("[1 < 2, 2 == 2, !false, true, 1 > 2, !true, false]");
+        let mut comp = Compiler::new();
+        comp.compile(program).unwrap();
+        let mut vm = VM::new(comp.bytecode());
+        vm.run().unwrap();
+
+        match &*vm.last_popped_stack_elem() {
+            Object::Array(elements) => {
+                for element in &elements[..4] {
+                    assert!(Rc::ptr_eq(element, &vm.true_object));
+                }
+                for element in &elements[4..] {
+                    assert!(Rc::ptr_eq(element, &vm.false_object));
+                }
+            }
+            other => panic!("expected array, got {}", other),
+        }
+    }
+
+    #[test]
+    fn it_executes_boolean_prefix_expressions() {
+        let tests = vec![
+            VmTest {
+                input: "!true".to_string(),
+                expected: Ok(Object::Boolean(false)),
+            },
+            VmTest {
                 input: "!false".to_string(),
                 expected: Ok(Object::Boolean(true)),
             },
@@ -926,6 +1834,152 @@ mod test {
         }];
 
         run_vm_tests(tests);
+
+        // bound conditionals
+        let tests = vec![
+            VmTest {
+                input: "let x = if (1 < 2) { 10 } else { 20 }; x * 2".to_string(),
+                expected: Ok(Object::Integer(20)),
+            },
+            VmTest {
+                input: "let x = if (1 > 2) { 10 }; x".to_string(),
+                expected: Ok(Object::Null),
+            },
+            VmTest {
+                input: "let x = if (true) {} else { 1 }; x".to_string(),
+                expected: Ok(Object::Null),
+            },
+            VmTest {
+                input: "let f = fn(c) { let x = if (c) { 1 } else { 2 }; x * 10 }; f(true) + f(false)"
+                    .to_string(),
+                expected: Ok(Object::Integer(30)),
+            },
+            VmTest {
+                input: "let f = fn(c) { let x = if (c) { return 1; } else { 2 }; x * 10 }; f(true) + f(false)"
+                    .to_string(),
+                expected: Ok(Object::Integer(21)),
+            },
+        ];
+
+        run_vm_tests(tests);
+    }
+
+    #[test]
+    fn it_executes_assignments() {
+        let tests = vec![
+            ("let x = 1; x = 2; x", 2),
+            ("let x = 0; x = 7", 7),
+            ("let a = 1; let b = 2; a = b = 3; a + b", 6),
+            ("let x = 1; let f = fn() { x = 5; }; f(); x", 5),
+            ("let f = fn() { let y = 1; y = y + 1; y }; f()", 2),
+            ("let f = fn(n) { n = n * 2; n }; f(4)", 8),
+            ("let i = 0; while (i < 3) { i = i + 1 }; i", 3),
+            (
+                "let f = fn() { let i = 0; while (i < 4) { i = i + 1 }; i }; f()",
+                4,
+            ),
+        ];
+        run_vm_tests(
+            tests
+                .into_iter()
+                .map(|(input, expected)| VmTest {
+                    input: input.to_string(),
+                    expected: Ok(Object::Integer(expected)),
+                })
+                .collect(),
+        );
+    }
+
+    #[test]
+    fn it_executes_index_assignments() {
+        let tests = vec![
+            ("let a = [1, 2, 3]; a[1] = 5; a", "[1, 5, 3]"),
+            ("let a = [1, 2, 3]; a[0] = a[2] * 2", "6"),
+            (r#"let h = {"a": 1}; h["a"] = 2; h"#, "{a: 2}"),
+            (r#"let h = {"a": 1}; h["b"] = 2; h"#, "{a: 1, b: 2}"),
+            ("let a = [1]; let b = a; a[0] = 2; [a, b]", "[[2], [1]]"),
+            (
+                "let f = fn() { let a = [0, 0]; a[1] = 7; a }; f()",
+                "[0, 7]",
+            ),
+            (
+                "let a = [0, 0, 0]; let i = 0; while (i < 3) { a[i] = i * i; i = i + 1 }; a",
+                "[0, 1, 4]",
+            ),
+        ];
+        for (input, expected) in tests {
+            let mut comp = Compiler::new();
+            comp.compile(parse(input)).unwrap();
+            let mut vm = VM::new(comp.bytecode());
+            vm.run().unwrap();
+            assert_eq!(
+                vm.last_popped_stack_elem().to_string(),
+                expected,
+                "{}",
+                input
+            );
+        }
+
+        let errors = vec![
+            (
+                "let a = [1]; a[1] = 2",
+                "index 1 out of bounds for array of length 1",
+            ),
+            (
+                "let a = [1]; a[-1] = 2",
+                "index -1 out of bounds for array of length 1",
+            ),
+            (
+                r#"let a = [1]; a["x"] = 2"#,
+                "array index must be INTEGER, got STRING",
+            ),
+            ("let h = {}; h[[1]] = 2", "unusable as hash key: ARRAY"),
+            (
+                "let s = \"ab\"; s[0] = 1",
+                "index assignment not supported: STRING",
+            ),
+        ];
+        run_vm_tests(
+            errors
+                .into_iter()
+                .map(|(input, msg)| VmTest {
+                    input: input.to_string(),
+                    expected: Err(VmError::with_kind(VmErrorKind::Index, msg.to_string())),
+                })
+                .collect(),
+        );
+    }
+
+    #[test]
+    fn it_executes_while_loops() {
+        let tests = vec![
+            VmTest {
+                input: "while (false) { 1 }".to_string(),
+                expected: Ok(Object::Null),
+            },
+            VmTest {
+                input: "let i = 0; while (i < 10) { let i = i + 1; }; i;".to_string(),
+                expected: Ok(Object::Integer(10)),
+            },
+            VmTest {
+                input: "let sum = 0; let i = 0; while (i < 5) { let sum = sum + i; let i = i + 1; }; sum;".to_string(),
+                expected: Ok(Object::Integer(10)),
+            },
+            VmTest {
+                input: "let total = 0; let i = 0; while (i < 3) { let j = 0; while (j < 4) { let total = total + 1; let j = j + 1; }; let i = i + 1; }; total;".to_string(),
+                expected: Ok(Object::Integer(12)),
+            },
+            VmTest {
+                input: "let f = fn(n) { let i = 0; while (true) { if (i == n) { return i * 2; }; let i = i + 1; } }; f(5);".to_string(),
+                expected: Ok(Object::Integer(10)),
+            },
+            VmTest {
+                input: "let i = 0; while (i < 5000) { let i = i + 1; }; i;".to_string(),
+                expected: Ok(Object::Integer(5000)),
+            },
+        ];
+
+        run_vm_tests(tests);
     }
 
     #[test]
@@ -1005,6 +2059,68 @@ mod test {
         run_vm_tests(tests);
     }
 
+    #[test]
+    fn it_executes_collection_differences() {
+        let int_array = |values: &[i64]| {
+            Object::Array(
+                values
+                    .iter()
+                    .map(|v| Rc::new(Object::Integer(*v)))
+                    .collect(),
+            )
+        };
+        let int_hash = |pairs: &[(i64, i64)]| {
+            Object::Hash(
+                pairs
+                    .iter()
+                    .map(|(k, v)| (Rc::new(Object::Integer(*k)), Rc::new(Object::Integer(*v))))
+                    .collect(),
+            )
+        };
+        let mismatch = |operands: &str| {
+            Err(VmError::new(format!(
+                "type mismatch between operands: {}",
+                operands
+            )))
+        };
+        let tests = vec![
+            VmTest {
+                input: "[1, 2, 3] - [2]".to_string(),
+                expected: Ok(int_array(&[1, 3])),
+            },
+            VmTest {
+                input: "[1, 2, 1, 3, 1] - [1]".to_string(),
+                expected: Ok(int_array(&[2, 3])),
+            },
+            VmTest {
+                input: "[1, 2] - [1, 2]".to_string(),
+                expected: Ok(int_array(&[])),
+            },
+            VmTest {
+                input: "{1: 2, 3: 4} - [1]".to_string(),
+                expected: Ok(int_hash(&[(3, 4)])),
+            },
+            VmTest {
+                input: "{1: 2, 3: 4} - {3: 0, 5: 6}".to_string(),
+                expected: Ok(int_hash(&[(1, 2)])),
+            },
+            VmTest {
+                input: "[1, 2] - 1".to_string(),
+                expected: mismatch("[1, 2] - 1"),
+            },
+            VmTest {
+                input: "{1: 2} - 1".to_string(),
+                expected: mismatch("{1: 2} - 1"),
+            },
+            VmTest {
+                input: "[1] - {1: 2}".to_string(),
+                expected: mismatch("[1] - {1: 2}"),
+            },
+        ];
+
+        run_vm_tests(tests);
+    }
+
     #[test]
     fn it_executes_hash_expressions() {
         let tests = vec![
@@ -1229,34 +2345,331 @@ mod test {
         run_vm_tests(test);
     }
 
+    #[test]
+    fn it_executes_calls_on_expression_results() {
+        let tests = vec![
+            VmTest {
+                input: "[fn() { 1 }][0]();".to_string(),
+                expected: Ok(Object::Integer(1)),
+            },
+            VmTest {
+                input: "(fn() { fn() { 2 } })()();".to_string(),
+                expected: Ok(Object::Integer(2)),
+            },
+            VmTest {
+                input: r#"let fns = {"add": fn(a, b) { a + b }}; fns["add"](1, 2) + fns["add"](3, 4);"#
+                    .to_string(),
+                expected: Ok(Object::Integer(10)),
+            },
+            VmTest {
+                input: "let getFn = fn(x) { fn(y) { x + y } }; let a = 1; getFn(a)(2) + [getFn(3)][0](4);"
+                    .to_string(),
+                expected: Ok(Object::Integer(10)),
+            },
+            VmTest {
+                input: "[fn(a) { a }][0]();".to_string(),
+                expected: Err(VmError::new(
+                    "wrong number of arguments: got=0, want=1".to_string(),
+                )),
+            },
+        ];
+        run_vm_tests(tests);
+    }
+
     #[test]
     fn it_executes_calling_functions_with_wrong_arguments() {
         let tests = vec![
             VmTest {
                 input: "fn() { 1; }(1);".to_string(),
                 expected: Err(VmError::new(
-                    "Invalid number of arguments: want 0, got 1".to_string(),
+                    "wrong number of arguments: got=1, want=0".to_string(),
                 )),
             },
             VmTest {
                 input: "fn(a) { a; }();".to_string(),
                 expected: Err(VmError::new(
-                    "Invalid number of arguments: want 1, got 0".to_string(),
+                    "wrong number of arguments: got=0, want=1".to_string(),
                 )),
             },
             VmTest {
                 input: "fn(a, b) { a + b; }(1);".to_string(),
                 expected: Err(VmError::new(
-                    "Invalid number of arguments: want 2, got 1".to_string(),
+                    "wrong number of arguments: got=1, want=2".to_string(),
+                )),
+            },
+        ];
+        run_vm_tests(tests);
+    }
+
+    #[test]
+    fn it_classifies_runtime_errors() {
+        let tests = vec![
+            ("fn(a) { a }();", VmErrorKind::Arity),
+            ("1();", VmErrorKind::NotCallable),
+            ("1 + true;", VmErrorKind::TypeMismatch),
+            ("-true;", VmErrorKind::TypeMismatch),
+            ("\"a\" - \"b\";", VmErrorKind::TypeMismatch),
+            ("[1][true];", VmErrorKind::TypeMismatch),
+            ("len(1);", VmErrorKind::Builtin),
+            ("let a = [1]; a[1] = 2;", VmErrorKind::Index),
+            ("1 / 0;", VmErrorKind::DivisionByZero),
+            ("9223372036854775807 * 2;", VmErrorKind::IntegerOverflow),
+        ];
+
+        for (input, kind) in tests {
+            let mut comp = Compiler::new();
+            comp.compile(parse(input)).unwrap();
+            let mut vm = VM::new(comp.bytecode());
+            let err = vm.run().unwrap_err();
+            assert_eq!(err.kind, kind, "{}", input);
+        }
+    }
+
+    #[test]
+    fn it_maps_arrays_through_functions() {
+        let ints = |values: &[i64]| {
+            Ok(Object::Array(
+                values
+                    .iter()
+                    .map(|&i| Rc::new(Object::Integer(i)))
+                    .collect(),
+            ))
+        };
+        let tests = vec![
+            VmTest {
+                input: "map([1, 2, 3], fn(x) { x * 2 })".to_string(),
+                expected: ints(&[2, 4, 6]),
+            },
+            VmTest {
+                input: "map([], fn(x) { x * 2 })".to_string(),
+                expected: ints(&[]),
+            },
+            VmTest {
+                input: "let f = fn(n) { map([1, 2], fn(x) { x + n }) }; f(10)".to_string(),
+                expected: ints(&[11, 12]),
+            },
+            VmTest {
+                input: r#"map(["a", "bc"], len)"#.to_string(),
+                expected: ints(&[1, 2]),
+            },
+            VmTest {
+                input: "map([[1, 2], [3]], fn(a) { map(a, fn(x) { x + 1 }) })".to_string(),
+                expected: Ok(Object::Array(vec![
+                    Rc::new(Object::Array(vec![
+                        Rc::new(Object::Integer(2)),
+                        Rc::new(Object::Integer(3)),
+                    ])),
+                    Rc::new(Object::Array(vec![Rc::new(Object::Integer(4))])),
+                ])),
+            },
+            VmTest {
+                input: "let double = memoize(fn(x) { x * 2 }); map([1, 1], double)".to_string(),
+                expected: ints(&[2, 2]),
+            },
+            VmTest {
+                input: "map([1], fn(x, y) { x })".to_string(),
+                expected: Err(VmError::new(
+                    "wrong number of arguments: got=1, want=2".to_string(),
+                )),
+            },
+            VmTest {
+                input: "map(1, len)".to_string(),
+                expected: Err(VmError::new(
+                    "argument to `map` must be ARRAY, got 1".to_string(),
+                )),
+            },
+            VmTest {
+                input: "map([1], 2)".to_string(),
+                expected: Err(VmError::new(
+                    "second argument to `map` must be FUNCTION, got 2".to_string(),
+                )),
+            },
+        ];
+
+        run_vm_tests(tests);
+    }
+
+    #[test]
+    fn it_filters_and_reduces_arrays() {
+        let ints = |values: &[i64]| {
+            Ok(Object::Array(
+                values
+                    .iter()
+                    .map(|&i| Rc::new(Object::Integer(i)))
+                    .collect(),
+            ))
+        };
+        let tests = vec![
+            VmTest {
+                input: "filter([1, 2, 3, 4], fn(x) { x % 2 == 0 })".to_string(),
+                expected: ints(&[2, 4]),
+            },
+            VmTest {
+                input: "filter([1, 2], fn(x) { if (x > 1) { x } })".to_string(),
+                expected: ints(&[2]),
+            },
+            VmTest {
+                input: "filter([], fn(x) { true })".to_string(),
+                expected: ints(&[]),
+            },
+            VmTest {
+                input: "reduce([1, 2, 3, 4], 0, fn(acc, x) { acc + x })".to_string(),
+                expected: Ok(Object::Integer(10)),
+            },
+            VmTest {
+                input: "reduce([1, 2, 3], [], fn(acc, x) { unshift(acc, x) })".to_string(),
+                expected: ints(&[3, 2, 1]),
+            },
+            VmTest {
+                input: "reduce([1, 2, 3], 0, fn(a, b) { a + b }) == 6".to_string(),
+                expected: Ok(Object::Boolean(true)),
+            },
+            VmTest {
+                input: "reduce([], 7, fn(acc, x) { acc + x })".to_string(),
+                expected: Ok(Object::Integer(7)),
+            },
+            VmTest {
+                input: "let limit = 2; reduce(filter([1, 2, 3], fn(x) { x > limit }), 0, fn(acc, x) { acc + x })"
+                    .to_string(),
+                expected: Ok(Object::Integer(3)),
+            },
+            VmTest {
+                input: "filter([1], 2)".to_string(),
+                expected: Err(VmError::new(
+                    "second argument to `filter` must be FUNCTION, got 2".to_string(),
+                )),
+            },
+            VmTest {
+                input: "reduce([1], 0, 2)".to_string(),
+                expected: Err(VmError::new(
+                    "third argument to `reduce` must be FUNCTION, got 2".to_string(),
                 )),
             },
         ];
+
+        run_vm_tests(tests);
+    }
+
+    #[test]
+    fn it_splits_and_joins_strings() {
+        let tests = vec![
+            VmTest {
+                input: r#"split("a,b,c", ",")"#.to_string(),
+                expected: Ok(Object::Array(vec![
+                    Rc::new(Object::String("a".to_string())),
+                    Rc::new(Object::String("b".to_string())),
+                    Rc::new(Object::String("c".to_string())),
+                ])),
+            },
+            VmTest {
+                input: r#"split("ab", "")"#.to_string(),
+                expected: Ok(Object::Array(vec![
+                    Rc::new(Object::String("a".to_string())),
+                    Rc::new(Object::String("b".to_string())),
+                ])),
+            },
+            VmTest {
+                input: r#"join(["a", "b"], "-") == "a-b""#.to_string(),
+                expected: Ok(Object::Boolean(true)),
+            },
+            VmTest {
+                input: r#"join(map(split("1 2", " "), fn(s) { s + s }), ",")"#.to_string(),
+                expected: Ok(Object::String("11,22".to_string())),
+            },
+            VmTest {
+                input: r#"join(["a", true], "")"#.to_string(),
+                expected: Err(VmError::new(
+                    "elements passed to `join` must be STRING, got true".to_string(),
+                )),
+            },
+            VmTest {
+                input: r#"split("a", [","])"#.to_string(),
+                expected: Err(VmError::new(
+                    "second argument to `split` must be STRING, got [,]".to_string(),
+                )),
+            },
+        ];
+
+        run_vm_tests(tests);
+    }
+
+    #[test]
+    fn it_lists_hash_keys_and_values_in_sorted_order() {
+        let tests = vec![
+            VmTest {
+                input: r#"keys({"b": 1, 10: 2, 9: 3, false: 4})"#.to_string(),
+                expected: Ok(Object::Array(vec![
+                    Rc::new(Object::Boolean(false)),
+                    Rc::new(Object::Integer(9)),
+                    Rc::new(Object::Integer(10)),
+                    Rc::new(Object::String("b".to_string())),
+                ])),
+            },
+            VmTest {
+                input: r#"values({"b": 1, 10: 2, 9: 3, false: 4})"#.to_string(),
+                expected: Ok(Object::Array(vec![
+                    Rc::new(Object::Integer(4)),
+                    Rc::new(Object::Integer(3)),
+                    Rc::new(Object::Integer(2)),
+                    Rc::new(Object::Integer(1)),
+                ])),
+            },
+            VmTest {
+                input: r#"let h = {"a": 1, "b": 2}; reduce(keys(h), 0, fn(acc, k) { acc + h[k] })"#
+                    .to_string(),
+                expected: Ok(Object::Integer(3)),
+            },
+            VmTest {
+                input: "values(1)".to_string(),
+                expected: Err(VmError::new(
+                    "argument to `values` must be HASH, got 1".to_string(),
+                )),
+            },
+        ];
+
+        run_vm_tests(tests);
+    }
+
+    #[test]
+    fn it_parses_integers() {
+        let tests = vec![
+            VmTest {
+                input: r#"int("42") == 42"#.to_string(),
+                expected: Ok(Object::Boolean(true)),
+            },
+            VmTest {
+                input: "int(true) == 1".to_string(),
+                expected: Ok(Object::Boolean(true)),
+            },
+            VmTest {
+                input: r#"reduce(map(split("1,2,3", ","), int), 0, fn(a, b) { a + b })"#
+                    .to_string(),
+                expected: Ok(Object::Integer(6)),
+            },
+            VmTest {
+                input: r#"int("abc")"#.to_string(),
+                expected: Err(VmError::new(
+                    r#"could not parse "abc" as INTEGER"#.to_string(),
+                )),
+            },
+            VmTest {
+                input: r#"int("99999999999999999999")"#.to_string(),
+                expected: Err(VmError::new(
+                    r#"could not parse "99999999999999999999" as INTEGER"#.to_string(),
+                )),
+            },
+        ];
+
         run_vm_tests(tests);
     }
 
     #[test]
     fn it_executes_builtins() {
         let tests = vec![
+            VmTest {
+                input: "doc(len)".to_string(),
+                expected: Ok(Object::String(crate::object::builtin::Builtin::Len.help())),
+            },
             VmTest {
                 input: r#"len("")"#.to_string(),
                 expected: Ok(Object::Integer(0)),
@@ -1272,13 +2685,13 @@ mod test {
             VmTest {
                 input: r#"len(1)"#.to_string(),
                 expected: Err(VmError::new(
-                    "Argument to `len` not supported, got Integer".to_string(),
+                    "argument to `len` not supported, got 1".to_string(),
                 )),
             },
             VmTest {
                 input: r#"len("one", "two")"#.to_string(),
                 expected: Err(VmError::new(
-                    "Wrong number of arguments. got=2, want=1".to_string(),
+                    "wrong number of arguments. expected=1, got=2".to_string(),
                 )),
             },
             VmTest {
@@ -1316,7 +2729,7 @@ mod test {
             VmTest {
                 input: r#"last(1)"#.to_string(),
                 expected: Err(VmError::new(
-                    "Argument to `last` must be ARRAY, got Integer".to_string(),
+                    "argument to `last` must be ARRAY, got 1".to_string(),
                 )),
             },
             VmTest {
@@ -1342,7 +2755,54 @@ mod test {
             VmTest {
                 input: r#"push(1, 2)"#.to_string(),
                 expected: Err(VmError::new(
-                    "Argument to `push` must be ARRAY, got Integer".to_string(),
+                    "argument to `push` must be ARRAY, got 1".to_string(),
+                )),
+            },
+            VmTest {
+                input: r#"let stack = push(push([], 1), 2); let popped = pop(push(stack, 3)); stack = popped[1]; [popped[0], len(stack), last(stack)]"#.to_string(),
+                expected: Ok(Object::Array(vec![
+                    Rc::new(Object::Integer(3)),
+                    Rc::new(Object::Integer(2)),
+                    Rc::new(Object::Integer(2)),
+                ])),
+            },
+            VmTest {
+                input: r#"let queue = unshift([2, 3], 1); let shifted = shift(queue); queue = shifted[1]; [shifted[0], len(queue), first(queue)]"#.to_string(),
+                expected: Ok(Object::Array(vec![
+                    Rc::new(Object::Integer(1)),
+                    Rc::new(Object::Integer(2)),
+                    Rc::new(Object::Integer(2)),
+                ])),
+            },
+            VmTest {
+                input: r#"pop([])"#.to_string(),
+                expected: Ok(Object::Null),
+            },
+            VmTest {
+                input: r#"shift([])"#.to_string(),
+                expected: Ok(Object::Null),
+            },
+            VmTest {
+                input: r#"unshift(1, 2)"#.to_string(),
+                expected: Err(VmError::new(
+                    "argument to `unshift` must be ARRAY, got 1".to_string(),
+                )),
+            },
+            VmTest {
+                input: r#"len(chars("héllo"))"#.to_string(),
+                expected: Ok(Object::Integer(5)),
+            },
+            VmTest {
+                input: r#"chars("añ")"#.to_string(),
+                expected: Ok(Object::Array(vec![
+                    Rc::new(Object::String("a".to_string())),
+                    Rc::new(Object::String("ñ".to_string())),
+                ])),
+            },
+            VmTest {
+                input: r#"chars(1)"#.to_string(),
+                expected: Err(VmError::new(
+                    "argument to `chars` must be STRING, got 1".to_string(),
                 )),
             },
         ];
@@ -1486,6 +2946,19 @@ mod test {
                 .to_string(),
                 expected: Ok(Object::Integer(0)),
             },
+            VmTest {
+                input: r#"
+                fn wrapper() {
+                    fn sum(x) {
+                        if (x == 0) { 0 } else { x + sum(x - 1) }
+                    }
+                    sum(4);
+                }
+                wrapper();
+                "#
+                .to_string(),
+                expected: Ok(Object::Integer(10)),
+            },
         ];
         run_vm_tests(tests);
     }
@@ -1512,4 +2985,176 @@ mod test {
         }];
         run_vm_tests(tests);
     }
+
+    #[cfg(feature = "profile")]
+    #[test]
+    fn it_counts_executed_opcodes() {
+        let input = r#"
+            let fibonacci = fn(x) {
+                if (x < 2) { x } else { fibonacci(x - 1) + fibonacci(x - 2) }
+            };
+            fibonacci(10);
+            "#;
+        let mut comp = Compiler::new();
+        comp.compile(parse(input)).unwrap();
+        let mut vm = VM::new(comp.bytecode());
+        vm.run().unwrap();
+
+        let counts = vm.opcode_counts();
+        // one call per node of the recursion tree: 2 * fib(11) - 1
+        assert_eq!(counts[&Opcode::Call], 177);
+        assert!(counts[&Opcode::Constant] > 0);
+        assert!(!counts.contains_key(&Opcode::Hash));
+    }
+
+    // Builds the smallest program that makes `VM::run` dispatch `op`. There is
+    // deliberately no wildcard arm, so adding an opcode without teaching this
+    // test about it fails to compile.
+    fn dispatch_program(op: Opcode) -> Vec<Vec<u8>> {
+        use code::make;
+        match op {
+            Opcode::Constant => vec![make(Opcode::Constant, vec![0])],
+            Opcode::ConstantWide => vec![make(Opcode::ConstantWide, vec![0])],
+            Opcode::Add | Opcode::Sub | Opcode::Mul | Opcode::Div | Opcode::Mod => vec![
+                make(Opcode::Constant, vec![1]),
+                make(Opcode::Constant, vec![0]),
+                make(op, vec![]),
+            ],
+            Opcode::Equal
+            | Opcode::NotEqual
+            | Opcode::GreaterThan
+            | Opcode::LessThan
+            | Opcode::LessEqual
+            | Opcode::GreaterEqual => vec![
+                make(Opcode::Constant, vec![0]),
+                make(Opcode::Constant, vec![1]),
+                make(op, vec![]),
+            ],
+            Opcode::Pop => vec![make(Opcode::Constant, vec![0]), make(Opcode::Pop, vec![])],
+            Opcode::True | Opcode::False | Opcode::Null => vec![make(op, vec![])],
+            Opcode::Minus => vec![make(Opcode::Constant, vec![0]), make(Opcode::Minus, vec![])],
+            Opcode::Bang => vec![make(Opcode::True, vec![]), make(Opcode::Bang, vec![])],
+            Opcode::JumpNotTruthy => vec![
+                make(Opcode::True, vec![]),
+                make(Opcode::JumpNotTruthy, vec![4]),
+            ],
+            Opcode::Jump => vec![make(Opcode::Jump, vec![3])],
+            Opcode::JumpNotNull => vec![
+                make(Opcode::True, vec![]),
+                make(Opcode::JumpNotNull, vec![4]),
+            ],
+            Opcode::GetGlobal => vec![make(Opcode::GetGlobal, vec![0])],
+            Opcode::SetGlobal => vec![
+                make(Opcode::Constant, vec![0]),
+                make(Opcode::SetGlobal, vec![0]),
+            ],
+            Opcode::Array => vec![
+                make(Opcode::Constant, vec![0]),
+                make(Opcode::Array, vec![1]),
+            ],
+            Opcode::Hash => vec![
+                make(Opcode::Constant, vec![0]),
+                make(Opcode::Constant, vec![1]),
+                make(Opcode::Hash, vec![2]),
+            ],
+            Opcode::Index => vec![
+                make(Opcode::Constant, vec![0]),
+                make(Opcode::Array, vec![1]),
+                make(Opcode::Constant, vec![0]),
+                make(Opcode::Index, vec![]),
+            ],
+            Opcode::SetIndex => vec![
+                make(Opcode::Constant, vec![0]),
+                make(Opcode::Constant, vec![1]),
+                make(Opcode::Array, vec![2]),
+                make(Opcode::Constant, vec![0]),
+                make(Opcode::Constant, vec![0]),
+                make(Opcode::SetIndex, vec![]),
+            ],
+            Opcode::Closure | Opcode::Call | Opcode::Return => vec![
+                make(Opcode::Closure, vec![2, 0]),
+                make(Opcode::Call, vec![0]),
+            ],
+            Opcode::ReturnValue | Opcode::GetLocal | Opcode::SetLocal => vec![
+                make(Opcode::Closure, vec![3, 0]),
+                make(Opcode::Call, vec![0]),
+            ],
+            Opcode::GetFree => vec![
+                make(Opcode::Constant, vec![0]),
+                make(Opcode::Closure, vec![4, 1]),
+                make(Opcode::Call, vec![0]),
+            ],
+            Opcode::CurrentClosure => vec![
+                make(Opcode::Closure, vec![5, 0]),
+                make(Opcode::Call, vec![0]),
+            ],
+            Opcode::GetBuiltin => vec![make(Opcode::GetBuiltin, vec![0])],
+        }
+    }
+
+    // Function bodies referenced by `dispatch_program`, as (instructions, num_locals).
+    fn dispatch_functions() -> Vec<(Vec<Vec<u8>>, usize)> {
+        use code::make;
+        vec![
+            (vec![make(Opcode::Return, vec![])], 0),
+            (
+                vec![
+                    make(Opcode::Constant, vec![0]),
+                    make(Opcode::SetLocal, vec![0]),
+                    make(Opcode::GetLocal, vec![0]),
+                    make(Opcode::ReturnValue, vec![]),
+                ],
+                1,
+            ),
+            (
+                vec![
+                    make(Opcode::GetFree, vec![0]),
+                    make(Opcode::ReturnValue, vec![]),
+                ],
+                0,
+            ),
+            (
+                vec![
+                    make(Opcode::CurrentClosure, vec![]),
+                    make(Opcode::ReturnValue, vec![]),
+                ],
+                0,
+            ),
+        ]
+    }
+
+    fn dispatch_constants() -> Vec<Rc<Object>> {
+        let mut constants = vec![Rc::new(Object::Integer(1)), Rc::new(Object::Integer(2))];
+        for (instructions, num_locals) in dispatch_functions() {
+            constants.push(Rc::new(Object::CompiledFunction(Rc::new(
+                CompiledFunction::new(instructions.into_iter().flatten().collect(), 0, num_locals),
+            ))));
+        }
+        constants
+    }
+
+    #[test]
+    fn it_dispatches_every_opcode() {
+        for op in Opcode::all() {
+            let program = dispatch_program(*op);
+            let functions = dispatch_functions();
+            let mut emitted = program
+                .iter()
+                .chain(functions.iter().flat_map(|(body, _)| body.iter()));
+            assert!(
+                emitted.any(|instruction| instruction[0] == *op as u8),
+                "program for {} never emits it",
+                op.name()
+            );
+
+            let bytecode = compiler::Bytecode {
+                instructions: program.into_iter().flatten().collect(),
+                constants: Rc::new(RefCell::new(dispatch_constants())),
+            };
+            let mut vm = VM::new(bytecode);
+            if let Err(e) = vm.run() {
+                panic!("{} failed to dispatch: {}", op.name(), e);
+            }
+        }
+    }
 }
@@ -13,8 +13,8 @@ pub fn interpret(input: &str) -> String {
     let env = Rc::new(RefCell::new(Environment::new()));
     let macro_env = Rc::new(RefCell::new(Environment::new()));
 
-    let lexer = Lexer::new(&input);
-    let mut parser = Parser::new(lexer.into());
+    let lexer = Lexer::new(input);
+    let mut parser = Parser::new(lexer);
     let program = parser.parse_program();
 
     match program {
@@ -26,11 +26,11 @@ pub fn interpret(input: &str) -> String {
                 Ok(expanded) => {
                     // Note: You may want to return the result of evaluation. Assuming `evaluate` returns a Result<String, SomeError>:
                     match evaluate(expanded, Rc::clone(&env)) {
-                        Ok(result) => return result.to_string(),
-                        Err(err) => return format!("Evaluation error: {:?}", err),
+                        Ok(result) => result.to_string(),
+                        Err(err) => format!("Evaluation error: {:?}", err),
                     }
                 }
-                Err(err) => return format!("macro expansion error: {:?}", err),
+                Err(err) => format!("macro expansion error: {:?}", err),
             }
         }
         Err(err) => {